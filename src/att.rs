@@ -0,0 +1,110 @@
+use super::gen_x86::{ARGREG32, ARGREG64, ARGREG8, REG32, REG64, REG8};
+
+// AT&T syntax.
+//
+// gen_x86 writes instructions in Intel syntax, and with --syntax=att each
+// of them is translated here as it is emitted: the operands are swapped,
+// registers are prefixed with `%` and immediates with `$`, `[base-8]`
+// becomes `-8(%base)`, and the mnemonic takes the b/l/q suffix of the
+// size it operates on. Only the forms gen_x86 emits are handled.
+
+enum Operand {
+    Reg(String, char),
+    Imm(String),
+    // memory, with the size given by `ptr` if any
+    Mem(String, Option<char>),
+    // a label or a symbol
+    Sym(String),
+}
+
+// The size suffix of a register.
+fn reg_suffix(name: &str) -> Option<char> {
+    let is = |regs: &[&str]| regs.contains(&name);
+    if is(&REG64) || is(&ARGREG64) || is(&["rax", "rsp", "rbp"]) {
+        return Some('q');
+    }
+    if is(&REG32) || is(&ARGREG32) || name == "eax" {
+        return Some('l');
+    }
+    if is(&REG8) || is(&ARGREG8) || name == "al" {
+        return Some('b');
+    }
+    None
+}
+
+fn operand(s: &str) -> Operand {
+    let (s, size) = match s.strip_prefix("qword ptr ") {
+        Some(s) => (s, Some('q')),
+        None => (s, None),
+    };
+    if let Some(addr) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        let mem = match addr.find(['+', '-']) {
+            _ if addr.starts_with("rip + ") => format!("{}(%rip)", &addr[6..]),
+            Some(i) => {
+                let disp = addr[i..].trim_start_matches('+');
+                format!("{}(%{})", disp, &addr[..i])
+            }
+            None => format!("(%{})", addr),
+        };
+        return Operand::Mem(mem, size);
+    }
+    if let Some(suffix) = reg_suffix(s) {
+        return Operand::Reg(format!("%{}", s), suffix);
+    }
+    if s.parse::<i64>().is_ok() {
+        return Operand::Imm(format!("${}", s));
+    }
+    Operand::Sym(s.to_string())
+}
+
+fn suffix(op: &Operand) -> Option<char> {
+    match op {
+        Operand::Reg(_, suffix) => Some(*suffix),
+        Operand::Mem(_, size) => *size,
+        _ => None,
+    }
+}
+
+// Translates an instruction in Intel syntax.
+pub fn to_att(insn: &str) -> String {
+    let (mnemonic, operands) = match insn.split_once(' ') {
+        Some((mnemonic, operands)) => (mnemonic, operands),
+        None if insn == "cqo" => return String::from("cqto"),
+        None if insn == "cdq" => return String::from("cltd"),
+        None => return insn.to_string(),
+    };
+    let ops: Vec<Operand> = operands.split(", ").map(operand).collect();
+    let text: Vec<&str> = ops
+        .iter()
+        .rev()
+        .map(|op| match op {
+            Operand::Reg(s, _) | Operand::Imm(s) | Operand::Mem(s, _) | Operand::Sym(s) => {
+                s.as_str()
+            }
+        })
+        .collect();
+    let mut text = text.join(", ");
+
+    let mnemonic = match mnemonic {
+        // the extensions are suffixed with the source size, then the
+        // destination size
+        "movsx" | "movzx" | "movsxd" => {
+            let (to, from) = (suffix(&ops[0]).unwrap(), suffix(&ops[1]).unwrap());
+            format!("mov{}{}{}", &mnemonic[3..4], from, to)
+        }
+        "call" | "jmp" => {
+            if let Operand::Reg(..) = ops[0] {
+                text.insert(0, '*');
+            }
+            mnemonic.to_string()
+        }
+        _ if mnemonic.starts_with('j') || mnemonic.starts_with("set") => mnemonic.to_string(),
+        // the size of the destination, or of the source when the
+        // destination is memory of no given size
+        _ => match ops.iter().find_map(suffix) {
+            Some(suffix) => format!("{}{}", mnemonic, suffix),
+            None => mnemonic.to_string(),
+        },
+    };
+    format!("{} {}", mnemonic, text)
+}
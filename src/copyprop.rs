@@ -0,0 +1,80 @@
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::collections::HashMap;
+
+// Copy propagation on the two-address IR.
+//
+// Every `A = B op C` is lowered to `A = B; A = A op C`, which costs a
+// register and a `mov` even when B is never used again. When both A and
+// B are only used within the block and B has no occurrence after the
+// copy, the copy is removed and A is renamed to B for the rest of the
+// block.
+
+// Counts the blocks each register occurs in, including as a parameter.
+fn blocks_of(fun: &Function) -> HashMap<i32, usize> {
+    let mut blocks = HashMap::new();
+    for bb in &fun.bbs {
+        let bb = bb.borrow();
+        let mut seen = vec![bb.param.vn];
+        for ir in &bb.irs {
+            for r in ir.defs().into_iter().chain(ir.uses()) {
+                if !seen.contains(&r.vn) {
+                    seen.push(r.vn);
+                }
+            }
+        }
+        for vn in seen {
+            *blocks.entry(vn).or_insert(0) += 1;
+        }
+    }
+    blocks
+}
+
+fn propagate(bb: &mut BB, blocks: &HashMap<i32, usize>) -> usize {
+    let mut removed = 0;
+    let mut i = 0;
+    while i < bb.irs.len() {
+        let ir = &bb.irs[i];
+        if ir.op != IrMov {
+            i += 1;
+            continue;
+        }
+        let (dst, src) = (ir.r0.vn, ir.r2.vn);
+        let local = |vn: i32| blocks.get(&vn) == Some(&1) && vn != bb.param.vn;
+        let src_dead = bb.irs[i + 1..]
+            .iter()
+            .all(|ir| ir.defs().into_iter().chain(ir.uses()).all(|r| r.vn != src));
+        if !local(dst) || !local(src) || !src_dead {
+            i += 1;
+            continue;
+        }
+        let src = ir.r2.clone();
+        bb.irs.remove(i);
+        for ir in &mut bb.irs[i..] {
+            for r in ir.uses_mut() {
+                if r.vn == dst {
+                    *r = src.clone();
+                }
+            }
+            for r in ir.defs_mut() {
+                if r.vn == dst {
+                    *r = src.clone();
+                }
+            }
+        }
+        removed += 1;
+    }
+    removed
+}
+
+pub fn copyprop(program: &mut Program) -> usize {
+    let mut removed = 0;
+    for fun in &program.funs {
+        let blocks = blocks_of(fun);
+        for bb in &fun.bbs {
+            removed += propagate(&mut bb.borrow_mut(), &blocks);
+        }
+    }
+    removed
+}
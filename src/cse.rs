@@ -0,0 +1,131 @@
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::collections::HashMap;
+
+// Local common subexpression elimination.
+//
+// Within a block, a pure instruction computing the same op on the same
+// operands as an earlier one is removed and its register is replaced by
+// the earlier register everywhere. Only registers defined exactly once
+// take part, so the earlier register still holds the value wherever the
+// removed one was used. Loads are reused only until the next store or
+// call, and volatile loads never are.
+//
+// Constants are cheaper to rematerialize than to keep in a register, so
+// `Imm` is never removed, but registers holding the same constant are
+// treated as the same operand.
+
+// An operand is either a register or a constant.
+type Operand = (bool, i32);
+type Key = (IrOp, Operand, Operand, i32, i32);
+
+fn is_pure(op: &IrOp) -> bool {
+    matches!(
+        op,
+        IrImm
+            | IrBpRel
+            | IrLabelAddr(_)
+            | IrAdd(_)
+            | IrSub(_)
+            | IrMul(_)
+            | IrLt
+            | IrLe
+            | IrEqual
+            | IrNe
+            | IrOr
+            | IrXor
+            | IrAnd
+            | IrShl(_)
+            | IrShr
+            | IrSar
+            | IrMovsx(_)
+            | IrMovzx(_)
+            | IrLoad(_)
+    )
+}
+
+fn rename(ir: &mut Ir, renamed: &HashMap<i32, Reg>) {
+    for r in ir.uses_mut() {
+        if let Some(to) = renamed.get(&r.vn) {
+            *r = to.clone();
+        }
+    }
+}
+
+fn cse_fun(fun: &mut Function) -> usize {
+    let mut defs = HashMap::new();
+    for bb in &fun.bbs {
+        for ir in &bb.borrow().irs {
+            if ir.r0.active() {
+                *defs.entry(ir.r0.vn).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut renamed = HashMap::new();
+    for bb in &fun.bbs {
+        let mut bb = bb.borrow_mut();
+        let mut table: HashMap<Key, Reg> = HashMap::new();
+        let mut consts = HashMap::new();
+        let mut dead = vec![];
+        for (i, ir) in bb.irs.iter_mut().enumerate() {
+            rename(ir, &renamed);
+            match ir.op {
+                IrStore(_) | IrStoreArg(_) | IrCall(..) => {
+                    table.retain(|key, _| !matches!(key.0, IrLoad(_)));
+                    continue;
+                }
+                _ => {}
+            }
+            if !is_pure(&ir.op) || ir.volatile || defs.get(&ir.r0.vn) != Some(&1) {
+                continue;
+            }
+            if ir.op == IrImm {
+                consts.insert(ir.r0.vn, ir.imm);
+                continue;
+            }
+            let operand = |r: &Reg| match consts.get(&r.vn) {
+                Some(imm) => (true, *imm),
+                None => (false, r.vn),
+            };
+            let key = (
+                ir.op.clone(),
+                operand(&ir.r1),
+                operand(&ir.r2),
+                ir.imm,
+                ir.imm2,
+            );
+            match table.get(&key) {
+                Some(prev) => {
+                    renamed.insert(ir.r0.vn, prev.clone());
+                    dead.push(i);
+                }
+                None => {
+                    table.insert(key, ir.r0.clone());
+                }
+            }
+        }
+        let mut i = 0;
+        bb.irs.retain(|_| {
+            i += 1;
+            !dead.contains(&(i - 1))
+        });
+    }
+
+    // uses in blocks placed before the definition
+    for bb in &fun.bbs {
+        for ir in &mut bb.borrow_mut().irs {
+            rename(ir, &renamed);
+        }
+    }
+    renamed.len()
+}
+
+pub fn cse(program: &mut Program) -> usize {
+    let mut removed = 0;
+    for fun in &mut program.funs {
+        removed += cse_fun(fun);
+    }
+    removed
+}
@@ -0,0 +1,194 @@
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Dead code elimination.
+//
+// A branch on a constant becomes a jump to the taken arm. Blocks which
+// then cannot be reached from the entry block are removed, and
+// so are stores to stack slots which are overwritten in the same block
+// before being loaded. Only slots whose address never escapes are
+// considered, so a store through a pointer cannot be mistaken for dead.
+
+#[derive(Default)]
+pub struct DceStats {
+    pub blocks: usize,
+    pub stores: usize,
+}
+
+// Turns `Br` on a register set by `Imm` earlier in the block into `Jmp`.
+fn fold_branches(fun: &mut Function) {
+    // edges to the arms not taken, removed afterwards
+    let mut dropped = vec![];
+    for bb in &fun.bbs {
+        let mut bb = bb.borrow_mut();
+        let label = bb.label;
+        let mut consts = HashMap::new();
+        for ir in bb.irs.iter_mut() {
+            if ir.op == IrBr {
+                if let Some(val) = consts.get(&ir.r2.vn) {
+                    let (target, other) = if *val != 0 {
+                        (ir.bb1.take(), ir.bb2.take())
+                    } else {
+                        (ir.bb2.take(), ir.bb1.take())
+                    };
+                    dropped.push((label, other.unwrap(), Rc::clone(target.as_ref().unwrap())));
+                    ir.op = IrJmp;
+                    ir.r2 = Reg::dummy();
+                    ir.bb1 = target;
+                    ir.bb2 = None;
+                }
+            } else if ir.r0.active() {
+                if ir.op == IrImm {
+                    consts.insert(ir.r0.vn, ir.imm);
+                } else {
+                    consts.remove(&ir.r0.vn);
+                }
+            }
+        }
+    }
+    for (from, other, target) in dropped {
+        if !Rc::ptr_eq(&other, &target) {
+            let to = other.borrow().label;
+            fun.unlink(from, to);
+        }
+    }
+}
+
+// Removes the blocks which cannot be reached from the entry block.
+pub fn prune_unreachable(fun: &mut Function) -> usize {
+    let index: HashMap<i32, usize> = fun
+        .bbs
+        .iter()
+        .enumerate()
+        .map(|(i, bb)| (bb.borrow().label, i))
+        .collect();
+    let mut reachable = HashSet::new();
+    let mut worklist = vec![0];
+    while let Some(i) = worklist.pop() {
+        if !reachable.insert(i) {
+            continue;
+        }
+        for succ in &fun.bbs[i].borrow().succ {
+            worklist.push(index[succ]);
+        }
+    }
+    let before = fun.bbs.len();
+    let mut i = 0;
+    fun.bbs.retain(|_| {
+        i += 1;
+        reachable.contains(&(i - 1))
+    });
+    let kept: HashSet<BBId> = fun.bbs.iter().map(|bb| bb.borrow().label).collect();
+    for bb in &fun.bbs {
+        bb.borrow_mut().pred.retain(|id| kept.contains(id));
+    }
+    before - fun.bbs.len()
+}
+
+fn remove_unreachable(fun: &mut Function) -> usize {
+    fold_branches(fun);
+    prune_unreachable(fun)
+}
+
+// Maps each register holding the address of a stack slot to the
+// offset of the slot, for slots only ever used as a load or store
+// address.
+fn private_slots(fun: &Function) -> HashMap<i32, i32> {
+    let mut defs = HashMap::new();
+    let mut bprel = HashMap::new();
+    for bb in &fun.bbs {
+        for ir in &bb.borrow().irs {
+            if ir.r0.active() {
+                *defs.entry(ir.r0.vn).or_insert(0) += 1;
+                if ir.op == IrBpRel {
+                    bprel.insert(ir.r0.vn, ir.imm);
+                }
+            }
+        }
+    }
+    bprel.retain(|vn, _| defs[vn] == 1);
+
+    // offsets whose address is used for anything but a load or store
+    let mut escaped = HashSet::new();
+    for bb in &fun.bbs {
+        for ir in &bb.borrow().irs {
+            let uses: Vec<&Reg> = match &ir.op {
+                // r2 is the address
+                IrLoad(_) => vec![],
+                // r1 is the address and r2 the stored value
+                IrStore(_) => vec![&ir.r2],
+                IrCall(_, args) => args.iter().chain([&ir.r1]).collect(),
+                _ => vec![&ir.r1, &ir.r2, &ir.bbarg],
+            };
+            for r in uses {
+                if let Some(offset) = bprel.get(&r.vn) {
+                    escaped.insert(*offset);
+                }
+            }
+        }
+    }
+    bprel.retain(|_, offset| !escaped.contains(offset));
+    bprel
+}
+
+fn remove_dead_stores(fun: &mut Function) -> usize {
+    let slots = private_slots(fun);
+    let mut removed = 0;
+    for bb in &fun.bbs {
+        let mut bb = bb.borrow_mut();
+        // stores not yet loaded, as (offset, size) -> index
+        let mut pending: HashMap<(i32, i32), usize> = HashMap::new();
+        let mut dead = vec![];
+        for (i, ir) in bb.irs.iter().enumerate() {
+            match &ir.op {
+                IrStore(size) => {
+                    if let Some(offset) = slots.get(&ir.r1.vn) {
+                        if let Some(prev) = pending.insert((*offset, *size), i) {
+                            dead.push(prev);
+                        }
+                        if ir.volatile {
+                            pending.remove(&(*offset, *size));
+                        }
+                        // a partial overwrite keeps the earlier store
+                        pending.retain(|(o, s), _| {
+                            (*o, *s) == (*offset, *size) || !overlaps(*o, *s, *offset, *size)
+                        });
+                    }
+                }
+                IrLoad(size) => {
+                    if let Some(offset) = slots.get(&ir.r2.vn) {
+                        pending.retain(|(o, s), _| !overlaps(*o, *s, *offset, *size));
+                    }
+                }
+                IrCall(..) => {
+                    pending.clear();
+                }
+                _ => {}
+            }
+        }
+        removed += dead.len();
+        let mut i = 0;
+        bb.irs.retain(|_| {
+            i += 1;
+            !dead.contains(&(i - 1))
+        });
+    }
+    removed
+}
+
+// A slot at offset `o` occupies [rbp-o, rbp-o+size).
+fn overlaps(o1: i32, s1: i32, o2: i32, s2: i32) -> bool {
+    -o1 < -o2 + s2 && -o2 < -o1 + s1
+}
+
+pub fn dce(program: &mut Program) -> DceStats {
+    let mut stats = DceStats::default();
+    for fun in &mut program.funs {
+        stats.blocks += remove_unreachable(fun);
+        stats.stores += remove_dead_stores(fun);
+    }
+    stats
+}
@@ -1,13 +1,12 @@
+use super::mir::*;
 use super::parse::*;
+use super::sema::has_side_effects;
 use super::token::{TokenType::*, *};
 use IrOp::*;
-// use super::lib::*;
-use super::mir::*;
 
 use linked_hash_map::LinkedHashMap;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::sync::Mutex;
 
 // mir9cc's code generation is two-pass. In the first pass, abstract
 // syntax trees are compiled to IR (intermediate representation).
@@ -20,27 +19,55 @@ use std::sync::Mutex;
 // Such infinite number of registers are mapped to a finite registers
 // in a later pass.
 
-lazy_static! {
-    pub static ref REGNO: Mutex<i32> = Mutex::new(1);
+// State of lowering one function, passed down the gen_* functions: the
+// function being built, the line of the node being lowered and the
+// blocks of the enclosing statements, innermost last.
+struct IrContext {
+    fun: Function,
+    // source line of the node being lowered, recorded on each Ir.
+    line: usize,
+    // the blocks of the cases of each switch, which are taken in order
+    switches: Vec<Vec<Rc<RefCell<BB>>>>,
+    // the default label of each switch, if it has one
+    defaults: Vec<Option<Rc<RefCell<BB>>>>,
+    continues: Vec<Rc<RefCell<BB>>>,
+    breaks: Vec<Rc<RefCell<BB>>>,
 }
 
-thread_local!(pub static SWITCHES: Rc<RefCell<Vec<Vec<Rc<RefCell<BB>>>>>> = Rc::new(RefCell::new(vec![])));
-thread_local!(pub static CONTINUE_VEC: Rc<RefCell<Vec<Rc<RefCell<BB>>>>> = Rc::new(RefCell::new(vec![])));
-thread_local!(pub static BREAK_VEC: Rc<RefCell<Vec<Rc<RefCell<BB>>>>> = Rc::new(RefCell::new(vec![])));
+impl IrContext {
+    // Appends the instruction to the current block.
+    fn push(&mut self, ir: Ir) {
+        let ir = ir.with_line(self.line);
+        self.fun.bbs.last().unwrap().borrow_mut().irs.push(ir);
+    }
+}
+
+// The comparison `r1 <cond> r2` of a branch taking bb1 when it holds.
+#[derive(Debug, Clone, Copy, PartialEq, std::cmp::Eq, std::hash::Hash)]
+pub enum Cond {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
 
 #[allow(dead_code)]
-#[derive(Debug, PartialEq, std::cmp::Eq, std::hash::Hash)]
+#[derive(Debug, Clone, PartialEq, std::cmp::Eq, std::hash::Hash)]
 pub enum IrOp {
     IrImm,
     IrMov,
-    IrAdd,
+    // the arithmetic which can overflow is done in the size of its type,
+    // 4 for int and 8 for pointers
+    IrAdd(i32),
     IrBpRel,
-    IrSub,
-    IrMul,
-    IrDiv,
+    IrSub(i32),
+    IrMul(i32),
+    IrDiv(i32),
     IrRet,
     IrStore(i32),
     IrLoad(i32),
+    IrMovsx(i32),
+    IrMovzx(i32),
     IrJmp,
     IrCall(String, Vec<Reg>),
     IrStoreArg(i32),
@@ -52,11 +79,13 @@ pub enum IrOp {
     IrXor,
     IrAnd,
     IrLe,
-    IrShl,
+    IrShl(i32),
     IrShr,
-    IrMod,
+    IrSar,
+    IrMod(i32),
     IrNeg,
     IrBr,
+    IrBrCmp(Cond),
     IrLoadSpill,
     IrStoreSpill,
 }
@@ -72,6 +101,10 @@ pub struct Ir {
     pub bb2: Option<Rc<RefCell<BB>>>,
     pub imm: i32,
     pub imm2: i32,
+    // set on loads and stores of volatile objects, which optimizations must keep.
+    pub volatile: bool,
+    // source line the instruction was generated from, or 0 if unknown.
+    pub line: usize,
 }
 
 impl Ir {
@@ -96,19 +129,52 @@ impl Ir {
             bb2,
             imm,
             imm2,
+            volatile: false,
+            line: 0,
+        }
+    }
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+    // Registers the instruction reads: its operands, the value it passes
+    // to the block it jumps to and the arguments of a call.
+    pub fn uses(&self) -> Vec<&Reg> {
+        let mut regs = vec![&self.r1, &self.r2, &self.bbarg];
+        if let IrCall(_, args) = &self.op {
+            regs.extend(args.iter());
         }
+        regs.into_iter().filter(|r| r.active()).collect()
     }
-    fn bittype(ty: &TokenType) -> IrOp {
+    pub fn uses_mut(&mut self) -> Vec<&mut Reg> {
+        let mut regs = vec![&mut self.r1, &mut self.r2, &mut self.bbarg];
+        if let IrCall(_, args) = &mut self.op {
+            regs.extend(args.iter_mut());
+        }
+        regs.into_iter().filter(|r| r.active()).collect()
+    }
+    // Registers the instruction writes, which is at most r0.
+    pub fn defs(&self) -> Vec<&Reg> {
+        Some(&self.r0).into_iter().filter(|r| r.active()).collect()
+    }
+    pub fn defs_mut(&mut self) -> Vec<&mut Reg> {
+        Some(&mut self.r0)
+            .into_iter()
+            .filter(|r| r.active())
+            .collect()
+    }
+    fn bittype(ty: &TokenType, size: i32) -> IrOp {
         match ty {
-            TokenAdd => IrAdd,
-            TokenSub => IrSub,
-            TokenStar => IrMul,
-            TokenDiv => IrDiv,
+            TokenAdd => IrAdd(size),
+            TokenSub => IrSub(size),
+            TokenStar => IrMul(size),
+            TokenDiv => IrDiv(size),
             TokenLt => IrLt,
             TokenLe => IrLe,
-            TokenShl => IrShl,
-            TokenShr => IrShr,
-            TokenMod => IrMod,
+            TokenShl => IrShl(size),
+            // every integer type is signed
+            TokenShr => IrSar,
+            TokenMod => IrMod(size),
             TokenAmpersand => IrAnd,
             TokenOr => IrOr,
             TokenXor => IrXor,
@@ -128,35 +194,49 @@ impl Ir {
             IrMov => {
                 return format!("Mov r{}, r{}", self.r0, self.r2);
             }
-            IrAdd => {
-                return format!("Add r{}, r{}", self.r0, self.r2);
+            IrAdd(size) => {
+                format!("Add{} r{}, r{}", size, self.r0, self.r2)
             }
             IrBpRel => {
                 return format!("Lea r{}, [rbp-{}]", self.r0, self.imm);
             }
-            IrSub => {
-                return format!("Sub r{}, r{}", self.r0, self.r2);
+            IrSub(size) => {
+                format!("Sub{} r{}, r{}", size, self.r0, self.r2)
             }
-            IrMul => {
-                return format!("Mul r{}, r{}", self.r0, self.r2);
+            IrMul(size) => {
+                format!("Mul{} r{}, r{}", size, self.r0, self.r2)
             }
-            IrDiv => {
-                return format!("Div r{}, r{}", self.r0, self.r2);
+            IrDiv(size) => {
+                format!("Div{} r{}, r{}", size, self.r0, self.r2)
             }
             IrRet => {
-                return format!("Return {}", self.r0);
+                if !self.r2.active() {
+                    return String::from("Return");
+                }
+                format!("Return r{}", self.r2)
             }
             IrStore(ir_size) => {
-                return format!("Store{} [r{}], r{}", ir_size, self.r0, self.r2);
+                let vol = if self.volatile { " volatile" } else { "" };
+                format!("Store{}{} [r{}], r{}", ir_size, vol, self.r0, self.r2)
             }
             IrLoad(ir_size) => {
-                return format!("Load{} r{}, [r{}]", ir_size, self.r0, self.r2);
+                let vol = if self.volatile { " volatile" } else { "" };
+                format!("Load{}{} r{}, [r{}]", ir_size, vol, self.r0, self.r2)
+            }
+            IrMovsx(ir_size) => {
+                format!("Movsx{} r{}, r{}", ir_size, self.r0, self.r2)
+            }
+            IrMovzx(ir_size) => {
+                format!("Movzx{} r{}, r{}", ir_size, self.r0, self.r2)
             }
             IrJmp => {
                 return format!("Jmp .L{}", self.imm);
             }
             IrCall(name, args) => {
-                let mut call_s = format!("Call {}(", name);
+                let mut call_s = match name.is_empty() {
+                    true => format!("Call *r{}(", self.r1),
+                    false => format!("Call {}(", name),
+                };
                 for arg in args {
                     call_s.push_str(&format!("r{}, ", arg));
                 }
@@ -194,14 +274,17 @@ impl Ir {
             IrLe => {
                 return format!("Le r{}, r{}", self.r0, self.r2);
             }
-            IrShl => {
-                return format!("Shl r{}, r{}", self.r0, self.r2);
+            IrShl(size) => {
+                format!("Shl{} r{}, {}", size, self.r0, self.shift_count())
             }
             IrShr => {
-                return format!("Shr r{}, r{}", self.r0, self.r2);
+                format!("Shr r{}, {}", self.r0, self.shift_count())
+            }
+            IrSar => {
+                format!("Sar r{}, {}", self.r0, self.shift_count())
             }
-            IrMod => {
-                return format!("Mod r{}, r{}", self.r0, self.r2);
+            IrMod(size) => {
+                format!("Mod{} r{}, r{}", size, self.r0, self.r2)
             }
             IrNeg => {
                 return format!("Neg r{}", self.r0);
@@ -209,11 +292,21 @@ impl Ir {
             IrBr => {
                 return format!(
                     "Br r{}, .L{}, .L{}",
-                    self.r0,
+                    self.r2,
                     self.bb1.clone().unwrap().borrow().label,
                     self.bb2.clone().unwrap().borrow().label
                 );
             }
+            IrBrCmp(cond) => {
+                format!(
+                    "Br{:?} r{}, r{}, .L{}, .L{}",
+                    cond,
+                    self.r1,
+                    self.r2,
+                    self.bb1.clone().unwrap().borrow().label,
+                    self.bb2.clone().unwrap().borrow().label
+                )
+            }
             IrLoadSpill => {
                 return format!("LoadSpill");
             }
@@ -222,18 +315,8 @@ impl Ir {
             }
         }
     }
-    fn emit(op: IrOp, r0: Reg, r1: Reg, r2: Reg, fun: &mut Function) {
-        fun.bbs.last_mut().unwrap().borrow_mut().irs.push(Ir::new(
-            op,
-            r0,
-            r1,
-            r2,
-            Reg::dummy(),
-            None,
-            None,
-            -1,
-            -1,
-        ));
+    fn emit(op: IrOp, r0: Reg, r1: Reg, r2: Reg, ctx: &mut IrContext) {
+        ctx.push(Ir::new(op, r0, r1, r2, Reg::dummy(), None, None, -1, -1));
     }
     fn bb_emit(
         op: IrOp,
@@ -242,25 +325,70 @@ impl Ir {
         bbarg: Reg,
         bb1: Option<Rc<RefCell<BB>>>,
         bb2: Option<Rc<RefCell<BB>>>,
-        fun: &mut Function,
+        ctx: &mut IrContext,
     ) {
-        fun.bbs.last_mut().unwrap().borrow_mut().irs.push(Ir::new(
-            op,
-            r0,
+        let cur = ctx.fun.bbs.last().unwrap();
+        for target in bb1.iter().chain(bb2.iter()) {
+            BB::link(cur, target);
+        }
+        ctx.push(Ir::new(op, r0, Reg::dummy(), r2, bbarg, bb1, bb2, -1, -1));
+    }
+    fn br(
+        r: Reg,
+        then: Option<Rc<RefCell<BB>>>,
+        els: Option<Rc<RefCell<BB>>>,
+        ctx: &mut IrContext,
+    ) {
+        Ir::bb_emit(IrBr, Reg::dummy(), r, Reg::dummy(), then, els, ctx);
+    }
+    fn br_cmp(
+        cond: Cond,
+        r1: Reg,
+        r2: Reg,
+        then: Rc<RefCell<BB>>,
+        els: Rc<RefCell<BB>>,
+        ctx: &mut IrContext,
+    ) {
+        let cur = ctx.fun.bbs.last().unwrap();
+        BB::link(cur, &then);
+        BB::link(cur, &els);
+        ctx.push(Ir::new(
+            IrBrCmp(cond),
             Reg::dummy(),
+            r1,
             r2,
-            bbarg,
-            bb1,
-            bb2,
+            Reg::dummy(),
+            Some(then),
+            Some(els),
             -1,
             -1,
         ));
     }
-    fn br(r: Reg, then: Option<Rc<RefCell<BB>>>, els: Option<Rc<RefCell<BB>>>, fun: &mut Function) {
-        Ir::bb_emit(IrBr, Reg::dummy(), r, Reg::dummy(), then, els, fun);
+    // A shift by a constant count takes it in imm rather than in r2.
+    pub fn new_shift(op: IrOp, r0: Reg, r1: Reg, count: i32) -> Self {
+        Ir::new(
+            op,
+            r0,
+            r1,
+            Reg::dummy(),
+            Reg::dummy(),
+            None,
+            None,
+            count,
+            -1,
+        )
+    }
+    pub fn shift_count(&self) -> String {
+        if self.r2.active() {
+            return format!("r{}", self.r2);
+        }
+        self.imm.to_string()
     }
-    fn imm_emit(op: IrOp, r0: Reg, imm: i32, imm2: i32, fun: &mut Function) {
-        fun.bbs.last_mut().unwrap().borrow_mut().irs.push(Ir::new(
+    fn shift_emit(op: IrOp, r0: Reg, r1: Reg, count: i32, ctx: &mut IrContext) {
+        ctx.push(Ir::new_shift(op, r0, r1, count));
+    }
+    fn imm_emit(op: IrOp, r0: Reg, imm: i32, imm2: i32, ctx: &mut IrContext) {
+        ctx.push(Ir::new(
             op,
             r0,
             Reg::dummy(),
@@ -279,142 +407,202 @@ pub struct Function {
     pub bbs: Vec<Rc<RefCell<BB>>>,
     pub args: LinkedHashMap<String, Var>,
     pub stacksize: i32,
+    // callee-saved registers the function uses, which the prologue saves.
+    // Filled by the register allocator.
+    pub saved_regs: Vec<usize>,
+    // the numbers of the last register and block created. Both are
+    // numbered per function, so that a function's IR doesn't depend on
+    // the ones before it.
+    pub regno: Cell<i32>,
+    pub label: Cell<BBId>,
 }
 
 impl Function {
-    pub fn new(
-        name: String,
-        bbs: Vec<Rc<RefCell<BB>>>,
-        args: LinkedHashMap<String, Var>,
-        stacksize: i32,
-    ) -> Self {
-        Self {
+    // A function with an empty entry block.
+    pub fn new(name: String, args: LinkedHashMap<String, Var>, stacksize: i32) -> Self {
+        let mut fun = Self {
             name,
-            bbs,
+            bbs: vec![],
             args,
             stacksize,
-        }
+            saved_regs: vec![],
+            regno: Cell::new(1),
+            label: Cell::new(0),
+        };
+        fun.bbs.push(fun.new_bb());
+        fun
     }
     pub fn bb_push(&mut self, bb: Rc<RefCell<BB>>) {
+        if let Some(last) = self.bbs.last() {
+            if !last.borrow().is_terminated() {
+                BB::link(last, &bb);
+            }
+        }
         self.bbs.push(bb);
     }
 }
 
-fn get_switches_rc_mut() -> Rc<RefCell<Vec<Vec<Rc<RefCell<BB>>>>>> {
-    SWITCHES.with(|rc| rc.clone())
+fn jmp(x_bb: Option<Rc<RefCell<BB>>>, bbarg: Reg, ctx: &mut IrContext) {
+    Ir::bb_emit(IrJmp, Reg::dummy(), Reg::dummy(), bbarg, x_bb, None, ctx);
+}
+
+// Returns the op widening a value of the type to 64 bits. A char is
+// signed and a _Bool is 0 or 1.
+fn extend_op(ctype: &Type) -> Option<IrOp> {
+    match (&ctype.ty, ctype.size) {
+        (Ty::BOOL, _) => Some(IrMovzx(1)),
+        (Ty::STRUCT(..), _) | (Ty::ARY, _) => None,
+        (_, 1) => Some(IrMovsx(1)),
+        (_, 4) => Some(IrMovsx(4)),
+        _ => None,
+    }
 }
 
-fn get_continue_vec_rc_mut() -> Rc<RefCell<Vec<Rc<RefCell<BB>>>>> {
-    CONTINUE_VEC.with(|rc| rc.clone())
+fn load(ctype: &Type, dst: Reg, src: Reg, ctx: &mut IrContext) {
+    match extend_op(ctype) {
+        Some(op) => {
+            let r = ctx.fun.new_reg();
+            Ir::emit(IrOp::IrLoad(ctype.size), r.clone(), Reg::dummy(), src, ctx);
+            mark_volatile(ctype, ctx);
+            Ir::emit(op, dst, Reg::dummy(), r, ctx);
+        }
+        None => {
+            Ir::emit(IrOp::IrLoad(ctype.size), dst, Reg::dummy(), src, ctx);
+            mark_volatile(ctype, ctx);
+        }
+    }
 }
 
-fn get_break_vec_rc_mut() -> Rc<RefCell<Vec<Rc<RefCell<BB>>>>> {
-    BREAK_VEC.with(|rc| rc.clone())
+fn store(ctype: &Type, dst: Reg, src: Reg, ctx: &mut IrContext) {
+    Ir::emit(IrOp::IrStore(ctype.size), Reg::dummy(), dst, src, ctx);
+    mark_volatile(ctype, ctx);
 }
 
-fn jmp(x_bb: Option<Rc<RefCell<BB>>>, bbarg: Reg, fun: &mut Function) {
-    Ir::bb_emit(IrJmp, Reg::dummy(), Reg::dummy(), bbarg, x_bb, None, fun);
+// Copies `size` bytes from the address in `src` to the one in `dst`,
+// 8 bytes at a time, then 4, then 1.
+fn copy_struct(size: i32, dst: Reg, src: Reg, ctx: &mut IrContext) {
+    let mut offset = 0;
+    for chunk in [8, 4, 1] {
+        while size - offset >= chunk {
+            let from = ctx.fun.new_reg();
+            let off = imm(IrImm, offset, ctx);
+            Ir::emit(IrAdd(8), from.clone(), src.clone(), off, ctx);
+            let to = ctx.fun.new_reg();
+            let off = imm(IrImm, offset, ctx);
+            Ir::emit(IrAdd(8), to.clone(), dst.clone(), off, ctx);
+            let r = ctx.fun.new_reg();
+            Ir::emit(IrLoad(chunk), r.clone(), Reg::dummy(), from, ctx);
+            Ir::emit(IrStore(chunk), Reg::dummy(), to, r, ctx);
+            offset += chunk;
+        }
+    }
 }
 
-fn load(ctype: &Type, dst: Reg, src: Reg, fun: &mut Function) {
-    Ir::emit(IrOp::IrLoad(ctype.size), dst, Reg::dummy(), src, fun);
+fn mark_volatile(ctype: &Type, ctx: &mut IrContext) {
+    if ctype.is_volatile {
+        let bb = ctx.fun.bbs.last_mut().unwrap();
+        bb.borrow_mut().irs.last_mut().unwrap().volatile = true;
+    }
 }
 
-fn store(ctype: &Type, dst: Reg, src: Reg, fun: &mut Function) {
-    Ir::emit(IrOp::IrStore(ctype.size), Reg::dummy(), dst, src, fun);
+fn store_arg(size: i32, offset: i32, id: i32, ctx: &mut IrContext) {
+    Ir::imm_emit(IrOp::IrStoreArg(size), Reg::dummy(), offset, id, ctx);
 }
 
-fn store_arg(size: i32, offset: i32, id: i32, fun: &mut Function) {
-    Ir::imm_emit(IrOp::IrStoreArg(size), Reg::dummy(), offset, id, fun);
+// Branches to `then` if the condition holds and to `els` otherwise. A
+// comparison is fused into the branch, so `if (a < b)` compares once
+// instead of computing 0 or 1 and testing that again.
+fn gen_br(cond: &Node, then: &Rc<RefCell<BB>>, els: &Rc<RefCell<BB>>, ctx: &mut IrContext) {
+    let (then, els) = (Rc::clone(then), Rc::clone(els));
+    let (cmp, lhs, rhs) = match &cond.op {
+        NodeType::BinaryTree(_, TokenLt, lhs, rhs) => (Cond::Lt, lhs, rhs),
+        NodeType::BinaryTree(_, TokenLe, lhs, rhs) => (Cond::Le, lhs, rhs),
+        NodeType::Equal(lhs, rhs) => (Cond::Eq, lhs, rhs),
+        NodeType::Ne(lhs, rhs) => (Cond::Ne, lhs, rhs),
+        _ => {
+            Ir::br(gen_expr(cond, ctx), Some(then), Some(els), ctx);
+            return;
+        }
+    };
+    let saved = enter_line(cond, ctx);
+    let r1 = gen_expr(lhs, ctx);
+    let r2 = gen_expr(rhs, ctx);
+    Ir::br_cmp(cmp, r1, r2, then, els, ctx);
+    ctx.line = saved;
 }
 
-fn gen_binop(irop: IrOp, lhs: &Node, rhs: &Node, fun: &mut Function) -> Reg {
-    let r0 = Reg::new();
+fn gen_binop(irop: IrOp, lhs: &Node, rhs: &Node, ctx: &mut IrContext) -> Reg {
+    let r0 = ctx.fun.new_reg();
     Ir::emit(
         irop,
         r0.clone(),
-        gen_expr(lhs, fun),
-        gen_expr(rhs, fun),
-        fun,
+        gen_expr(lhs, ctx),
+        gen_expr(rhs, ctx),
+        ctx,
     );
     return r0;
 }
 
+// The size arithmetic on the type is done in: integers are promoted to
+// int.
+fn op_size(ctype: &Type) -> i32 {
+    match ctype.ty {
+        Ty::PTR | Ty::ARY => 8,
+        _ => 4,
+    }
+}
+
 fn gen_inc_scale(ctype: &Type) -> i32 {
     match ctype.ty {
-        Ty::PTR => {
-            return ctype.ptr_to.as_ref().unwrap().size;
-        }
+        Ty::PTR => ctype.stride(),
         _ => {
             return 1;
         }
     }
 }
 
-fn imm(op: IrOp, imm: i32, fun: &mut Function) -> Reg {
-    let r = Reg::new();
-    Ir::imm_emit(op, r.clone(), imm, -1, fun);
+fn imm(op: IrOp, imm: i32, ctx: &mut IrContext) -> Reg {
+    let r = ctx.fun.new_reg();
+    Ir::imm_emit(op, r.clone(), imm, -1, ctx);
     return r;
 }
 
-fn gen_pre_inc(ctype: &Type, lhs: &Node, fun: &mut Function, num: i32) -> Reg {
-    let r1 = gen_lval(lhs, fun);
-    let r2 = Reg::new();
-    load(ctype, r2.clone(), r1.clone(), fun);
-    let r3 = imm(IrImm, num * gen_inc_scale(ctype), fun);
-    let r4 = Reg::new();
-    Ir::emit(IrAdd, r4.clone(), r2, r3, fun);
-    store(ctype, r1, r4.clone(), fun);
+fn gen_pre_inc(ctype: &Type, lhs: &Node, ctx: &mut IrContext, num: i32) -> Reg {
+    let r1 = gen_lval(lhs, ctx);
+    let r2 = ctx.fun.new_reg();
+    load(ctype, r2.clone(), r1.clone(), ctx);
+    let r3 = imm(IrImm, num * gen_inc_scale(ctype), ctx);
+    let r4 = ctx.fun.new_reg();
+    Ir::emit(IrAdd(op_size(ctype)), r4.clone(), r2, r3, ctx);
+    store(ctype, r1, r4.clone(), ctx);
     return r4;
 }
 
-fn gen_post_inc(ctype: &Type, lhs: &Node, fun: &mut Function, num: i32) -> Reg {
-    let r1 = gen_pre_inc(ctype, lhs, fun, num);
-    let r2 = imm(IrImm, num * gen_inc_scale(ctype), fun);
-    let r3 = Reg::new();
-    Ir::emit(IrSub, r3.clone(), r1, r2, fun);
+fn gen_post_inc(ctype: &Type, lhs: &Node, ctx: &mut IrContext, num: i32) -> Reg {
+    let r1 = gen_pre_inc(ctype, lhs, ctx, num);
+    let r2 = imm(IrImm, num * gen_inc_scale(ctype), ctx);
+    let r3 = ctx.fun.new_reg();
+    Ir::emit(IrSub(op_size(ctype)), r3.clone(), r1, r2, ctx);
     return r3;
 }
 
-fn get_bb_break() -> Rc<RefCell<BB>> {
-    let break_vec = get_break_vec_rc_mut();
-    if let Some(bb_break) = break_vec.borrow().last() {
-        return bb_break.clone();
-    } else {
-        eprintln!("cannot find jmp point of break.");
-    }
-    std::process::exit(0);
+// sema rejects `break` and `continue` outside of a loop or switch.
+fn get_bb_break(ctx: &IrContext) -> Rc<RefCell<BB>> {
+    ctx.breaks.last().unwrap().clone()
 }
 
-fn get_bb_continue() -> Rc<RefCell<BB>> {
-    let continue_vec = get_continue_vec_rc_mut();
-    if let Some(bb_continue) = continue_vec.borrow().last() {
-        return bb_continue.clone();
-    } else {
-        eprintln!("cannot find jmp point of break.");
-    }
-    std::process::exit(0);
+fn get_bb_continue(ctx: &IrContext) -> Rc<RefCell<BB>> {
+    ctx.continues.last().unwrap().clone()
 }
 
-fn loop_inc(bb_continue: Rc<RefCell<BB>>, bb_break: Rc<RefCell<BB>>) {
-    let continue_vec = get_continue_vec_rc_mut();
-    let break_vec = get_break_vec_rc_mut();
-    continue_vec.borrow_mut().push(bb_continue);
-    break_vec.borrow_mut().push(bb_break);
+fn loop_inc(bb_continue: Rc<RefCell<BB>>, bb_break: Rc<RefCell<BB>>, ctx: &mut IrContext) {
+    ctx.continues.push(bb_continue);
+    ctx.breaks.push(bb_break);
 }
 
-fn loop_dec() {
-    let break_vec = get_break_vec_rc_mut();
-    let continue_vec = get_continue_vec_rc_mut();
-    if let None = break_vec.borrow_mut().pop() {
-        eprintln!("cannot find jmp point of break.");
-        std::process::exit(0);
-    }
-    if let None = continue_vec.borrow_mut().pop() {
-        eprintln!("cannot find jmp point of continue.");
-        std::process::exit(0);
-    }
-    return;
+fn loop_dec(ctx: &mut IrContext) {
+    ctx.breaks.pop();
+    ctx.continues.pop();
 }
 
 // In C, all expressions that can be written on the left-hand side of
@@ -435,32 +623,34 @@ fn loop_dec() {
 //
 // This function evaluates a given node as an lvalue.
 
-fn gen_lval(node: &Node, fun: &mut Function) -> Reg {
+fn gen_lval(node: &Node, ctx: &mut IrContext) -> Reg {
     match &node.op {
         NodeType::Deref(_, expr) => {
-            return gen_expr(expr, fun);
+            return gen_expr(expr, ctx);
         }
         NodeType::VarRef(var) => {
             if var.is_local {
-                let r = imm(IrBpRel, var.offset, fun);
+                let r = imm(IrBpRel, var.offset, ctx);
                 return r;
             } else {
-                let r = Reg::new();
+                let r = ctx.fun.new_reg();
                 Ir::emit(
                     IrLabelAddr(var.labelname.clone().unwrap()),
                     r.clone(),
                     Reg::dummy(),
                     Reg::dummy(),
-                    fun,
+                    ctx,
                 );
                 return r;
             }
         }
+        // the struct assigned, whose address the assignment gives
+        NodeType::Assign(..) => gen_expr(node, ctx),
         NodeType::Dot(ctype, expr, _) => {
-            let r1 = gen_lval(expr, fun);
-            let r2 = imm(IrImm, ctype.offset, fun);
-            let r3 = Reg::new();
-            Ir::emit(IrAdd, r3.clone(), r1, r2, fun);
+            let r1 = gen_lval(expr, ctx);
+            let r2 = imm(IrImm, ctype.offset, ctx);
+            let r3 = ctx.fun.new_reg();
+            Ir::emit(IrAdd(8), r3.clone(), r1, r2, ctx);
             return r3;
         }
         _ => {
@@ -469,216 +659,279 @@ fn gen_lval(node: &Node, fun: &mut Function) -> Reg {
     }
 }
 
+// Makes the line of the node current while it is lowered, and returns
+// the line to restore afterwards.
+fn enter_line(node: &Node, ctx: &mut IrContext) -> usize {
+    let saved = ctx.line;
+    if let Some(token) = &node.token {
+        ctx.line = token.line;
+    }
+    saved
+}
+
+fn gen_expr(node: &Node, ctx: &mut IrContext) -> Reg {
+    let saved = enter_line(node, ctx);
+    let r = gen_expr_node(node, ctx);
+    ctx.line = saved;
+    r
+}
+
+fn gen_stmt(node: &Node, ctx: &mut IrContext) {
+    let saved = enter_line(node, ctx);
+    gen_stmt_node(node, ctx);
+    ctx.line = saved;
+}
+
 // allocate of index for register to NodeNum
-fn gen_expr(node: &Node, fun: &mut Function) -> Reg {
+fn gen_expr_node(node: &Node, ctx: &mut IrContext) -> Reg {
+    // sema::annotate_types gives every expression a real type.
+    debug_assert!(
+        node.nodesctype(None).ty != Ty::NULL,
+        "untyped node reached gen_ir: {:?}",
+        node.op
+    );
     match &node.op {
         NodeType::Num(val) => {
-            let r = imm(IrImm, *val, fun);
+            let r = imm(IrImm, *val, ctx);
             return r;
         }
-        NodeType::BinaryTree(_, ty, lhs, rhs) => {
+        NodeType::BinaryTree(ctype, ty, lhs, rhs) => {
             match ty {
                 // a && b
                 TokenLogAnd => {
-                    let bb = BB::new_rc();
-                    let set0 = BB::new_rc();
-                    let set1 = BB::new_rc();
-                    let last = BB::new_param_rc();
+                    let bb = ctx.fun.new_bb();
+                    let set0 = ctx.fun.new_bb();
+                    let set1 = ctx.fun.new_bb();
+                    let last = ctx.fun.new_param_bb();
                     let ret = last.borrow().param.clone();
 
-                    Ir::br(
-                        gen_expr(lhs, fun),
-                        Some(Rc::clone(&bb)),
-                        Some(Rc::clone(&set0)),
-                        fun,
-                    );
+                    gen_br(lhs, &bb, &set0, ctx);
 
-                    fun.bb_push(bb);
-                    Ir::br(
-                        gen_expr(rhs, fun),
-                        Some(Rc::clone(&set1)),
-                        Some(Rc::clone(&set0)),
-                        fun,
-                    );
+                    ctx.fun.bb_push(bb);
+                    gen_br(rhs, &set1, &set0, ctx);
 
-                    fun.bb_push(set0);
-                    jmp(Some(Rc::clone(&last)), imm(IrImm, 0, fun), fun);
+                    ctx.fun.bb_push(set0);
+                    jmp(Some(Rc::clone(&last)), imm(IrImm, 0, ctx), ctx);
 
-                    fun.bb_push(set1);
-                    jmp(Some(Rc::clone(&last)), imm(IrImm, 1, fun), fun);
+                    ctx.fun.bb_push(set1);
+                    jmp(Some(Rc::clone(&last)), imm(IrImm, 1, ctx), ctx);
 
-                    fun.bb_push(last);
+                    ctx.fun.bb_push(last);
 
                     return ret;
                 }
                 // a || b
                 TokenLogOr => {
-                    let bb = BB::new_rc();
-                    let set0 = BB::new_rc();
-                    let set1 = BB::new_rc();
-                    let last = BB::new_param_rc();
+                    let bb = ctx.fun.new_bb();
+                    let set0 = ctx.fun.new_bb();
+                    let set1 = ctx.fun.new_bb();
+                    let last = ctx.fun.new_param_bb();
                     let ret = last.borrow().param.clone();
 
-                    Ir::br(
-                        gen_expr(lhs, fun),
-                        Some(Rc::clone(&set1)),
-                        Some(Rc::clone(&bb)),
-                        fun,
-                    );
+                    gen_br(lhs, &set1, &bb, ctx);
 
-                    fun.bb_push(bb);
-                    Ir::br(
-                        gen_expr(rhs, fun),
-                        Some(Rc::clone(&set1)),
-                        Some(Rc::clone(&last)),
-                        fun,
-                    );
+                    ctx.fun.bb_push(bb);
+                    gen_br(rhs, &set1, &set0, ctx);
 
-                    fun.bb_push(set0);
-                    jmp(Some(Rc::clone(&last)), imm(IrImm, 0, fun), fun);
+                    ctx.fun.bb_push(set0);
+                    jmp(Some(Rc::clone(&last)), imm(IrImm, 0, ctx), ctx);
 
-                    fun.bb_push(set1);
-                    jmp(Some(Rc::clone(&last)), imm(IrImm, 1, fun), fun);
+                    ctx.fun.bb_push(set1);
+                    jmp(Some(Rc::clone(&last)), imm(IrImm, 1, ctx), ctx);
 
-                    fun.bb_push(last);
+                    ctx.fun.bb_push(last);
 
                     return ret;
                 }
                 _ => {
                     // a R b (R != &&, ||)
-                    return gen_binop(Ir::bittype(ty), lhs, rhs, fun);
+                    let op = Ir::bittype(ty, op_size(ctype));
+                    if let (TokenShl | TokenShr, NodeType::Num(count @ 0..=31)) = (ty, &rhs.op) {
+                        let r0 = ctx.fun.new_reg();
+                        let r1 = gen_expr(lhs, ctx);
+                        Ir::shift_emit(op, r0.clone(), r1, *count, ctx);
+                        return r0;
+                    }
+                    gen_binop(op, lhs, rhs, ctx)
                 }
             }
         }
         // a
         NodeType::VarRef(var) => {
-            let r0 = Reg::new();
-            load(&var.ctype, r0.clone(), gen_lval(node, fun), fun);
+            let r0 = ctx.fun.new_reg();
+            load(&var.ctype, r0.clone(), gen_lval(node, ctx), ctx);
             return r0;
         }
         // a.b (struct member)
         NodeType::Dot(ctype, ..) => {
-            let r0 = Reg::new();
-            load(ctype, r0.clone(), gen_lval(node, fun), fun);
+            let r0 = ctx.fun.new_reg();
+            load(ctype, r0.clone(), gen_lval(node, ctx), ctx);
             return r0;
         }
+        // a = b, copying a struct and giving its address
+        NodeType::Assign(ctype, lhs, rhs) if matches!(ctype.ty, Ty::STRUCT(..)) => {
+            let src = gen_lval(rhs, ctx);
+            let dst = gen_lval(lhs, ctx);
+            copy_struct(ctype.size, dst.clone(), src, ctx);
+            dst
+        }
         // a = b
         NodeType::Assign(ctype, lhs, rhs) => {
-            let r2 = gen_expr(rhs, fun);
-            store(ctype, gen_lval(lhs, fun), r2.clone(), fun);
+            let r2 = gen_expr(rhs, ctx);
+            store(ctype, gen_lval(lhs, ctx), r2.clone(), ctx);
             return r2;
         }
         // fun(...)
         NodeType::Call(_, ident, callarg) => {
             let mut args = vec![];
             for arg in callarg {
-                args.push(gen_expr(arg, fun));
+                args.push(gen_expr(arg, ctx));
             }
-            let r = Reg::new();
+            let r = ctx.fun.new_reg();
             Ir::emit(
                 IrCall((*ident).clone(), args.clone()),
                 r.clone(),
                 Reg::dummy(),
                 Reg::dummy(),
-                fun,
+                ctx,
             );
             return r;
         }
+        // (*fptr)(...), calling the address in r1 instead of a label
+        NodeType::CallPtr(_, fptr, callarg) => {
+            let r1 = gen_expr(fptr, ctx);
+            let mut args = vec![];
+            for arg in callarg {
+                args.push(gen_expr(arg, ctx));
+            }
+            let r = ctx.fun.new_reg();
+            Ir::emit(
+                IrCall(String::new(), args),
+                r.clone(),
+                r1,
+                Reg::dummy(),
+                ctx,
+            );
+            r
+        }
         // *a
         NodeType::Deref(_, lhs) => {
-            let r0 = Reg::new();
+            let r0 = ctx.fun.new_reg();
             load(
                 lhs.nodesctype(None).ptr_to.unwrap().as_ref(),
                 r0.clone(),
-                gen_expr(lhs, fun),
-                fun,
+                gen_expr(lhs, ctx),
+                ctx,
             );
             return r0;
         }
         // &a
         NodeType::Addr(_, lhs) => {
-            return gen_lval(lhs, fun);
+            return gen_lval(lhs, ctx);
         }
         // a == b
         NodeType::Equal(lhs, rhs) => {
-            return gen_binop(IrEqual, lhs, rhs, fun);
+            return gen_binop(IrEqual, lhs, rhs, ctx);
         }
         // a != b
         NodeType::Ne(lhs, rhs) => {
-            return gen_binop(IrNe, lhs, rhs, fun);
+            return gen_binop(IrNe, lhs, rhs, ctx);
         }
         // !a
         NodeType::Not(expr) => {
-            let r0 = Reg::new();
+            let r0 = ctx.fun.new_reg();
             Ir::emit(
                 IrEqual,
                 r0.clone(),
-                gen_expr(expr, fun),
-                imm(IrImm, 0, fun),
-                fun,
+                gen_expr(expr, ctx),
+                imm(IrImm, 0, ctx),
+                ctx,
             );
             return r0;
         }
         // a ? b : c
-        NodeType::Ternary(_, cond, then, els) => {
-            let bb1 = BB::new_rc();
-            let bb2 = BB::new_rc();
-            let last = BB::new_param_rc();
+        NodeType::Ternary(ctype, cond, then, els) => {
+            let bb1 = ctx.fun.new_bb();
+            let bb2 = ctx.fun.new_bb();
+            // void arms pass no value
+            let last = match ctype.ty {
+                Ty::VOID => ctx.fun.new_bb(),
+                _ => ctx.fun.new_param_bb(),
+            };
             let ret = last.borrow().param.clone();
 
-            Ir::br(
-                gen_expr(cond, fun),
-                Some(Rc::clone(&bb1)),
-                Some(Rc::clone(&bb2)),
-                fun,
-            );
+            let r = gen_expr(cond, ctx);
+            Ir::br(r.clone(), Some(Rc::clone(&bb1)), Some(Rc::clone(&bb2)), ctx);
 
-            fun.bb_push(bb1);
-            jmp(Some(Rc::clone(&last)), gen_expr(then, fun), fun);
+            ctx.fun.bb_push(bb1);
+            // `a ?: b` reuses the value of the condition.
+            let r1 = match then.op {
+                NodeType::NULL => r,
+                _ => gen_expr(then, ctx),
+            };
+            jmp(Some(Rc::clone(&last)), r1, ctx);
 
-            fun.bb_push(bb2);
-            jmp(Some(Rc::clone(&last)), gen_expr(els, fun), fun);
+            ctx.fun.bb_push(bb2);
+            jmp(Some(Rc::clone(&last)), gen_expr(els, ctx), ctx);
 
-            fun.bb_push(last);
+            ctx.fun.bb_push(last);
 
             return ret;
         }
         // (a, b)
         NodeType::TupleExpr(_, lhs, rhs) => {
-            gen_expr(lhs, fun);
-            return gen_expr(rhs, fun);
+            gen_expr(lhs, ctx);
+            return gen_expr(rhs, ctx);
         }
         // ++a, a++
         NodeType::IncDec(ctype, selector, lhs) => {
             if *selector == 1 {
-                return gen_post_inc(ctype, lhs, fun, 1);
+                return gen_post_inc(ctype, lhs, ctx, 1);
             } else {
-                return gen_post_inc(ctype, lhs, fun, -1);
+                return gen_post_inc(ctype, lhs, ctx, -1);
+            }
+        }
+        // (void)expr keeps only the side effects, and has no value
+        NodeType::Cast(ctype, expr) if ctype.ty == Ty::VOID => {
+            if has_side_effects(expr) {
+                gen_expr(expr, ctx);
             }
+            Reg::dummy()
         }
         // _Bool x = 2; -> x == 1;
         NodeType::Cast(ctype, expr) => {
-            let r1 = gen_expr(expr, fun);
-            if ctype.ty != Ty::BOOL {
+            let r1 = gen_expr(expr, ctx);
+            let r0 = ctx.fun.new_reg();
+            if ctype.ty == Ty::BOOL {
+                Ir::emit(IrNe, r0.clone(), r1, imm(IrImm, 0, ctx), ctx);
+                return r0;
+            }
+            // only a narrowing cast changes the value
+            if ctype.size >= expr.nodesctype(None).size {
                 return r1;
             }
-            let r0 = Reg::new();
-            Ir::emit(IrNe, r0.clone(), r1, imm(IrImm, 0, fun), fun);
-            return r0;
+            match extend_op(ctype) {
+                Some(op) => {
+                    Ir::emit(op, r0.clone(), Reg::dummy(), r1, ctx);
+                    r0
+                }
+                None => r1,
+            }
         }
         NodeType::StmtExpr(_, body) => {
             if let NodeType::CompStmt(stmts) = &body.op {
-                let len = stmts.len();
-                for i in 0..len - 1 {
-                    gen_stmt(&stmts[i], fun);
-                }
-                if len > 0 {
-                    if let NodeType::Expr(ref expr) = stmts.last().unwrap().op {
-                        return gen_expr(expr, fun);
+                // `({})` has no statements and evaluates to 0.
+                if let Some((last, init)) = stmts.split_last() {
+                    for stmt in init {
+                        gen_stmt(stmt, ctx);
+                    }
+                    if let NodeType::Expr(ref expr) = last.op {
+                        return gen_expr(expr, ctx);
                     }
+                    gen_stmt(last, ctx);
                 }
             }
-            let r0 = imm(IrImm, 0, fun);
+            let r0 = imm(IrImm, 0, ctx);
             return r0;
         }
         _ => {
@@ -687,166 +940,175 @@ fn gen_expr(node: &Node, fun: &mut Function) -> Reg {
     }
 }
 
-fn gen_stmt(node: &Node, fun: &mut Function) {
+fn gen_stmt_node(node: &Node, ctx: &mut IrContext) {
     match &node.op {
         NodeType::NULL => {
             return;
         }
         NodeType::Ret(lhs) => {
-            Ir::emit(
-                IrRet,
-                Reg::dummy(),
-                Reg::dummy(),
-                gen_expr(lhs.as_ref(), fun),
-                fun,
-            );
-            fun.bb_push(BB::new_rc());
+            // return; has no value to pass in rax
+            let r = match lhs.op {
+                NodeType::NULL => Reg::dummy(),
+                _ => gen_expr(lhs.as_ref(), ctx),
+            };
+            Ir::emit(IrRet, Reg::dummy(), Reg::dummy(), r, ctx);
+            ctx.fun.bb_push(ctx.fun.new_bb());
         }
         NodeType::Expr(lhs) => {
             if let NodeType::ArrIni(_) = lhs.op {
-                gen_stmt(lhs, fun);
+                gen_stmt(lhs, ctx);
                 return;
             }
-            gen_expr(lhs.as_ref(), fun);
+            gen_expr(lhs.as_ref(), ctx);
         }
         NodeType::IfThen(cond, then, els) => {
-            let bbt = BB::new_rc();
-            let bbe = BB::new_rc();
-            let last = BB::new_rc();
-
-            Ir::br(
-                gen_expr(cond, fun),
-                Some(Rc::clone(&bbt)),
-                Some(Rc::clone(&bbe)),
-                fun,
-            );
+            let bbt = ctx.fun.new_bb();
+            let bbe = ctx.fun.new_bb();
+            let last = ctx.fun.new_bb();
+
+            gen_br(cond, &bbt, &bbe, ctx);
 
-            fun.bb_push(bbt);
-            gen_stmt(then, fun);
-            jmp(Some(Rc::clone(&last)), Reg::dummy(), fun);
+            ctx.fun.bb_push(bbt);
+            gen_stmt(then, ctx);
+            jmp(Some(Rc::clone(&last)), Reg::dummy(), ctx);
 
-            fun.bb_push(bbe);
+            ctx.fun.bb_push(bbe);
             if let Some(elsth) = els {
-                gen_stmt(elsth, fun);
+                gen_stmt(elsth, ctx);
             }
-            jmp(Some(Rc::clone(&last)), Reg::dummy(), fun);
+            jmp(Some(Rc::clone(&last)), Reg::dummy(), ctx);
 
-            fun.bb_push(last);
+            ctx.fun.bb_push(last);
         }
         NodeType::CompStmt(lhs) => {
             for stmt in lhs {
-                gen_stmt(stmt, fun);
+                gen_stmt(stmt, ctx);
             }
         }
         NodeType::For(init, cond, inc, body) => {
-            let bb_cond = BB::new_rc();
-            let bb_body = BB::new_rc();
-            let bb_continue = BB::new_rc();
-            let bb_break = BB::new_rc();
+            let bb_cond = ctx.fun.new_bb();
+            let bb_body = ctx.fun.new_bb();
+            let bb_continue = ctx.fun.new_bb();
+            let bb_break = ctx.fun.new_bb();
             let bb_cond_rc = Rc::clone(&bb_cond);
 
-            loop_inc(bb_continue.clone(), bb_break.clone());
-            gen_stmt(init, fun);
+            loop_inc(bb_continue.clone(), bb_break.clone(), ctx);
+            gen_stmt(init, ctx);
 
-            fun.bb_push(bb_cond);
+            ctx.fun.bb_push(bb_cond);
             match cond.op {
                 NodeType::NULL => {}
                 _ => {
-                    Ir::br(
-                        gen_expr(cond, fun),
-                        Some(Rc::clone(&bb_body)),
-                        Some(Rc::clone(&bb_break)),
-                        fun,
-                    );
+                    gen_br(cond, &bb_body, &bb_break, ctx);
                 }
             }
-            jmp(Some(Rc::clone(&bb_body)), Reg::dummy(), fun);
+            jmp(Some(Rc::clone(&bb_body)), Reg::dummy(), ctx);
 
-            fun.bb_push(bb_body);
-            gen_stmt(body, fun);
-            jmp(Some(Rc::clone(&bb_continue)), Reg::dummy(), fun);
+            ctx.fun.bb_push(bb_body);
+            gen_stmt(body, ctx);
+            jmp(Some(Rc::clone(&bb_continue)), Reg::dummy(), ctx);
 
-            fun.bb_push(bb_continue);
-            gen_stmt(inc, fun);
-            jmp(Some(bb_cond_rc), Reg::dummy(), fun);
+            ctx.fun.bb_push(bb_continue);
+            gen_stmt(inc, ctx);
+            jmp(Some(bb_cond_rc), Reg::dummy(), ctx);
 
-            fun.bb_push(bb_break);
+            ctx.fun.bb_push(bb_break);
 
-            loop_dec();
+            loop_dec(ctx);
         }
         NodeType::DoWhile(body, cond) => {
-            let bb_body = BB::new_rc();
-            let bb_continue = BB::new_rc();
-            let bb_break = BB::new_rc();
+            let bb_body = ctx.fun.new_bb();
+            let bb_continue = ctx.fun.new_bb();
+            let bb_break = ctx.fun.new_bb();
             let bb_body_rc = Rc::clone(&bb_body);
 
-            loop_inc(bb_continue.clone(), bb_break.clone());
+            loop_inc(bb_continue.clone(), bb_break.clone(), ctx);
 
-            fun.bb_push(bb_body);
-            gen_stmt(body, fun);
-            jmp(Some(Rc::clone(&bb_continue)), Reg::dummy(), fun);
+            ctx.fun.bb_push(bb_body);
+            gen_stmt(body, ctx);
+            jmp(Some(Rc::clone(&bb_continue)), Reg::dummy(), ctx);
 
-            fun.bb_push(bb_continue);
-            Ir::br(
-                gen_expr(cond, fun),
-                Some(bb_body_rc),
-                Some(Rc::clone(&bb_break)),
-                fun,
-            );
+            ctx.fun.bb_push(bb_continue);
+            gen_br(cond, &bb_body_rc, &bb_break, ctx);
 
-            fun.bb_push(bb_break);
+            ctx.fun.bb_push(bb_break);
 
-            loop_dec();
+            loop_dec(ctx);
         }
-        NodeType::Switch(cond, body, case_conds) => {
-            let bb_continue = BB::new_rc();
-            let bb_break = BB::new_rc();
-            loop_inc(bb_continue.clone(), bb_break.clone());
-            let switches = get_switches_rc_mut();
-            switches.borrow_mut().push(vec![]);
-
-            let r = gen_expr(cond, fun);
+        NodeType::Switch(cond, body, case_conds, has_default) => {
+            // `continue` in a switch continues the enclosing loop
+            let bb_continue = match ctx.continues.last() {
+                Some(bb) => Rc::clone(bb),
+                None => ctx.fun.new_bb(),
+            };
+            let bb_break = ctx.fun.new_bb();
+            loop_inc(bb_continue.clone(), bb_break.clone(), ctx);
+            ctx.switches.push(vec![]);
+
+            let r = gen_expr(cond, ctx);
 
             for val in case_conds {
-                let bbc = BB::new_rc();
-                let bbn = BB::new_rc();
+                let bbc = ctx.fun.new_bb();
+                let bbn = ctx.fun.new_bb();
 
-                let r0 = Reg::new();
-                Ir::emit(IrEqual, r0.clone(), gen_expr(val, fun), r.clone(), fun);
-                Ir::br(r0, Some(Rc::clone(&bbc)), Some(Rc::clone(&bbn)), fun);
+                let r1 = gen_expr(val, ctx);
+                Ir::br_cmp(
+                    Cond::Eq,
+                    r1,
+                    r.clone(),
+                    Rc::clone(&bbc),
+                    Rc::clone(&bbn),
+                    ctx,
+                );
 
-                fun.bb_push(bbn);
-                switches.borrow_mut().last_mut().unwrap().push(bbc);
+                ctx.fun.bb_push(bbn);
+                ctx.switches.last_mut().unwrap().push(bbc);
             }
-            switches.borrow_mut().last_mut().unwrap().reverse();
-            jmp(Some(Rc::clone(&bb_break)), Reg::dummy(), fun);
-            gen_stmt(body, fun);
-
-            fun.bb_push(bb_break);
-
-            loop_dec();
+            ctx.switches.last_mut().unwrap().reverse();
+            // no case matched
+            let bb_default = if *has_default {
+                Some(ctx.fun.new_bb())
+            } else {
+                None
+            };
+            let target = bb_default.clone().unwrap_or_else(|| Rc::clone(&bb_break));
+            jmp(Some(target), Reg::dummy(), ctx);
+            ctx.defaults.push(bb_default);
+            gen_stmt(body, ctx);
+            ctx.defaults.pop();
+            ctx.switches.pop();
+
+            ctx.fun.bb_push(bb_break);
+
+            loop_dec(ctx);
         }
         NodeType::Case(_, body) => {
-            if let Some(bb_case) = get_switches_rc_mut().borrow_mut().last_mut().unwrap().pop() {
-                fun.bb_push(bb_case);
-                gen_stmt(body, fun);
+            let bb_case = ctx.switches.last_mut().unwrap().pop();
+            if let Some(bb_case) = bb_case {
+                ctx.fun.bb_push(bb_case);
+                gen_stmt(body, ctx);
             } else {
                 panic!("gen_ir Case error.");
             }
         }
+        NodeType::Default(body) => {
+            let bb_default = ctx.defaults.last().cloned().flatten();
+            ctx.fun.bb_push(bb_default.unwrap());
+            gen_stmt(body, ctx);
+        }
         NodeType::ArrIni(arrini) => {
             for (lhs, rhs) in arrini {
-                let r2 = gen_expr(rhs, fun);
-                store(&rhs.nodesctype(None), gen_lval(lhs, fun), r2.clone(), fun);
+                let r2 = gen_expr(rhs, ctx);
+                store(&lhs.nodesctype(None), gen_lval(lhs, ctx), r2.clone(), ctx);
             }
         }
         NodeType::Break => {
-            jmp(Some(Rc::clone(&get_bb_break())), Reg::dummy(), fun);
-            fun.bb_push(BB::new_rc());
+            jmp(Some(Rc::clone(&get_bb_break(ctx))), Reg::dummy(), ctx);
+            ctx.fun.bb_push(ctx.fun.new_bb());
         }
         NodeType::Continue => {
-            jmp(Some(Rc::clone(&get_bb_continue())), Reg::dummy(), fun);
-            fun.bb_push(BB::new_rc());
+            jmp(Some(Rc::clone(&get_bb_continue(ctx))), Reg::dummy(), ctx);
+            ctx.fun.bb_push(ctx.fun.new_bb());
         }
         enode => {
             panic!("unexpeceted node {:?}", enode);
@@ -856,27 +1118,29 @@ fn gen_stmt(node: &Node, fun: &mut Function) {
 
 // generate IR Vector
 pub fn gen_ir(program: &mut Program) {
-    *REGNO.lock().unwrap() = 1;
-
     for funode in &mut program.nodes {
         match &mut funode.op {
             NodeType::Func(_, name, args, body, stacksize) => {
-                let mut fun = Function::new(
-                    name.clone(),
-                    vec![BB::new_rc()],
-                    LinkedHashMap::new(),
-                    *stacksize,
-                );
+                let fun =
+                    Function::new(name.clone(), LinkedHashMap::new(), roundup(*stacksize, 16));
+                let mut ctx = IrContext {
+                    fun,
+                    line: 0,
+                    switches: vec![],
+                    defaults: vec![],
+                    continues: vec![],
+                    breaks: vec![],
+                };
                 for i in 0..args.len() {
                     store_arg(
                         args[i].ctype.size as i32,
                         args[i].offset,
                         i as i32,
-                        &mut fun,
+                        &mut ctx,
                     );
                 }
-                gen_stmt(body, &mut fun);
-                program.funs.push(fun);
+                gen_stmt(body, &mut ctx);
+                program.funs.push(ctx.fun);
             }
             _ => {
                 panic!(" should be func node at gen_ir: {:?}", funode);
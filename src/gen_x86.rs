@@ -1,8 +1,11 @@
+use super::att::to_att;
 use super::gen_ir::{IrOp::*, *};
 use super::mir::*;
-use super::parse::roundup;
+use super::parse::{roundup, Type, Var};
 
-use std::collections::HashMap;
+use linked_hash_map::LinkedHashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
 use std::sync::Mutex;
 
 // This pass generates x86-64 assembly from IR.
@@ -19,14 +22,29 @@ macro_rules! hash {
 	};
 }
 
+// an instruction or a directive
 macro_rules! emit{
-    ($fmt:expr) => (print!(concat!("\t", $fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!("\t", $fmt, "\n"), $($arg)*));
+    ($asm:expr, $fmt:expr) => (emit_line($asm, $fmt));
+    ($asm:expr, $fmt:expr, $($arg:tt)*) => (emit_line($asm, &format!($fmt, $($arg)*)));
 }
 
-pub static REG8: [&str; 7] = ["r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b"];
-pub static REG32: [&str; 7] = ["r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d"];
-pub static REG64: [&str; 7] = ["r10", "r11", "rbx", "r12", "r13", "r14", "r15"];
+// a label or a section
+macro_rules! emitln{
+    ($asm:expr, $fmt:expr) => ($asm.push_str(concat!($fmt, "\n")));
+    ($asm:expr, $fmt:expr, $($arg:tt)*) => ($asm.push_str(&format!(concat!($fmt, "\n"), $($arg)*)));
+}
+
+// The registers the allocator hands out, followed by the argument
+// registers, which are only given to call arguments (see regalloc.rs).
+pub static REG8: [&str; 13] = [
+    "r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b", "dil", "sil", "dl", "cl", "r8b", "r9b",
+];
+pub static REG32: [&str; 13] = [
+    "r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d", "edi", "esi", "edx", "ecx", "r8d", "r9d",
+];
+pub static REG64: [&str; 13] = [
+    "r10", "r11", "rbx", "r12", "r13", "r14", "r15", "rdi", "rsi", "rdx", "rcx", "r8", "r9",
+];
 pub static ARGREG8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
 pub static ARGREG32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
 pub static ARGREG64: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
@@ -36,8 +54,38 @@ lazy_static! {
         /*('b', '\b'), ('f', '\f'),*/ ('\n', 'n'), ('\r', 'r'),
         ('\t', 't'), ('\\', '\\'), ('\'', '\''), ('\"', '\"')
     ]);
+    // -fstack-clash-protection
+    pub static ref STACK_PROBE: Mutex<bool> = Mutex::new(false);
+    // -fverbose-asm
+    pub static ref VERBOSE_ASM: Mutex<bool> = Mutex::new(false);
+    // -fmerge-strings
+    pub static ref MERGE_STRINGS: Mutex<bool> = Mutex::new(false);
+    // -fpic, -fpie
+    pub static ref PIC: Mutex<bool> = Mutex::new(false);
+    // --syntax=att
+    pub static ref ATT_SYNTAX: Mutex<bool> = Mutex::new(false);
 }
 
+// Instructions are written in Intel syntax, and translated as they are
+// emitted when AT&T syntax is asked for.
+fn emit_line(asm: &mut String, line: &str) {
+    let att = *ATT_SYNTAX.lock().unwrap() && !line.starts_with(['.', '#']);
+    let line = if att { to_att(line) } else { line.to_string() };
+    asm.push('\t');
+    asm.push_str(&line);
+    asm.push('\n');
+}
+
+// Block labels restart in each function, so the assembly label of a block
+// is qualified with the index of its function.
+fn bb_label(fun_index: usize, bb: &BB) -> String {
+    format!(".L{}.{}", fun_index, bb.label)
+}
+
+const PAGE_SIZE: i32 = 4096;
+
+// Escapes the first `len` bytes of a string for `.string`, which adds
+// the terminating zero itself. Each char of the string is a byte.
 fn escape(strname: String, len: i32) -> String {
     let mut p = strname.chars();
     let mut name = String::new();
@@ -50,23 +98,26 @@ fn escape(strname: String, len: i32) -> String {
             } else if c.is_ascii_graphic() || c == ' ' {
                 name.push(c);
             } else {
-                name.push_str(&format!("\\{:o}", c as i8));
+                // always three digits, so that a digit following it isn't
+                // read as part of it
+                name.push_str(&format!("\\{:03o}", c as u32 as u8));
             }
         } else {
             name.push_str("\\000");
         }
     }
-    name.push_str("\\000");
     return name;
 }
 
-fn emit_cmp(ir: &Ir, insn: String) {
+fn emit_cmp(ir: &Ir, insn: String, asm: &mut String) {
     let r0 = ir.r0.rn as usize;
     let r1 = ir.r1.rn as usize;
     let r2 = ir.r2.rn as usize;
-    emit!("cmp {}, {}", REG64[r1], REG64[r2]);
-    emit!("{} {}", insn, REG8[r0]);
-    emit!("movzb {}, {}", REG64[r0], REG8[r0]);
+    emit!(asm, "cmp {}, {}", REG64[r1], REG64[r2]);
+    emit!(asm, "{} {}", insn, REG8[r0]);
+    // writing the 32-bit register clears the upper half as well, so the
+    // result is 0 or 1 in all 64 bits.
+    emit!(asm, "movzx {}, {}", REG32[r0], REG8[r0]);
 }
 
 fn reg(size: i32, r: usize) -> &'static str {
@@ -79,6 +130,23 @@ fn reg(size: i32, r: usize) -> &'static str {
     }
 }
 
+// An int is kept sign-extended to 64 bits in its register, which an
+// operation on the 32-bit register doesn't do.
+fn sign_extend(size: i32, r: usize, asm: &mut String) {
+    if size == 4 {
+        emit!(asm, "movsxd {}, {}", REG64[r], REG32[r]);
+    }
+}
+
+// The count of a shift, which is in cl unless it is a constant.
+fn shift_count(ir: &Ir, asm: &mut String) -> String {
+    if !ir.r2.active() {
+        return ir.imm.to_string();
+    }
+    emit!(asm, "mov cl, {}", REG8[ir.r2.rn as usize]);
+    String::from("cl")
+}
+
 fn argreg(size: i32, r: usize) -> &'static str {
     if size == 1 {
         return ARGREG8[r];
@@ -89,189 +157,416 @@ fn argreg(size: i32, r: usize) -> &'static str {
     }
 }
 
-fn emit_ir(ir: &Ir, ret: &str) {
+// A call argument is in a register unless it was spilled.
+fn arg_operand(arg: &Reg) -> String {
+    if arg.active() {
+        return String::from(REG64[arg.rn as usize]);
+    }
+    format!("qword ptr [rbp-{}]", arg.spill_offset)
+}
+
+// `next` is the label emitted right after the instruction, if any, which
+// a jump to can fall through instead.
+fn emit_ir(ir: &Ir, fun_index: usize, ret: &str, next: Option<&str>, asm: &mut String) {
+    let jmp = |label: &str, asm: &mut String| {
+        if next != Some(label) {
+            emit!(asm, "jmp {}", label);
+        }
+    };
     let r0 = ir.r0.rn as usize;
     let r1 = ir.r1.rn as usize;
     let r2 = ir.r2.rn as usize;
 
     match &ir.op {
         IrImm => {
-            emit!("mov {}, {}", REG64[r0], ir.imm);
+            emit!(asm, "mov {}, {}", REG64[r0], ir.imm);
         }
         IrMov => {
-            emit!("mov {}, {}", REG64[r0], REG64[r2]);
+            emit!(asm, "mov {}, {}", REG64[r0], REG64[r2]);
         }
-        IrAdd => {
-            emit!("add {}, {}", REG64[r0], REG64[r2]);
+        IrAdd(size) => {
+            emit!(asm, "add {}, {}", reg(*size, r0), reg(*size, r2));
+            sign_extend(*size, r0, asm);
         }
-        IrSub => {
-            emit!("sub {}, {}", REG64[r0], REG64[r2]);
+        IrSub(size) => {
+            emit!(asm, "sub {}, {}", reg(*size, r0), reg(*size, r2));
+            sign_extend(*size, r0, asm);
         }
         IrBpRel => {
-            emit!("lea {}, [rbp-{}]", REG64[r0], ir.imm);
+            emit!(asm, "lea {}, [rbp-{}]", REG64[r0], ir.imm);
         }
-        IrMul => {
-            emit!("mov rax, {}", REG64[r2]);
-            emit!("imul {}", REG64[r0]);
-            emit!("mov {}, rax", REG64[r0]);
+        IrMul(size) => {
+            emit!(asm, "mov rax, {}", REG64[r2]);
+            emit!(asm, "imul {}", reg(*size, r0));
+            emit!(asm, "mov {}, rax", REG64[r0]);
+            sign_extend(*size, r0, asm);
         }
-        IrDiv => {
-            emit!("mov rax, {}", REG64[r0]);
-            emit!("cqo");
-            emit!("idiv {}", REG64[r2]);
-            emit!("mov {}, rax", REG64[r0]);
+        IrDiv(size) => {
+            emit!(asm, "mov rax, {}", REG64[r0]);
+            emit!(asm, "{}", if *size == 4 { "cdq" } else { "cqo" });
+            emit!(asm, "idiv {}", reg(*size, r2));
+            emit!(asm, "mov {}, rax", REG64[r0]);
+            sign_extend(*size, r0, asm);
         }
         IrRet => {
-            emit!("mov rax, {}", REG64[r2]);
-            emit!("jmp {}", ret);
+            if ir.r2.active() {
+                emit!(asm, "mov rax, {}", REG64[r2]);
+            }
+            jmp(ret, asm);
         }
         IrStore(size) => {
-            emit!("mov [{}], {}", REG64[r1], reg(*size, r2));
+            emit!(asm, "mov [{}], {}", REG64[r1], reg(*size, r2));
         }
         IrLoad(size) => {
-            emit!("mov {}, [{}]", reg(*size, r0), REG64[r2]);
-            if *size == 1 {
-                emit!("movzb {}, {}", REG64[r0], REG8[r0]);
-            }
+            emit!(asm, "mov {}, [{}]", reg(*size, r0), REG64[r2]);
+        }
+        IrMovsx(4) => {
+            emit!(asm, "movsxd {}, {}", REG64[r0], REG32[r2]);
+        }
+        IrMovsx(size) => {
+            emit!(asm, "movsx {}, {}", REG64[r0], reg(*size, r2));
+        }
+        // writing a 32-bit register clears the upper half
+        IrMovzx(4) => {
+            emit!(asm, "mov {}, {}", REG32[r0], REG32[r2]);
+        }
+        IrMovzx(size) => {
+            emit!(asm, "movzx {}, {}", REG32[r0], reg(*size, r2));
         }
         IrBr => {
-            emit!("cmp {}, 0", REG64[r2]);
-            emit!("jne .L{}", ir.bb1.clone().unwrap().borrow().label);
-            emit!("jmp .L{}", ir.bb2.clone().unwrap().borrow().label);
+            emit!(asm, "cmp {}, 0", REG64[r2]);
+            let then = bb_label(fun_index, &ir.bb1.clone().unwrap().borrow());
+            let els = bb_label(fun_index, &ir.bb2.clone().unwrap().borrow());
+            if next == Some(&then) {
+                emit!(asm, "je {}", els);
+            } else {
+                emit!(asm, "jne {}", then);
+                jmp(&els, asm);
+            }
+        }
+        IrBrCmp(cond) => {
+            emit!(asm, "cmp {}, {}", REG64[ir.r1.rn as usize], REG64[r2]);
+            let (jcc, negated) = match cond {
+                Cond::Lt => ("jl", "jge"),
+                Cond::Le => ("jle", "jg"),
+                Cond::Eq => ("je", "jne"),
+                Cond::Ne => ("jne", "je"),
+            };
+            let then = bb_label(fun_index, &ir.bb1.clone().unwrap().borrow());
+            let els = bb_label(fun_index, &ir.bb2.clone().unwrap().borrow());
+            if next == Some(&then) {
+                emit!(asm, "{} {}", negated, els);
+            } else {
+                emit!(asm, "{} {}", jcc, then);
+                jmp(&els, asm);
+            }
         }
         IrJmp => {
             if ir.bbarg.active() {
                 emit!(
+                    asm,
                     "mov {}, {}",
                     REG64[ir.bb1.clone().unwrap().borrow().param.rn as usize],
                     REG64[ir.bbarg.rn as usize]
                 );
             }
-            emit!("jmp .L{}", ir.bb1.clone().unwrap().borrow().label);
+            jmp(&bb_label(fun_index, &ir.bb1.clone().unwrap().borrow()), asm);
         }
         IrCall(name, args) => {
-            for i in 0..args.len() {
-                emit!("mov {}, {}", ARGREG64[i], REG64[args[i].rn as usize]);
+            // arguments past the sixth are pushed right to left, keeping
+            // the stack 16-byte aligned at the call
+            let stack_args = args.len().saturating_sub(ARGREG64.len());
+            let pad = stack_args % 2;
+            if pad == 1 {
+                emit!(asm, "sub rsp, 8");
+            }
+            for arg in args[ARGREG64.len().min(args.len())..].iter().rev() {
+                emit!(asm, "push {}", arg_operand(arg));
+            }
+            for (i, arg) in args.iter().take(ARGREG64.len()).enumerate() {
+                // a precolored argument is in place already
+                if arg_operand(arg) != ARGREG64[i] {
+                    emit!(asm, "mov {}, {}", ARGREG64[i], arg_operand(arg));
+                }
             }
 
-            emit!("push r10");
-            emit!("push r11");
-            emit!("mov rax, 0");
-            emit!("call {}", name);
-            emit!("pop r11");
-            emit!("pop r10");
+            emit!(asm, "mov rax, 0");
+            match name.is_empty() {
+                true => emit!(asm, "call {}", REG64[ir.r1.rn as usize]),
+                // through the PLT, which the dynamic linker fills in
+                false if *PIC.lock().unwrap() => emit!(asm, "call {}@PLT", name),
+                false => emit!(asm, "call {}", name),
+            }
+            if stack_args > 0 {
+                emit!(asm, "add rsp, {}", (stack_args + pad) * 8);
+            }
 
-            emit!("mov {}, rax", REG64[r0]);
+            emit!(asm, "mov {}, rax", REG64[r0]);
+        }
+        // a parameter past the sixth is found above the return address
+        IrStoreArg(size) if ir.imm2 as usize >= ARGREG64.len() => {
+            let offset = 16 + (ir.imm2 as usize - ARGREG64.len()) * 8;
+            emit!(asm, "mov rax, [rbp+{}]", offset);
+            let rax = match size {
+                1 => "al",
+                4 => "eax",
+                _ => "rax",
+            };
+            emit!(asm, "mov [rbp-{}], {}", ir.imm, rax);
         }
         IrStoreArg(size) => {
-            emit!("mov [rbp-{}], {}", ir.imm, argreg(*size, ir.imm2 as usize));
+            emit!(
+                asm,
+                "mov [rbp-{}], {}",
+                ir.imm,
+                argreg(*size, ir.imm2 as usize)
+            );
         }
         IrLt => {
-            emit_cmp(ir, String::from("setl"));
+            emit_cmp(ir, String::from("setl"), asm);
         }
         IrLe => {
-            emit_cmp(ir, String::from("setle"));
+            emit_cmp(ir, String::from("setle"), asm);
         }
         IrEqual => {
-            emit_cmp(ir, String::from("sete"));
+            emit_cmp(ir, String::from("sete"), asm);
         }
         IrNe => {
-            emit_cmp(ir, String::from("setne"));
+            emit_cmp(ir, String::from("setne"), asm);
+        }
+        // relative to rip in position-independent code
+        IrLabelAddr(label) if *PIC.lock().unwrap() => {
+            emit!(asm, "lea {}, [rip + {}]", REG64[r0], label);
         }
         IrLabelAddr(label) => {
-            emit!("lea {}, {}", REG64[r0], label);
+            emit!(asm, "lea {}, {}", REG64[r0], label);
         }
         IrOr => {
-            emit!("or {}, {}", REG64[r0], REG64[r2]);
+            emit!(asm, "or {}, {}", REG64[r0], REG64[r2]);
         }
         IrXor => {
-            emit!("xor {}, {}", REG64[r0], REG64[r2]);
+            emit!(asm, "xor {}, {}", REG64[r0], REG64[r2]);
         }
         IrAnd => {
-            emit!("and {}, {}", REG64[r0], REG64[r2]);
+            emit!(asm, "and {}, {}", REG64[r0], REG64[r2]);
         }
-        IrShl => {
-            emit!("mov cl, {}", REG8[r2]);
-            emit!("shl {}, cl", REG64[r0]);
+        IrShl(size) => {
+            let count = shift_count(ir, asm);
+            emit!(asm, "shl {}, {}", reg(*size, r0), count);
+            sign_extend(*size, r0, asm);
         }
         IrShr => {
-            emit!("mov cl, {}", REG8[r2]);
-            emit!("shr {}, cl", REG64[r0]);
+            let count = shift_count(ir, asm);
+            emit!(asm, "shr {}, {}", REG64[r0], count);
+        }
+        IrSar => {
+            let count = shift_count(ir, asm);
+            emit!(asm, "sar {}, {}", REG64[r0], count);
         }
-        IrMod => {
-            emit!("mov rax, {}", REG64[r0]);
-            emit!("cqo");
-            emit!("idiv {}", REG64[r2]);
-            emit!("mov {}, rdx", REG64[r0]);
+        IrMod(size) => {
+            emit!(asm, "mov rax, {}", REG64[r0]);
+            emit!(asm, "{}", if *size == 4 { "cdq" } else { "cqo" });
+            emit!(asm, "idiv {}", reg(*size, r2));
+            emit!(asm, "mov {}, rdx", REG64[r0]);
+            sign_extend(*size, r0, asm);
         }
         IrNeg => {
-            emit!("neg {}", REG64[r0]);
+            emit!(asm, "neg {}", REG64[r0]);
         }
         IrLoadSpill => {
-            emit!("mov {}, [rbp-{}]", REG64[r0], ir.r0.spill_offset);
+            emit!(asm, "mov {}, [rbp-{}]", REG64[r0], ir.r0.spill_offset);
         }
         IrStoreSpill => {
-            emit!("mov [rbp-{}], {}", ir.r1.spill_offset, REG64[r1]);
+            emit!(asm, "mov [rbp-{}], {}", ir.r1.spill_offset, REG64[r1]);
         }
     }
 }
 
-fn gen(fun: &mut Function, label: usize) {
+// Grows the stack by `size` bytes. With stack probing, a frame larger
+// than a page is allocated one page at a time and each page is touched,
+// so that a guard page can't be jumped over.
+fn alloc_frame(size: i32, label: usize, asm: &mut String) {
+    if !*STACK_PROBE.lock().unwrap() || size <= PAGE_SIZE {
+        emit!(asm, "sub rsp, {}", size);
+        return;
+    }
+    emit!(asm, "mov r11, rsp");
+    emit!(asm, "sub r11, {}", size / PAGE_SIZE * PAGE_SIZE);
+    emitln!(asm, ".Lprobe{}:", label);
+    emit!(asm, "sub rsp, {}", PAGE_SIZE);
+    emit!(asm, "or qword ptr [rsp], 0");
+    emit!(asm, "cmp rsp, r11");
+    emit!(asm, "jne .Lprobe{}", label);
+    if size % PAGE_SIZE != 0 {
+        emit!(asm, "sub rsp, {}", size % PAGE_SIZE);
+    }
+}
+
+fn gen(fun: &mut Function, label: usize, asm: &mut String) {
     // program
-    println!(".text");
-    println!(".global {}", fun.name);
-    println!("{}:", fun.name);
-    emit!("push rbp");
-    emit!("mov rbp, rsp");
-    emit!("sub rsp, {}", roundup(fun.stacksize, 16));
-    emit!("push r12");
-    emit!("push r13");
-    emit!("push r14");
-    emit!("push r15");
+    emitln!(asm, ".text");
+    emitln!(asm, ".global {}", fun.name);
+    emit!(asm, ".type {}, @function", fun.name);
+    emitln!(asm, "{}:", fun.name);
+    emit!(asm, "push rbp");
+    emit!(asm, "mov rbp, rsp");
+    // the saved registers are pushed below the frame, which is padded so
+    // that the stack stays 16-byte aligned
+    let mut frame = roundup(fun.stacksize, 16);
+    if fun.saved_regs.len() % 2 == 1 {
+        frame += 8;
+    }
+    if frame > 0 {
+        alloc_frame(frame, label, asm);
+    }
+    for rn in &fun.saved_regs {
+        emit!(asm, "push {}", REG64[*rn]);
+    }
 
     let ret = format!(".Lend{}", label);
+    let verbose = *VERBOSE_ASM.lock().unwrap();
+    let mut line = 0;
+
+    // labels some jump still goes to, rather than falls through to
+    let next_label = |i: usize| fun.bbs.get(i + 1).map(|bb| bb.borrow().label);
+    let mut targets = HashSet::new();
+    for (i, bb) in fun.bbs.iter().enumerate() {
+        let irs = &bb.borrow().irs;
+        for (j, ir) in irs.iter().enumerate() {
+            for target in ir.bb1.iter().chain(ir.bb2.iter()) {
+                let label = target.borrow().label;
+                if j + 1 < irs.len() || next_label(i) != Some(label) {
+                    targets.insert(label);
+                }
+            }
+        }
+    }
+
+    for (i, bb) in fun.bbs.iter().enumerate() {
+        let bb = bb.borrow();
+        if targets.contains(&bb.label) {
+            emitln!(asm, "{}:", bb_label(label, &bb));
+        }
+        let next = match fun.bbs.get(i + 1) {
+            Some(next) => bb_label(label, &next.borrow()),
+            None => ret.clone(),
+        };
+        for (j, ir) in bb.irs.iter().enumerate() {
+            // mark where the code of each source line starts
+            if verbose && ir.line > 0 && ir.line != line {
+                line = ir.line;
+                emit!(asm, "# line {}", line);
+            }
+            let last = j + 1 == bb.irs.len();
+            emit_ir(ir, label, &ret, if last { Some(&next) } else { None }, asm);
+        }
+    }
+
+    emitln!(asm, "{}:", ret);
+    for rn in fun.saved_regs.iter().rev() {
+        emit!(asm, "pop {}", REG64[*rn]);
+    }
+    emit!(asm, "mov rsp, rbp");
+    emit!(asm, "pop rbp");
+    emit!(asm, "ret");
+    emit!(asm, ".size {}, .-{}", fun.name, fun.name);
+}
 
-    for bb in &fun.bbs {
-        println!(".L{}:", bb.borrow().label);
-        for ir in &bb.borrow().irs {
-            emit_ir(ir, &ret);
+// Maps the label of each string literal which is a suffix of a longer
+// (or an equal, earlier) one to the label of that one and the offset
+// into it, so that both share the same bytes.
+fn merge_strings(gvars: &[Var]) -> LinkedHashMap<String, (String, usize)> {
+    let mut strs: Vec<(&String, &String)> = gvars
+        .iter()
+        .filter_map(|gvar| Some((gvar.labelname.as_ref()?, gvar.strname.as_ref()?)))
+        .collect();
+    // stable, so that of equal strings the first one is kept
+    strs.sort_by_key(|(_, s)| std::cmp::Reverse(s.chars().count()));
+    let mut kept: Vec<(&String, &String)> = vec![];
+    let mut merged = LinkedHashMap::new();
+    for (label, s) in strs {
+        match kept.iter().find(|(_, t)| t.ends_with(s.as_str())) {
+            Some((base, t)) => {
+                let offset = t.chars().count() - s.chars().count();
+                merged.insert(label.clone(), (base.to_string(), offset));
+            }
+            None => kept.push((label, s)),
         }
     }
+    merged
+}
 
-    println!("{}:", ret);
-    emit!("pop r15");
-    emit!("pop r14");
-    emit!("pop r13");
-    emit!("pop r12");
-    emit!("mov rsp, rbp");
-    emit!("pop rbp");
-    emit!("ret");
+// Returns the assembly of one function, the `index`th of the program.
+// Functions don't share any state here, so they can be generated on
+// different threads and their assembly concatenated in order.
+pub fn gen_fun(fun: &mut Function, index: usize) -> String {
+    let mut asm = String::new();
+    gen(fun, index, &mut asm);
+    asm
 }
 
-pub fn gen_x86(mut program: Program) {
-    println!(".intel_syntax noprefix");
+// Emits the label of a global variable, along with its type, size and
+// alignment.
+fn emit_object(name: &str, ctype: &Type, asm: &mut String) {
+    emit!(asm, ".type {}, @object", name);
+    emit!(asm, ".size {}, {}", name, ctype.size);
+    emit!(asm, ".align {}", ctype.align.max(1));
+    emitln!(asm, "{}:", name);
+}
+
+// Returns the header of the assembly and the global variables.
+pub fn gen_data(gvars: Vec<Var>) -> String {
+    let mut out = String::new();
+    let asm = &mut out;
+    if *ATT_SYNTAX.lock().unwrap() {
+        emitln!(asm, ".att_syntax");
+    } else {
+        emitln!(asm, ".intel_syntax noprefix");
+    }
+
+    let merged = if *MERGE_STRINGS.lock().unwrap() {
+        merge_strings(&gvars)
+    } else {
+        LinkedHashMap::new()
+    };
 
     // global variable
-    for gvar in program.gvars {
+    for gvar in gvars {
         if let Some(s) = gvar.strname {
-            println!(".data");
-            println!("{}:", gvar.labelname.unwrap());
-            emit!(".ascii \"{}\"", escape(s, gvar.ctype.size));
+            if merged.contains_key(gvar.labelname.as_ref().unwrap()) {
+                continue;
+            }
+            emitln!(asm, ".section .rodata");
+            emitln!(asm, "{}:", gvar.labelname.unwrap());
+            emit!(asm, ".string \"{}\"", escape(s, gvar.ctype.size - 1));
         } else {
+            let name = gvar.labelname.unwrap();
             if let Some(initvec) = gvar.init {
-                println!(".data");
-                println!("{}:", gvar.labelname.unwrap());
+                emitln!(asm, ".data");
+                emit_object(&name, &gvar.ctype, asm);
                 for gvar_init in initvec {
-                    println!("\t{}", gvar_init);
+                    emit!(asm, "{}", gvar_init);
                 }
             } else {
-                println!(".bss");
-                println!("{}:", gvar.labelname.unwrap());
-                emit!(".zero {}", gvar.ctype.size);
+                emitln!(asm, ".bss");
+                emit_object(&name, &gvar.ctype, asm);
+                emit!(asm, ".zero {}", gvar.ctype.size);
             }
         }
     }
-    for i in 0..program.funs.len() {
-        gen(&mut program.funs[i], i);
+    for (label, (base, offset)) in &merged {
+        emitln!(asm, ".set {}, {}+{}", label, base, offset);
+    }
+    // the stack needn't be executable
+    emitln!(asm, ".section .note.GNU-stack,\"\",@progbits");
+    out
+}
+
+// Writes the assembly of the program to `out`, a function at a time.
+pub fn gen_x86(mut program: Program, out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(gen_data(std::mem::take(&mut program.gvars)).as_bytes())?;
+    for (i, fun) in program.funs.iter_mut().enumerate() {
+        out.write_all(gen_fun(fun, i).as_bytes())?;
     }
+    out.flush()
 }
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Identifiers are interned once per compilation: each distinct name is
+// given a small number, its symbol, which the parser's scopes are keyed
+// by. Looking a name up in nested scopes then hashes the string once
+// instead of once per scope, and the name itself is shared.
+
+pub type Symbol = u32;
+
+// the symbol of the empty name, which tokens other than identifiers carry
+pub const EMPTY: Symbol = 0;
+
+pub struct Interner {
+    ids: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+impl Default for Interner {
+    // The empty name is interned first, as EMPTY.
+    fn default() -> Self {
+        let mut interner = Self {
+            ids: HashMap::new(),
+            names: vec![],
+        };
+        interner.intern("");
+        interner
+    }
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.ids.get(name) {
+            return *sym;
+        }
+        let sym = self.names.len() as Symbol;
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(Rc::clone(&name));
+        self.ids.insert(name, sym);
+        sym
+    }
+    pub fn name(&self, sym: Symbol) -> &str {
+        &self.names[sym as usize]
+    }
+}
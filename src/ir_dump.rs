@@ -6,7 +6,11 @@ pub fn dump_ir(irv: &Vec<Function>, dump_option: &str) {
         println!("{}():", fun.name);
         for bb in &fun.bbs {
             for ir in &bb.borrow().irs {
-                println!("{}", ir.tostr());
+                if ir.line > 0 {
+                    println!("{} ; line {}", ir.tostr(), ir.line);
+                } else {
+                    println!("{}", ir.tostr());
+                }
             }
         }
     }
@@ -0,0 +1,133 @@
+use super::dce::prune_unreachable;
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Jump threading.
+//
+// The control-flow lowerings leave blocks which do nothing but jump to
+// another block, e.g. the join block of an `if` nested in a loop. Jumps
+// to such a forwarding block are redirected to where it leads, and the
+// blocks no longer reached are removed. This runs after register
+// allocation, so a block emptied by other passes but refilled with
+// spill code is not mistaken for a forwarding one. Jumps to the block
+// emitted next are dropped by gen_x86.
+
+// Maps the label of each forwarding block to the block it leads to.
+// An empty block falls through to the next one.
+fn forwarding(fun: &Function) -> HashMap<i32, Rc<RefCell<BB>>> {
+    let mut forward = HashMap::new();
+    for (i, bb) in fun.bbs.iter().enumerate() {
+        let bb = bb.borrow();
+        if bb.param.active() {
+            continue;
+        }
+        match &bb.irs[..] {
+            [] if i + 1 < fun.bbs.len() => {
+                forward.insert(bb.label, Rc::clone(&fun.bbs[i + 1]));
+            }
+            [ir] if ir.op == IrJmp && !ir.bbarg.active() => {
+                forward.insert(bb.label, Rc::clone(ir.bb1.as_ref().unwrap()));
+            }
+            _ => {}
+        }
+    }
+    forward
+}
+
+// Follows forwarding blocks from `label`, stopping at a cycle.
+fn resolve(label: i32, forward: &HashMap<i32, Rc<RefCell<BB>>>) -> Rc<RefCell<BB>> {
+    let mut target = Rc::clone(&forward[&label]);
+    let mut seen = HashSet::from([label]);
+    loop {
+        let label = target.borrow().label;
+        if !seen.insert(label) {
+            return target;
+        }
+        match forward.get(&label) {
+            Some(next) => target = Rc::clone(next),
+            None => return target,
+        }
+    }
+}
+
+// Drops what follows the first jump or return of each block, such as
+// the `Jmp` emitted after the `Br` of a loop condition.
+fn truncate_after_jumps(fun: &mut Function) {
+    for bb in &fun.bbs {
+        let mut bb = bb.borrow_mut();
+        if let Some(i) = bb
+            .irs
+            .iter()
+            .position(|ir| matches!(ir.op, IrJmp | IrBr | IrBrCmp(_) | IrRet))
+        {
+            bb.irs.truncate(i + 1);
+        }
+    }
+}
+
+fn thread(fun: &mut Function) -> usize {
+    truncate_after_jumps(fun);
+    let forward = forwarding(fun);
+    let resolved: HashMap<i32, Rc<RefCell<BB>>> = forward
+        .keys()
+        .map(|label| (*label, resolve(*label, &forward)))
+        .collect();
+    let mut threaded = 0;
+    for bb_rc in &fun.bbs {
+        let mut bb = bb_rc.borrow_mut();
+        for ir in bb.irs.iter_mut() {
+            for target in ir.bb1.iter_mut().chain(ir.bb2.iter_mut()) {
+                // a block jumping to itself is borrowed already
+                if Rc::ptr_eq(target, bb_rc) {
+                    continue;
+                }
+                let label = target.borrow().label;
+                if let Some(to) = resolved.get(&label) {
+                    if !Rc::ptr_eq(to, target) {
+                        *target = Rc::clone(to);
+                        threaded += 1;
+                    }
+                }
+            }
+            // both arms lead to the same place
+            if ir.bb2.is_some() && Rc::ptr_eq(ir.bb1.as_ref().unwrap(), ir.bb2.as_ref().unwrap()) {
+                ir.op = IrJmp;
+                ir.r1 = Reg::dummy();
+                ir.r2 = Reg::dummy();
+                ir.bb2 = None;
+            }
+        }
+    }
+    prune_unreachable(fun);
+
+    // empty blocks only fall through, so the ones no jump goes to
+    // anymore are dropped
+    let mut targets = HashSet::new();
+    for bb in &fun.bbs {
+        for ir in &bb.borrow().irs {
+            for target in ir.bb1.iter().chain(ir.bb2.iter()) {
+                targets.insert(target.borrow().label);
+            }
+        }
+    }
+    let last = fun.bbs.last().map(Rc::clone);
+    fun.bbs.retain(|bb| {
+        let bb_ref = bb.borrow();
+        let empty = bb_ref.irs.is_empty() && !bb_ref.param.active();
+        !empty || targets.contains(&bb_ref.label) || Rc::ptr_eq(bb, last.as_ref().unwrap())
+    });
+    fun.compute_cfg();
+    threaded
+}
+
+pub fn thread_jumps(program: &mut Program) -> usize {
+    let mut threaded = 0;
+    for fun in &mut program.funs {
+        threaded += thread(fun);
+    }
+    threaded
+}
@@ -1,9 +1,286 @@
-pub fn error(path: Option<String>, line: usize, message: &str) {
-    if let Some(p) = path {
-        eprintln!("Compile error at: {}. Line: {}", p, line);
-    } else {
-        eprintln!("Compile error.");
-    }
-    eprintln!("{}", message);
-    std::process::exit(1);
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub mod att;
+pub mod copyprop;
+pub mod cse;
+pub mod dce;
+pub mod gen_ir;
+pub mod gen_x86;
+pub mod intern;
+pub mod ir_dump;
+pub mod jumps;
+pub mod liveness;
+pub mod mir;
+pub mod parse;
+pub mod passes;
+pub mod preprocess;
+pub mod regalloc;
+pub mod sema;
+pub mod strength;
+pub mod token;
+pub mod verify;
+
+#[macro_use]
+extern crate lazy_static;
+
+// State of one compilation, passed explicitly instead of living in
+// process-wide globals, so that compilations can't leak into each
+// other and the parser doesn't take a lock on every lookup.
+//
+// Some state is still global: the source text of every file
+// (token::PROGRAMS, FILES), which diagnostics read from anywhere, and
+// the options set from the command line (the parse::WARN_* flags and
+// those of regalloc and gen_x86). Until they move here too, compile()
+// runs one compilation at a time.
+#[derive(Default)]
+pub struct Context {
+    // bytes of locals allocated so far in the current function
+    pub stacksize: i32,
+    // number of the last string literal label
+    pub str_label: i32,
+    // the innermost scope, linked to the enclosing ones
+    pub env: parse::Env,
+    // global variables and string literals, in definition order
+    pub gvars: Vec<parse::Var>,
+    // identifiers met so far
+    pub names: intern::Interner,
+    // file-scope declarations, to check redeclarations against
+    pub symbols: HashMap<intern::Symbol, parse::Symbol>,
+    // file-scope function definitions -> position of their declaration
+    pub funcs: HashMap<intern::Symbol, usize>,
+    // labels of the switch statements being parsed, innermost last
+    pub switches: Vec<parse::SwitchLabels>,
+    // the array whose brace initializer is being parsed
+    pub arrini: Option<parse::Var>,
+    // loops and switches enclosing the statement sema is walking, which
+    // decide where `break` and `continue` may appear
+    pub loop_depth: i32,
+    pub switch_depth: i32,
+    // errors the parser and sema recovered from
+    pub errors: Vec<CompileError>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Every input file, the main source as well as each `#include`d header,
+// is identified by its program id. We remember where each file was
+// included from so that diagnostics can print the include chain.
+pub struct SourceFile {
+    pub path: String,
+    pub included_from: Option<(usize, usize)>, // (program_id, line)
+}
+
+lazy_static! {
+    pub static ref FILES: Mutex<Vec<SourceFile>> = Mutex::new(vec![]);
+}
+
+pub fn add_file(path: String, included_from: Option<(usize, usize)>) -> usize {
+    let mut files = FILES.lock().unwrap();
+    files.push(SourceFile {
+        path,
+        included_from,
+    });
+    files.len() - 1
+}
+
+pub fn get_path(program_id: usize) -> String {
+    return FILES.lock().unwrap()[program_id].path.clone();
+}
+
+// Returns the (path, line) of every `#include` directive that led to
+// the given file, outermost first.
+pub fn include_chain(program_id: usize) -> Vec<(String, usize)> {
+    let files = FILES.lock().unwrap();
+    let mut chain = vec![];
+    let mut parent = files[program_id].included_from;
+    while let Some((id, line)) = parent {
+        chain.push((files[id].path.clone(), line));
+        parent = files[id].included_from;
+    }
+    chain.reverse();
+    chain
+}
+
+// A diagnostic pointing at the source range [pos, end) of a program.
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub program_id: usize,
+    pub pos: usize,
+    pub end: usize,
+    pub msg: String,
+}
+
+impl CompileError {
+    pub fn new(program_id: usize, pos: usize, end: usize, msg: String) -> Self {
+        Self {
+            program_id,
+            pos,
+            end,
+            msg,
+        }
+    }
+    pub fn format(&self, source: &str) -> String {
+        diagnostic(
+            "error",
+            self.program_id,
+            source,
+            self.pos,
+            self.end,
+            &self.msg,
+        )
+    }
+}
+
+// Formats a message as `file:line:col: kind: message` followed by the
+// offending source line with a caret under the range [pos, end).
+pub fn diagnostic(
+    kind: &str,
+    program_id: usize,
+    source: &str,
+    pos: usize,
+    end: usize,
+    msg: &str,
+) -> String {
+    let mut s = String::new();
+    for (path, line) in include_chain(program_id) {
+        s.push_str(&format!("In file included from {}:{}:\n", path, line));
+    }
+    let pos = std::cmp::min(pos, source.len());
+    let start = match source[..pos].rfind('\n') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let line_end = match source[pos..].find('\n') {
+        Some(i) => pos + i,
+        None => source.len(),
+    };
+    let line = source[..pos].matches('\n').count() + 1;
+    let col = pos - start + 1;
+    s.push_str(&format!(
+        "{}:{}:{}: {}: {}\n",
+        get_path(program_id),
+        line,
+        col,
+        kind,
+        msg
+    ));
+    s.push_str(&source[start..line_end]);
+    s.push('\n');
+    // keep tabs so that the caret lines up with the source line
+    for c in source[start..pos].chars() {
+        s.push(if c == '\t' { '\t' } else { ' ' });
+    }
+    s.push('^');
+    for _ in pos + 1..std::cmp::min(end, line_end) {
+        s.push('~');
+    }
+    s.push('\n');
+    s
+}
+
+// Returns the edit distance between two strings.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push(
+                *[prev[j] + cost, prev[j + 1] + 1, cur[j] + 1]
+                    .iter()
+                    .min()
+                    .unwrap(),
+            );
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+lazy_static! {
+    // the source files and options are still globals, so only one
+    // compilation can run at a time.
+    static ref COMPILING: Mutex<()> = Mutex::new(());
+}
+
+// Puts the options set from the command line back to their defaults.
+fn reset_options() {
+    let off = [
+        &*parse::WARN_UNUSED_VARIABLE,
+        &*parse::WARN_UNUSED_PARAMETER,
+        &*parse::WARN_UNREACHABLE_CODE,
+        &*parse::WARN_UNINITIALIZED,
+        &*parse::WARN_SWITCH,
+        &*gen_x86::STACK_PROBE,
+        &*gen_x86::VERBOSE_ASM,
+        &*gen_x86::MERGE_STRINGS,
+        &*gen_x86::PIC,
+        &*gen_x86::ATT_SYNTAX,
+        &*regalloc::ITERATIVE_REGALLOC,
+    ];
+    for option in off {
+        *option.lock().unwrap() = false;
+    }
+    *parse::WARN_INT_CONVERSION.lock().unwrap() = true;
+}
+
+// Compiles the C source with the default options and returns its
+// assembly. The global state left by a previous compilation is reset
+// first. On failure the first error in source order is returned; all
+// errors and warnings are still printed to stderr as by the command
+// line driver.
+pub fn compile(source: &str) -> Result<String, CompileError> {
+    let _guard = COMPILING.lock().unwrap_or_else(|e| e.into_inner());
+    FILES.lock().unwrap().clear();
+    token::PROGRAMS.lock().unwrap().clear();
+    reset_options();
+    let program_id = preprocess::add_source(String::from("<input>"), source.to_string(), None);
+
+    let tokens = match token::tokenize(program_id, true) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            let source = token::PROGRAMS.lock().unwrap()[e.program_id].clone();
+            eprint!("{}", e.format(&source));
+            return Err(e);
+        }
+    };
+    let mut ctx = Context::new();
+    let mut tokenset = token::TokenSet::new(tokens, &mut ctx);
+    let mut program = mir::Program::new();
+    let mut errors = vec![];
+    if let Err(mut e) = parse::parse(&mut tokenset, &mut program) {
+        errors.append(&mut e);
+    }
+    if errors.len() < parse::MAX_ERRORS {
+        if let Err(mut e) = sema::sema(&mut program, tokenset.ctx) {
+            errors.append(&mut e);
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| (e.program_id, e.pos));
+        for e in &errors {
+            let source = token::PROGRAMS.lock().unwrap()[e.program_id].clone();
+            eprint!("{}", e.format(&source));
+        }
+        return Err(errors.swap_remove(0));
+    }
+
+    // at -O1
+    let mut stats = passes::Stats::default();
+    passes::run(passes::AST_PASSES, 1, &mut program, &mut stats);
+    gen_ir::gen_ir(&mut program);
+    passes::run(passes::IR_PASSES, 1, &mut program, &mut stats);
+    regalloc::to_two_address(&mut program);
+    passes::run(passes::TWO_ADDRESS_PASSES, 1, &mut program, &mut stats);
+    regalloc::alloc_regs(&mut program);
+    passes::run(passes::ALLOCATED_PASSES, 1, &mut program, &mut stats);
+    let mut asm = vec![];
+    gen_x86::gen_x86(program, &mut asm).unwrap();
+    Ok(String::from_utf8(asm).unwrap())
 }
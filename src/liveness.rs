@@ -1,59 +1,194 @@
-use super::gen_ir::IrOp;
+use super::gen_ir::{Function, Ir, IrOp};
 use super::mir::*;
 
-use linked_hash_map::LinkedHashMap;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::rc::Rc;
 
-// decide lifespan for all Reg
-pub fn regs_life(
-    bb: &Rc<RefCell<BB>>,
-    ic: &mut i32,
-    borned_map: &mut LinkedHashMap<i32, i32>,
-    died_map: &mut HashMap<i32, i32>,
-) {
-    if bb.borrow().passed {
-        return;
+// Liveness analysis.
+//
+// A register is live at a point if some path from there reads it
+// before writing it. For each block, the registers read before being
+// written in it (use) and the ones written in it (def) are collected,
+// and the classic backward dataflow
+//
+//   out(B) = union of in(S) over the successors S of B
+//   in(B) = use(B) + (out(B) - def(B))
+//
+// is iterated to a fixed point. A jump passing a value to a block with
+// a parameter writes the parameter, so the parameter is live out of
+// the jumping block rather than defined in the block which takes it.
+
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<i32>>,
+    pub live_out: Vec<BTreeSet<i32>>,
+}
+
+fn uses(ir: &Ir) -> Vec<i32> {
+    ir.uses().into_iter().map(|r| r.vn).collect()
+}
+
+// A jump passing a value to a block also writes the block's parameter.
+fn defs(ir: &Ir) -> Vec<i32> {
+    let mut regs: Vec<i32> = ir.defs().into_iter().map(|r| r.vn).collect();
+    if ir.bbarg.active() {
+        regs.push(ir.bb1.as_ref().unwrap().borrow().param.vn);
     }
-    bb.borrow_mut().passed = true;
+    regs
+}
 
-    // decide lifespan
-    let param_vn = bb.borrow().param.vn;
-    if param_vn > 0 {
-        borned_map.insert(param_vn, *ic);
-        died_map.insert(param_vn, *ic);
+fn use_def(bb: &BB) -> (BTreeSet<i32>, BTreeSet<i32>) {
+    let mut used = BTreeSet::new();
+    let mut defined = BTreeSet::new();
+    for ir in &bb.irs {
+        for vn in uses(ir) {
+            if !defined.contains(&vn) {
+                used.insert(vn);
+            }
+        }
+        defined.extend(defs(ir));
     }
-    for ir in &bb.borrow().irs {
-        if !borned_map.contains_key(&ir.r0.vn) {
-            borned_map.insert(ir.r0.vn, *ic);
-            died_map.insert(ir.r0.vn, *ic);
+    (used, defined)
+}
+
+pub fn liveness(fun: &Function) -> Liveness {
+    let index: HashMap<BBId, usize> = fun
+        .bbs
+        .iter()
+        .enumerate()
+        .map(|(i, bb)| (bb.borrow().label, i))
+        .collect();
+    let use_defs: Vec<_> = fun.bbs.iter().map(|bb| use_def(&bb.borrow())).collect();
+    let mut live_in = vec![BTreeSet::new(); fun.bbs.len()];
+    let mut live_out = vec![BTreeSet::new(); fun.bbs.len()];
+
+    // visiting successors first makes it converge faster
+    let order: Vec<usize> = fun
+        .rpo()
+        .map(|bb| index[&bb.borrow().label])
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &i in &order {
+            let mut out = BTreeSet::new();
+            for succ in &fun.bbs[i].borrow().succ {
+                out.extend(live_in[index[succ]].iter().copied());
+            }
+            let (used, defined) = &use_defs[i];
+            let mut inn = used.clone();
+            inn.extend(out.difference(defined).copied());
+            if inn != live_in[i] || out != live_out[i] {
+                live_in[i] = inn;
+                live_out[i] = out;
+                changed = true;
+            }
         }
-        if ir.r1.active() {
-            died_map.insert(ir.r1.vn, *ic);
+    }
+    Liveness { live_in, live_out }
+}
+
+// The blocks in the order their instructions are numbered from 1 by
+// live_ranges: reverse postorder, followed by the blocks no longer
+// reachable.
+pub fn block_order(fun: &Function) -> Vec<Rc<RefCell<BB>>> {
+    let mut order: Vec<Rc<RefCell<BB>>> = fun.rpo().collect();
+    for bb in &fun.bbs {
+        if !order.iter().any(|visited| Rc::ptr_eq(visited, bb)) {
+            order.push(Rc::clone(bb));
         }
-        if ir.r2.active() {
-            died_map.insert(ir.r2.vn, *ic);
+    }
+    order
+}
+
+// Numbers the instructions of the function, visiting blocks in reverse
+// postorder, and returns for each register the range of numbers it is
+// live in: its definitions and uses, and the whole of every block it
+// is live through. Ranges are sorted by their start, and also list the
+// numbers of the instructions reading or writing the register and
+// whether a call comes in the middle of the range.
+pub fn live_ranges(fun: &Function) -> Vec<RegLife> {
+    let live = liveness(fun);
+    let index: HashMap<BBId, usize> = fun
+        .bbs
+        .iter()
+        .enumerate()
+        .map(|(i, bb)| (bb.borrow().label, i))
+        .collect();
+    let order = block_order(fun);
+
+    let mut ranges: HashMap<i32, (i32, i32)> = HashMap::new();
+    let mut points: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut calls = vec![];
+    let mut extend = |vn: i32, ic: i32| {
+        let range = ranges.entry(vn).or_insert((ic, ic));
+        range.0 = range.0.min(ic);
+        range.1 = range.1.max(ic);
+    };
+    let mut ic = 1;
+    for bb in order {
+        let bb = bb.borrow();
+        let i = index[&bb.label];
+        let start = ic;
+        if bb.param.active() {
+            extend(bb.param.vn, start);
         }
-        if ir.bbarg.active() {
-            died_map.insert(ir.bbarg.vn, *ic);
+        for vn in &live.live_in[i] {
+            extend(*vn, start);
         }
-        if let IrOp::IrCall(_, args) = &ir.op {
-            for i in 0..args.len() {
-                died_map.insert(args[i].vn, *ic);
+        for ir in &bb.irs {
+            if let IrOp::IrCall(..) = ir.op {
+                calls.push(ic);
+            }
+            for vn in uses(ir).into_iter().chain(defs(ir)) {
+                extend(vn, ic);
+                let points = points.entry(vn).or_default();
+                if points.last() != Some(&ic) {
+                    points.push(ic);
+                }
             }
+            ic += 1;
+        }
+        let end = (ic - 1).max(start);
+        for vn in &live.live_out[i] {
+            extend(*vn, end);
         }
-        *ic += 1;
     }
-    // when jmp block, the starting ic of it is the ic of this block
-    let irs = &bb.borrow().irs;
-    let irie = irs.iter();
-    if let Some(ir) = irie.last() {
-        if let Some(bb1) = &ir.bb1 {
-            regs_life(bb1, ic, borned_map, died_map);
-        }
-        if let Some(bb2) = &ir.bb1 {
-            regs_life(bb2, ic, borned_map, died_map);
+
+    let mut reglifes: Vec<RegLife> = ranges
+        .into_iter()
+        .map(|(vn, (start, end))| {
+            let uses = points.remove(&vn).unwrap_or_default();
+            let mut life = RegLife::new(vn, start, end, uses);
+            // the arguments are read and the result written by the call
+            life.crosses_call = calls.iter().any(|ic| start < *ic && *ic < end);
+            life
+        })
+        .collect();
+    reglifes.sort_by_key(|life| (life.start, life.vn));
+    reglifes
+}
+
+fn regs_str(regs: &BTreeSet<i32>) -> String {
+    let regs: Vec<String> = regs.iter().map(|vn| format!("r{}", vn)).collect();
+    regs.join(", ")
+}
+
+pub fn dump_liveness(funs: &[Function]) {
+    println!("-dump-liveness: ");
+    for fun in funs {
+        println!("{}():", fun.name);
+        let live = liveness(fun);
+        for (i, bb) in fun.bbs.iter().enumerate() {
+            println!(
+                ".L{}: in [{}] out [{}]",
+                bb.borrow().label,
+                regs_str(&live.live_in[i]),
+                regs_str(&live.live_out[i])
+            );
         }
     }
 }
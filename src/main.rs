@@ -1,59 +1,385 @@
 use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::Command;
 
-pub mod gen_ir;
-pub mod gen_x86;
-pub mod ir_dump;
-pub mod lib;
-pub mod liveness;
-pub mod mir;
-pub mod parse;
-pub mod preprocess;
-pub mod regalloc;
-pub mod sema;
-pub mod token;
-
-use gen_ir::*;
-use gen_x86::*;
-use ir_dump::*;
-use mir::*;
-use parse::*;
-use preprocess::*;
-use regalloc::*;
-use sema::*;
-use token::*;
-
-#[macro_use]
-extern crate lazy_static;
+use rayon::prelude::*;
+
+use mir9cc::gen_ir::*;
+use mir9cc::gen_x86::*;
+use mir9cc::ir_dump::*;
+use mir9cc::liveness::*;
+use mir9cc::mir::*;
+use mir9cc::parse::*;
+use mir9cc::passes::{self, *};
+use mir9cc::preprocess::*;
+use mir9cc::regalloc::*;
+use mir9cc::sema::*;
+use mir9cc::token::*;
+use mir9cc::verify::*;
+use mir9cc::{CompileError, Context};
 
 #[allow(dead_code)]
 fn print_typename<T>(_: T) {
     println!("{}", std::any::type_name::<T>());
 }
 
+// Prints the diagnostics in source order and exits.
+fn print_errors(mut errors: Vec<CompileError>) -> ! {
+    errors.sort_by_key(|e| (e.program_id, e.pos));
+    let too_many = errors.len() >= MAX_ERRORS;
+    errors.truncate(MAX_ERRORS);
+    for e in &errors {
+        let source = PROGRAMS.lock().unwrap()[e.program_id].clone();
+        eprint!("{}", e.format(&source));
+    }
+    if too_many {
+        eprintln!("too many errors emitted, stopping now.");
+    }
+    std::process::exit(1);
+}
+
+fn usage() -> ! {
+    println!("Usage: mir9cc [--version] [-dump-ir1] [-dump-ir2] [-dump-liveness] [-MD] [-MF <file>]\n              [-Wunused] [-Wunused-variable] [-Wunused-parameter]\n              [-Wunreachable-code] [-Wuninitialized]\n              [-Wswitch] [-Wno-int-conversion] [-fstack-clash-protection]\n              [-fverbose-asm] [-fmerge-strings] [-fpic]\n              [--syntax=intel|att]\n              [-fregalloc=linear|iterative] [-fstats] [--verify-ir] [-O0|-O1|-O2]\n              [-j <jobs>] [-o <file>]\n              [--run] <file> [-- <link args>]");
+    std::process::exit(1);
+}
+
+// Runs the IR verifier on every function and exits if one is broken.
+fn verify_ir(program: &Program, allocated: bool) {
+    let mut broken = false;
+    for fun in &program.funs {
+        if let Err(errors) = verify(fun, allocated) {
+            for e in errors {
+                eprintln!("invalid IR: {}", e);
+            }
+            broken = true;
+        }
+    }
+    if broken {
+        std::process::exit(1);
+    }
+}
+
+// Runs the backend on one function, the `index`th of the program, and
+// returns its assembly. The IR of a function lives in `Rc`s, so each
+// thread starts from the function's syntax tree.
+fn compile_function(node: Node, index: usize, level: u32, verify: bool) -> (String, Stats) {
+    let mut program = Program::new();
+    program.nodes.push(node);
+    let mut stats = Stats::default();
+    gen_ir(&mut program);
+    passes::run(IR_PASSES, level, &mut program, &mut stats);
+    if verify {
+        verify_ir(&program, false);
+    }
+    to_two_address(&mut program);
+    passes::run(TWO_ADDRESS_PASSES, level, &mut program, &mut stats);
+    stats.slots = alloc_regs(&mut program);
+    passes::run(ALLOCATED_PASSES, level, &mut program, &mut stats);
+    if verify {
+        verify_ir(&program, true);
+    }
+    (gen_fun(&mut program.funs[0], index), stats)
+}
+
+// Compiles the functions on `jobs` threads. The assembly of each is
+// collected separately and concatenated in source order, so the output
+// is the same as a sequential compilation's.
+fn compile_parallel(program: Program, jobs: usize, level: u32, verify: bool) -> (String, Stats) {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .unwrap();
+    let nodes = program.nodes;
+    let funs: Vec<(String, Stats)> = pool.install(|| {
+        nodes
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, node)| compile_function(node, i, level, verify))
+            .collect()
+    });
+    let mut asm = gen_data(program.gvars);
+    let mut stats = Stats::default();
+    for (fun, fun_stats) in funs {
+        asm.push_str(&fun);
+        stats.add(fun_stats);
+    }
+    (asm, stats)
+}
+
+// Prints the version, and the kind of build and the platform it was built
+// for, which bug reports should include.
+fn version() -> ! {
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    println!(
+        "mir9cc {} ({}, {}-{})",
+        env!("CARGO_PKG_VERSION"),
+        profile,
+        env::consts::ARCH,
+        env::consts::OS
+    );
+    std::process::exit(0);
+}
+
+// Writes the assembly with `write` to the file given with -o, or to
+// stdout, and exits if that fails.
+fn write_asm(out_path: Option<&str>, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+    let result = match out_path {
+        Some(path) => fs::File::create(path).and_then(|file| write(&mut BufWriter::new(file))),
+        None => write(&mut BufWriter::new(io::stdout().lock())),
+    };
+    if let Err(e) = result {
+        eprintln!("cannot write {}: {}", out_path.unwrap_or("<stdout>"), e);
+        std::process::exit(1);
+    }
+}
+
+// --run: assembles and links the assembly with $MIR9CC_CC, or cc, passing
+// `link_args` after it, then runs the program and exits with its status.
+// The assembly is kept if it was written to the file given with -o.
+fn run_program(
+    out_path: Option<&str>,
+    link_args: &[String],
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> ! {
+    let tmp = env::temp_dir().join(format!("mir9cc-run-{}", std::process::id()));
+    let tmp_asm = tmp.with_extension("s");
+    let asm_path = out_path.map_or(tmp_asm.as_path(), Path::new);
+    write_asm(Some(&asm_path.to_string_lossy()), write);
+    let cleanup = || {
+        let _ = fs::remove_file(&tmp_asm);
+        let _ = fs::remove_file(&tmp);
+    };
+
+    // code built without -fPIC has absolute addresses, which a PIE can't hold
+    let cc = env::var("MIR9CC_CC").unwrap_or(String::from("cc"));
+    let mut cmd = Command::new(&cc);
+    if !*PIC.lock().unwrap() {
+        cmd.arg("-no-pie");
+    }
+    let status = cmd
+        .arg("-o")
+        .arg(&tmp)
+        .arg(asm_path)
+        .args(link_args)
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            cleanup();
+            eprintln!("{} failed to link the program: {}", cc, status);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            cleanup();
+            eprintln!("cannot run {}: {}", cc, e);
+            std::process::exit(1);
+        }
+    }
+
+    // the program shares our stdin, stdout and stderr
+    let status = Command::new(&tmp).status();
+    cleanup();
+    match status {
+        // like a shell, a program killed by a signal exits with 128 + it
+        Ok(status) => std::process::exit(
+            status
+                .code()
+                .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+        ),
+        Err(e) => {
+            eprintln!("cannot run the program: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Writes a Make-compatible rule listing the headers included by `path`
+// so that build systems can rebuild it when one of them changes.
+fn write_dep_file(path: &str, dep_path: Option<String>) {
+    let stem = Path::new(path).file_stem().unwrap().to_string_lossy();
+    let dep_path = dep_path.unwrap_or(format!("{}.d", stem));
+    let mut rule = format!("{}.o: {}", stem, path);
+    for header in included_paths() {
+        rule.push_str(&format!(" {}", header));
+    }
+    rule.push('\n');
+    if let Err(e) = fs::write(&dep_path, rule) {
+        eprintln!("cannot write {}: {}", dep_path, e);
+        std::process::exit(1);
+    }
+}
+
 fn main() {
-    let mut args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().collect();
 
     let mut dump_ir1 = false;
     let mut dump_ir2 = false;
+    let mut dump_live = false;
+    let mut verify = cfg!(debug_assertions);
+    let mut stats = false;
+    let mut level = 0;
+    let mut dep_file = false;
+    let mut dep_path = None;
+    let mut jobs = 1;
+    let mut out_path = None;
+    let mut run = false;
+    let mut link_args = vec![];
+    let mut path = None;
 
-    if args.len() == 4 && args[1] == "-dump-ir1" && args[2] == "-dump-ir2" {
-        dump_ir1 = true;
-        dump_ir2 = true;
-    } else if args.len() == 3 && args[1] == "-dump-ir1" {
-        dump_ir1 = true;
-    } else if args.len() == 3 {
-        dump_ir2 = true;
-    } else if args.len() == 2 {
-    } else {
-        println!("Usage: mir9cc [-dump-ir1] [-dump-ir2] <file>");
-        std::process::exit(1);
+    let mut i = 1;
+    while i < args.len() {
+        match &args[i][..] {
+            "-dump-ir1" => {
+                dump_ir1 = true;
+            }
+            "-dump-ir2" => {
+                dump_ir2 = true;
+            }
+            "-dump-liveness" => {
+                dump_live = true;
+            }
+            "-O" => {
+                level = 1;
+            }
+            arg if arg.starts_with("-O") => {
+                level = match arg[2..].parse::<u32>() {
+                    Ok(n) => n.min(MAX_LEVEL),
+                    Err(_) => usage(),
+                };
+            }
+            "--version" => {
+                version();
+            }
+            "-fstats" => {
+                stats = true;
+            }
+            "--verify-ir" => {
+                verify = true;
+            }
+            "-MD" => {
+                dep_file = true;
+            }
+            // like gcc, -Wunused does not cover parameters.
+            "-Wunused" | "-Wunused-variable" => {
+                *WARN_UNUSED_VARIABLE.lock().unwrap() = true;
+            }
+            "-Wunused-parameter" => {
+                *WARN_UNUSED_PARAMETER.lock().unwrap() = true;
+            }
+            "-Wuninitialized" => {
+                *WARN_UNINITIALIZED.lock().unwrap() = true;
+            }
+            "-Wswitch" => {
+                *WARN_SWITCH.lock().unwrap() = true;
+            }
+            "-Wint-conversion" => {
+                *WARN_INT_CONVERSION.lock().unwrap() = true;
+            }
+            "-Wno-int-conversion" => {
+                *WARN_INT_CONVERSION.lock().unwrap() = false;
+            }
+            "-fstack-clash-protection" => {
+                *STACK_PROBE.lock().unwrap() = true;
+            }
+            "-fverbose-asm" => {
+                *VERBOSE_ASM.lock().unwrap() = true;
+            }
+            "-fregalloc=iterative" => {
+                *ITERATIVE_REGALLOC.lock().unwrap() = true;
+            }
+            "-fregalloc=linear" => {
+                *ITERATIVE_REGALLOC.lock().unwrap() = false;
+            }
+            "-fpic" | "-fPIC" | "-fpie" | "-fPIE" => {
+                *PIC.lock().unwrap() = true;
+            }
+            "-fno-pic" | "-fno-PIC" | "-fno-pie" | "-fno-PIE" => {
+                *PIC.lock().unwrap() = false;
+            }
+            "--syntax=att" => {
+                *ATT_SYNTAX.lock().unwrap() = true;
+            }
+            "--syntax=intel" => {
+                *ATT_SYNTAX.lock().unwrap() = false;
+            }
+            "-fmerge-strings" => {
+                *MERGE_STRINGS.lock().unwrap() = true;
+            }
+            "-Wunreachable-code" => {
+                *WARN_UNREACHABLE_CODE.lock().unwrap() = true;
+            }
+            "-MF" => {
+                if i + 1 == args.len() {
+                    usage();
+                }
+                i += 1;
+                dep_file = true;
+                dep_path = Some(args[i].clone());
+            }
+            "--run" => {
+                run = true;
+            }
+            // the rest is for the linker
+            "--" => {
+                link_args = args[i + 1..].to_vec();
+                break;
+            }
+            "-o" => {
+                if i + 1 == args.len() {
+                    usage();
+                }
+                i += 1;
+                out_path = Some(args[i].clone());
+            }
+            "-j" | "--jobs" => {
+                if i + 1 == args.len() {
+                    usage();
+                }
+                i += 1;
+                jobs = args[i].parse().unwrap_or_else(|_| usage());
+            }
+            arg if arg.starts_with("-j") => {
+                jobs = arg[2..].parse().unwrap_or_else(|_| usage());
+            }
+            arg => {
+                if path.is_some() || arg.starts_with('-') {
+                    usage();
+                }
+                path = Some(arg.to_string());
+            }
+        }
+        i += 1;
     }
 
-    add_program(args.pop().unwrap());
+    let path = match path {
+        Some(path) => path,
+        None => usage(),
+    };
+    if !run && !link_args.is_empty() {
+        usage();
+    }
+    if let Err(e) = add_program(path.clone(), None) {
+        eprintln!("cannot open {}: {}", path, e);
+        std::process::exit(1);
+    }
 
     // lexical analysis
-    let tokens = tokenize(0, true);
-    let mut tokenset = TokenSet::new(tokens);
+    let tokens = match tokenize(0, true) {
+        Ok(tokens) => tokens,
+        Err(e) => print_errors(vec![e]),
+    };
+    if dep_file {
+        write_dep_file(&path, dep_path);
+    }
+    let mut ctx = Context::new();
+    let mut tokenset = TokenSet::new(tokens, &mut ctx);
     // let mut i = 0;
     // for token in &tokens {
     // 	println!("{:?}", token);
@@ -64,16 +390,53 @@ fn main() {
     // }
     let mut program = Program::new();
     // parsing analysis
-    parse(&mut tokenset, &mut program);
+    let mut errors = vec![];
+    if let Err(mut e) = parse(&mut tokenset, &mut program) {
+        errors.append(&mut e);
+    }
     // println!("{:#?}", &program.nodes);
-    sema(&mut program);
+    // semantic analysis still runs after syntax errors so that both
+    // kinds are reported together.
+    if errors.len() < MAX_ERRORS {
+        if let Err(mut e) = sema(&mut program, tokenset.ctx) {
+            errors.append(&mut e);
+        }
+    }
+    if !errors.is_empty() {
+        print_errors(errors);
+    }
     // println!("{:#?}", &program.nodes);
 
+    passes::run(AST_PASSES, level, &mut program, &mut Stats::default());
+
+    // the dumps print the functions together, so they are compiled
+    // one after another
+    if jobs > 1 && !dump_ir1 && !dump_ir2 && !dump_live {
+        let (asm, pass_stats) = compile_parallel(program, jobs, level, verify);
+        if stats {
+            pass_stats.print();
+        }
+        let write = |out: &mut dyn Write| {
+            out.write_all(asm.as_bytes())?;
+            out.flush()
+        };
+        if run {
+            run_program(out_path.as_deref(), &link_args, write);
+        }
+        write_asm(out_path.as_deref(), write);
+        return;
+    }
+
     // alloc index for register
     gen_ir(&mut program);
+    let mut pass_stats = Stats::default();
+    passes::run(IR_PASSES, level, &mut program, &mut pass_stats);
     if dump_ir1 {
         dump_ir(&program.funs, "-dump-ir1");
     }
+    if verify {
+        verify_ir(&program, false);
+    }
     // for func in &program.funs {
     // 	for bb in &func.bbs {
     // 		for ir in &bb.borrow().irs {
@@ -81,10 +444,22 @@ fn main() {
     // 		}
     // 	}
     // }
-    alloc_regs(&mut program);
+    to_two_address(&mut program);
+    passes::run(TWO_ADDRESS_PASSES, level, &mut program, &mut pass_stats);
+    if dump_live {
+        dump_liveness(&program.funs);
+    }
+    pass_stats.slots = alloc_regs(&mut program);
+    passes::run(ALLOCATED_PASSES, level, &mut program, &mut pass_stats);
+    if stats {
+        pass_stats.print();
+    }
     if dump_ir2 {
         dump_ir(&program.funs, "-dump-ir2");
     }
+    if verify {
+        verify_ir(&program, true);
+    }
     // for func in &program.funs {
     // 	for bb in &func.bbs{
     // 		for ir in &bb.borrow().irs {
@@ -94,5 +469,9 @@ fn main() {
     // }
 
     // code generator
-    gen_x86(program);
+    let write = |out: &mut dyn Write| gen_x86(program, out);
+    if run {
+        run_program(out_path.as_deref(), &link_args, write);
+    }
+    write_asm(out_path.as_deref(), write);
 }
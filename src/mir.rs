@@ -1,19 +1,22 @@
+use super::gen_ir::IrOp::*;
 use super::gen_ir::*;
 use super::parse::*;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-fn new_regno() -> i32 {
-    *REGNO.lock().unwrap() += 1;
-    return *REGNO.lock().unwrap();
-}
-
 pub struct Program {
     pub gvars: Vec<Var>,
     pub nodes: Vec<Node>,
     pub funs: Vec<Function>,
 }
 
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Program {
     pub fn new() -> Self {
         Self {
@@ -24,36 +27,48 @@ impl Program {
     }
 }
 
+// Blocks are identified by their label.
+pub type BBId = i32;
+
 #[derive(Debug)]
 pub struct BB {
-    pub label: i32,
+    pub label: BBId,
     pub irs: Vec<Ir>,
     pub param: Reg,
-    pub passed: bool,
+    // blocks control can pass to from this one, and come from.
+    pub succ: Vec<BBId>,
+    pub pred: Vec<BBId>,
 }
 
 impl BB {
-    fn new() -> Self {
-        Self {
-            label: new_label(),
-            irs: vec![],
-            param: Reg::dummy(),
-            passed: false,
-        }
-    }
-    fn new_param() -> Self {
+    fn new(label: BBId, param: Reg) -> Self {
         Self {
-            label: new_label(),
+            label,
             irs: vec![],
-            param: Reg::new(),
-            passed: false,
+            param,
+            succ: vec![],
+            pred: vec![],
         }
     }
-    pub fn new_rc() -> Rc<RefCell<BB>> {
-        Rc::new(RefCell::new(BB::new()))
+    // Whether the block ends with a jump or a return rather than
+    // falling through to the next block.
+    pub fn is_terminated(&self) -> bool {
+        self.irs
+            .iter()
+            .any(|ir| matches!(ir.op, IrJmp | IrBr | IrBrCmp(_) | IrRet))
     }
-    pub fn new_param_rc() -> Rc<RefCell<BB>> {
-        Rc::new(RefCell::new(BB::new_param()))
+    // Adds the edge `from` -> `to`.
+    pub fn link(from: &Rc<RefCell<BB>>, to: &Rc<RefCell<BB>>) {
+        let (from_id, to_id) = (from.borrow().label, to.borrow().label);
+        let mut from = from.borrow_mut();
+        if !from.succ.contains(&to_id) {
+            from.succ.push(to_id);
+        }
+        drop(from);
+        let mut to = to.borrow_mut();
+        if !to.pred.contains(&from_id) {
+            to.pred.push(from_id);
+        }
     }
 }
 
@@ -66,9 +81,10 @@ pub struct Reg {
 }
 
 impl Reg {
-    pub fn new() -> Self {
+    // The virtual register numbered `vn`; Function::new_reg gives new ones.
+    pub fn new(vn: i32) -> Self {
         Self {
-            vn: new_regno(),
+            vn,
             rn: -1,
             spill: false,
             spill_offset: -1,
@@ -82,6 +98,16 @@ impl Reg {
             spill_offset: -1,
         }
     }
+    // A spilled call argument, which is passed straight from its stack
+    // slot and needs no register.
+    pub fn stack(spill_offset: i32) -> Self {
+        Self {
+            vn: -1,
+            rn: -1,
+            spill: true,
+            spill_offset,
+        }
+    }
     pub fn active(&self) -> bool {
         return self.vn > 0;
     }
@@ -98,10 +124,133 @@ pub struct RegLife {
     pub vn: i32,
     pub start: i32,
     pub end: i32,
+    // numbers of the instructions reading or writing it, in order
+    pub uses: Vec<i32>,
+    // live before and after some call, which clobbers the caller-saved
+    // registers
+    pub crosses_call: bool,
 }
 
 impl RegLife {
-    pub fn new(vn: i32, start: i32, end: i32) -> Self {
-        Self { vn, start, end }
+    pub fn new(vn: i32, start: i32, end: i32, uses: Vec<i32>) -> Self {
+        Self {
+            vn,
+            start,
+            end,
+            uses,
+            crosses_call: false,
+        }
+    }
+}
+
+impl Function {
+    // A new virtual register of the function.
+    pub fn new_reg(&self) -> Reg {
+        self.regno.set(self.regno.get() + 1);
+        Reg::new(self.regno.get())
+    }
+    // A new block of the function, and one taking a value from the
+    // jumps to it in its parameter.
+    pub fn new_bb(&self) -> Rc<RefCell<BB>> {
+        self.label.set(self.label.get() + 1);
+        Rc::new(RefCell::new(BB::new(self.label.get(), Reg::dummy())))
+    }
+    pub fn new_param_bb(&self) -> Rc<RefCell<BB>> {
+        self.label.set(self.label.get() + 1);
+        let param = self.new_reg();
+        Rc::new(RefCell::new(BB::new(self.label.get(), param)))
+    }
+    // Registers are numbered per function, so a pass adding registers
+    // to a function after gen_ir continues from the highest number it
+    // uses.
+    pub fn resume_regno(&self) {
+        let mut max = 0;
+        for bb in &self.bbs {
+            let bb = bb.borrow();
+            max = max.max(bb.param.vn);
+            for ir in &bb.irs {
+                max = ir
+                    .defs()
+                    .into_iter()
+                    .chain(ir.uses())
+                    .fold(max, |max, r| max.max(r.vn));
+            }
+        }
+        self.regno.set(max);
+    }
+    pub fn bb(&self, id: BBId) -> Rc<RefCell<BB>> {
+        let bb = self.bbs.iter().find(|bb| bb.borrow().label == id);
+        Rc::clone(bb.unwrap())
+    }
+    pub fn unlink(&self, from: BBId, to: BBId) {
+        self.bb(from).borrow_mut().succ.retain(|id| *id != to);
+        self.bb(to).borrow_mut().pred.retain(|id| *id != from);
+    }
+    // Successors of the i-th block according to its instructions: the
+    // targets of its jumps, and the next block if it falls through.
+    pub fn successors(&self, i: usize) -> Vec<BBId> {
+        let bb = self.bbs[i].borrow();
+        let mut succs = vec![];
+        for ir in &bb.irs {
+            for target in ir.bb1.iter().chain(ir.bb2.iter()) {
+                let id = target.borrow().label;
+                if !succs.contains(&id) {
+                    succs.push(id);
+                }
+            }
+        }
+        if !bb.is_terminated() && i + 1 < self.bbs.len() {
+            succs.push(self.bbs[i + 1].borrow().label);
+        }
+        succs
+    }
+    // Refills the edges from the instructions, for passes which
+    // rearrange blocks too much to update them one by one.
+    pub fn compute_cfg(&self) {
+        for bb in &self.bbs {
+            let mut bb = bb.borrow_mut();
+            bb.succ.clear();
+            bb.pred.clear();
+        }
+        for i in 0..self.bbs.len() {
+            let from = self.bbs[i].borrow().label;
+            for to in self.successors(i) {
+                self.bbs[i].borrow_mut().succ.push(to);
+                self.bb(to).borrow_mut().pred.push(from);
+            }
+        }
+    }
+    // The blocks reachable from the entry block in reverse postorder,
+    // so that a block comes before its successors except along back
+    // edges.
+    pub fn rpo(&self) -> impl Iterator<Item = Rc<RefCell<BB>>> {
+        let index: HashMap<BBId, usize> = self
+            .bbs
+            .iter()
+            .enumerate()
+            .map(|(i, bb)| (bb.borrow().label, i))
+            .collect();
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        // (block, number of successors visited)
+        let mut stack = vec![];
+        if !self.bbs.is_empty() {
+            visited.insert(0);
+            stack.push((0, 0));
+        }
+        while let Some((i, n)) = stack.pop() {
+            let succ = self.bbs[i].borrow().succ.get(n).copied();
+            match succ {
+                Some(id) => {
+                    stack.push((i, n + 1));
+                    let j = index[&id];
+                    if visited.insert(j) {
+                        stack.push((j, 0));
+                    }
+                }
+                None => order.push(Rc::clone(&self.bbs[i])),
+            }
+        }
+        order.into_iter().rev()
     }
 }
@@ -1,8 +1,9 @@
-use super::token::TokenType::*;
-use super::token::*;
-// use super::lib::*;
+use super::intern::Interner;
 use super::mir::*;
 use super::sema::*;
+use super::token::TokenType::*;
+use super::token::*;
+use super::*;
 
 use linked_hash_map::LinkedHashMap;
 use std::collections::HashMap;
@@ -18,28 +19,30 @@ use std::sync::Mutex;
 // Types are added to variables and literals. For other nodes, Sema
 // will add type for them.
 //
+// On a syntax error, the parser records it and skips to the end of the
+// statement (or toplevel declaration) so that several errors can be
+// reported in one run.
+//
 // Semantic checking is omitted from this parser to make the code in
 // this file closely resemble the C's BNF. Invalid expressions, such
 // as `1+2=3`, are accepted at this stage. Such errors are detected in
 // a later pass.
 
+// Parsing stops after this many errors.
+pub const MAX_ERRORS: usize = 20;
+
 macro_rules! env_find {
-    ($s:expr, $m:ident, $null:expr) => {{
-        let mut target = $null;
-        if let Some(t) = ENV.lock().unwrap().$m.get(&$s) {
-            target = t.clone()
-        }
-        if target == $null {
-            let mut env = &ENV.lock().unwrap().next;
-            while let Some(e) = env {
-                if let Some(t) = e.$m.get(&$s) {
-                    target = t.clone();
-                    break;
-                }
-                env = &e.next;
+    ($ctx:expr, $sym:expr, $m:ident, $null:expr) => {{
+        let mut target = None;
+        let mut env = Some(&$ctx.env);
+        while let Some(e) = env {
+            if let Some(t) = e.$m.get(&$sym) {
+                target = Some(t.clone());
+                break;
             }
+            env = e.next.as_deref();
         }
-        target
+        target.unwrap_or_else(|| $null)
     }};
 }
 
@@ -52,6 +55,7 @@ lazy_static! {
         align: 4,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref CHAR_TY: Type = Type {
         ty: Ty::CHAR,
@@ -61,6 +65,7 @@ lazy_static! {
         align: 1,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref VOID_TY: Type = Type {
         ty: Ty::VOID,
@@ -70,6 +75,7 @@ lazy_static! {
         align: 0,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref NULL_TY: Type = Type {
         ty: Ty::NULL,
@@ -79,6 +85,7 @@ lazy_static! {
         align: 0,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref STRUCT_TY: Type = Type {
         ty: Ty::STRUCT(String::new(), LinkedHashMap::new()),
@@ -88,6 +95,7 @@ lazy_static! {
         align: 0,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref BOOL_TY: Type = Type {
         ty: Ty::BOOL,
@@ -97,6 +105,7 @@ lazy_static! {
         align: 1,
         offset: 0,
         len: 0,
+        is_volatile: false,
     };
     pub static ref NULL_VAR: Var = Var {
         ctype: NULL_TY.clone(),
@@ -105,14 +114,15 @@ lazy_static! {
         labelname: None,
         strname: None,
         init: None,
+        is_register: false,
     };
-    pub static ref ENV: Mutex<Env> = Mutex::new(Env::new_env(None));
-    pub static ref GVARS: Mutex<Vec<Var>> = Mutex::new(vec![]);
-    pub static ref LVARS: Mutex<LinkedHashMap<String, Var>> = Mutex::new(LinkedHashMap::new());
-    pub static ref LABEL: Mutex<i32> = Mutex::new(0);
-    pub static ref SWITCHES: Mutex<Vec<Vec<Node>>> = Mutex::new(vec![]);
-    pub static ref STACKSIZE: Mutex<i32> = Mutex::new(0);
-    pub static ref ARRINI: Mutex<Var> = Mutex::new(NULL_VAR.clone());
+    pub static ref WARN_UNUSED_VARIABLE: Mutex<bool> = Mutex::new(false);
+    pub static ref WARN_UNUSED_PARAMETER: Mutex<bool> = Mutex::new(false);
+    pub static ref WARN_UNREACHABLE_CODE: Mutex<bool> = Mutex::new(false);
+    pub static ref WARN_UNINITIALIZED: Mutex<bool> = Mutex::new(false);
+    pub static ref WARN_SWITCH: Mutex<bool> = Mutex::new(false);
+    // on by default, as in gcc and clang
+    pub static ref WARN_INT_CONVERSION: Mutex<bool> = Mutex::new(true);
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -124,6 +134,57 @@ pub struct Type {
     pub align: i32,
     pub offset: i32,
     pub len: i32,
+    // loads and stores through a volatile type must never be removed.
+    pub is_volatile: bool,
+}
+
+// Prints a type in C syntax, e.g. `int *`, `char *[3]`, `int (*)[2]` or
+// `int *(*)()`.
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut decl = String::new();
+        let mut ty = self;
+        loop {
+            match &ty.ty {
+                Ty::PTR => {
+                    decl = format!("*{}", decl);
+                    ty = ty.ptr_to.as_ref().unwrap();
+                }
+                Ty::ARY => {
+                    if decl.starts_with('*') {
+                        decl = format!("({})", decl);
+                    }
+                    decl = format!("{}[{}]", decl, ty.len);
+                    ty = ty.ary_to.as_ref().unwrap();
+                }
+                Ty::FUNC => {
+                    if decl.starts_with('*') {
+                        decl = format!("({})", decl);
+                    }
+                    decl = format!("{}()", decl);
+                    ty = ty.ptr_to.as_ref().unwrap();
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        let base = match &ty.ty {
+            Ty::INT => String::from("int"),
+            Ty::CHAR => String::from("char"),
+            Ty::VOID => String::from("void"),
+            Ty::BOOL => String::from("_Bool"),
+            Ty::STRUCT(tag, _) if tag.is_empty() => String::from("struct <anonymous>"),
+            Ty::STRUCT(tag, _) => format!("struct {}", tag),
+            Ty::ENUM(tag, _) if tag.is_empty() => String::from("enum <anonymous>"),
+            Ty::ENUM(tag, _) => format!("enum {}", tag),
+            _ => String::from("<unknown>"),
+        };
+        if decl.is_empty() {
+            return write!(f, "{}", base);
+        }
+        write!(f, "{} {}", base, decl)
+    }
 }
 
 impl Type {
@@ -144,6 +205,7 @@ impl Type {
             align,
             offset,
             len,
+            is_volatile: false,
         }
     }
     pub fn ptr_to(self) -> Self {
@@ -155,6 +217,21 @@ impl Type {
             align: 8,
             offset: 0,
             len: 0,
+            is_volatile: false,
+        }
+    }
+    // The size of a function is 1 as in GCC, for `sizeof` and pointer
+    // arithmetic on function pointers.
+    pub fn func_returning(self) -> Self {
+        Self {
+            ty: Ty::FUNC,
+            ptr_to: Some(Box::new(self)),
+            ary_to: None,
+            size: 1,
+            align: 1,
+            offset: 0,
+            len: 0,
+            is_volatile: false,
         }
     }
     pub fn ary_of(self, len: i32) -> Self {
@@ -164,11 +241,21 @@ impl Type {
             ty: Ty::ARY,
             ptr_to: None,
             ary_to: Some(Box::new(self)),
-            size: size * len,
+            size: size.saturating_mul(len),
             align,
             offset: 0,
             len,
+            is_volatile: false,
+        }
+    }
+    // Returns the number of bytes a pointer of this type advances by
+    // on `p + 1`. As a GNU extension, `void *` advances by one byte.
+    pub fn stride(&self) -> i32 {
+        let ptr_to = self.ptr_to.as_ref().unwrap();
+        if let Ty::VOID = ptr_to.ty {
+            return 1;
         }
+        ptr_to.size
     }
 }
 
@@ -179,8 +266,10 @@ pub enum Ty {
     ARY,
     CHAR,
     STRUCT(String, LinkedHashMap<String, Type>),
+    ENUM(String, Vec<(String, i32)>), // ENUM(tag, enumerators)
     VOID,
     BOOL,
+    FUNC, // FUNC returns the type in ptr_to
     NULL,
 }
 
@@ -192,11 +281,12 @@ impl PartialEq for Ty {
             | (Ty::ARY, Ty::ARY)
             | (Ty::CHAR, Ty::CHAR)
             | (Ty::VOID, Ty::VOID)
+            | (Ty::FUNC, Ty::FUNC)
             | (Ty::NULL, Ty::NULL)
             | (Ty::BOOL, Ty::BOOL) => {
                 return true;
             }
-            (Ty::STRUCT(tag1, _), Ty::STRUCT(tag2, _)) => {
+            (Ty::STRUCT(tag1, _), Ty::STRUCT(tag2, _)) | (Ty::ENUM(tag1, _), Ty::ENUM(tag2, _)) => {
                 if tag1 == tag2 {
                     return true;
                 } else {
@@ -223,9 +313,10 @@ pub enum NodeType {
     Assign(Type, Box<Node>, Box<Node>),                // Assign(ctype, lhs, rhs)
     IfThen(Box<Node>, Box<Node>, Option<Box<Node>>),   // IfThen(cond, then, elthen)
     Call(Type, String, Vec<Node>),                     // Call(ctype, ident, args)
+    CallPtr(Type, Box<Node>, Vec<Node>),               // CallPtr(ctype, fptr, args)
     Func(Type, String, Vec<Var>, Box<Node>, i32),      // Func(ctype, ident, args, body, stacksize)
     For(Box<Node>, Box<Node>, Box<Node>, Box<Node>),   // For(init, cond, inc, body)
-    VarDef(String, Var, Option<Box<Node>>),            // VarDef(name, var, init)
+    VarDef(intern::Symbol, Var, Option<Box<Node>>),    // VarDef(name, var, init)
     Deref(Type, Box<Node>),                            // Deref(ctype, lhs)
     Addr(Type, Box<Node>),                             // Addr(ctype, lhs)
     Equal(Box<Node>, Box<Node>),                       // Equal(lhs, rhs)
@@ -241,15 +332,17 @@ pub enum NodeType {
     Break,                                             // Break,
     Continue,                                          // Continue,
     Cast(Type, Box<Node>),                             // Cast(ctype, expr),
-    Switch(Box<Node>, Box<Node>, Vec<Node>),           // Switch(cond, body, case_conds),
-    Case(Box<Node>, Box<Node>),                        // Case(val, body),
-    ArrIni(Vec<(Node, Node)>),                         // ArrIni(arrini),
-    NULL,                                              // NULL,
+    Switch(Box<Node>, Box<Node>, Vec<Node>, bool), // Switch(cond, body, case_conds, has_default),
+    Case(Box<Node>, Box<Node>),                    // Case(val, body),
+    Default(Box<Node>),                            // Default(body),
+    ArrIni(Vec<(Node, Node)>),                     // ArrIni(arrini),
+    NULL,                                          // NULL,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     pub op: NodeType,
+    pub token: Option<Token>, // where the node was parsed from
 }
 
 #[allow(dead_code)]
@@ -262,7 +355,12 @@ impl Node {
             | NodeType::Dot(ctype, ..)
             | NodeType::Ternary(ctype, ..)
             | NodeType::IncDec(ctype, ..)
-            | NodeType::Assign(ctype, ..) => {
+            | NodeType::Call(ctype, ..)
+            | NodeType::CallPtr(ctype, ..)
+            | NodeType::Assign(ctype, ..)
+            | NodeType::TupleExpr(ctype, ..)
+            | NodeType::StmtExpr(ctype, ..)
+            | NodeType::Cast(ctype, ..) => {
                 return ctype.clone();
             }
             NodeType::VarRef(var) | NodeType::VarDef(_, var, ..) => {
@@ -271,9 +369,7 @@ impl Node {
             NodeType::Num(_) => {
                 return INT_TY.clone();
             }
-            NodeType::Equal(lhs, ..) => {
-                return lhs.nodesctype(None);
-            }
+            NodeType::Equal(..) | NodeType::Ne(..) | NodeType::Not(..) => INT_TY.clone(),
             _ => {
                 if let Some(ty) = basetype {
                     return ty;
@@ -284,50 +380,79 @@ impl Node {
         }
     }
 
-    pub fn checklval(&self) {
+    pub fn checklval(&self) -> Result<(), CompileError> {
         match &self.op {
-            NodeType::VarRef(..) | NodeType::Deref(..) | NodeType::Dot(..) => {}
-            _ => {
-                // error("not an lvalue");
-                // for debug.
-                panic!("not an lvalue");
-            }
+            NodeType::VarRef(..) | NodeType::Deref(..) | NodeType::Dot(..) => Ok(()),
+            _ => Err(self.error(String::from("not an lvalue"))),
+        }
+    }
+    // Attaches the token the node was parsed from unless it already has one.
+    pub fn with_token(mut self, token: &Token) -> Self {
+        if self.token.is_none() {
+            self.token = Some(token.clone());
+        }
+        self
+    }
+    // Takes over the position of another node unless it already has one.
+    pub fn with_token_of(mut self, node: &Node) -> Self {
+        if self.token.is_none() {
+            self.token = node.token.clone();
+        }
+        self
+    }
+    pub fn warn(&self, msg: &str) {
+        if let Some(token) = &self.token {
+            token.warn(msg);
+        }
+    }
+    // Returns an error pointing at the node.
+    pub fn error(&self, msg: String) -> CompileError {
+        match &self.token {
+            Some(token) => token.error(msg),
+            None => CompileError::new(0, 0, 0, msg),
         }
     }
 
     pub fn new_num(val: i32) -> Self {
         Self {
             op: NodeType::Num(val),
+            token: None,
         }
     }
     pub fn new_bit(ctype: Type, tk_ty: TokenType, lhs: Node, rhs: Node) -> Self {
         Self {
             op: NodeType::BinaryTree(ctype, tk_ty, Box::new(lhs), Box::new(rhs)),
+            token: None,
         }
     }
     pub fn new_ret(lhs: Node) -> Self {
         Self {
             op: NodeType::Ret(Box::new(lhs)),
+            token: None,
         }
     }
     pub fn new_expr(lhs: Node) -> Self {
         Self {
             op: NodeType::Expr(Box::new(lhs)),
+            token: None,
         }
     }
     pub fn new_stmt(stmts: Vec<Node>) -> Self {
         Self {
             op: NodeType::CompStmt(stmts),
+            token: None,
         }
     }
     pub fn new_ident(ident: String) -> Self {
         Self {
             op: NodeType::Ident(ident),
+            token: None,
         }
     }
     pub fn new_assign(ctype: Type, lhs: Node, rhs: Node) -> Self {
         Self {
             op: NodeType::Assign(ctype, Box::new(lhs), Box::new(rhs)),
+            token: None,
         }
     }
     pub fn new_if(cond: Node, then: Node, elthen: Option<Node>) -> Self {
@@ -338,11 +463,19 @@ impl Node {
                 }
                 None => NodeType::IfThen(Box::new(cond), Box::new(then), None),
             },
+            token: None,
         }
     }
     pub fn new_call(ctype: Type, ident: String, args: Vec<Node>) -> Self {
         Self {
             op: NodeType::Call(ctype, ident, args),
+            token: None,
+        }
+    }
+    pub fn new_callptr(ctype: Type, fptr: Node, args: Vec<Node>) -> Self {
+        Self {
+            op: NodeType::CallPtr(ctype, Box::new(fptr), args),
+            token: None,
         }
     }
     pub fn new_func(
@@ -354,6 +487,7 @@ impl Node {
     ) -> Self {
         Self {
             op: NodeType::Func(ctype, ident, args, Box::new(body), stacksize),
+            token: None,
         }
     }
     pub fn new_for(init: Node, cond: Node, inc: Node, body: Node) -> Self {
@@ -364,113 +498,143 @@ impl Node {
                 Box::new(inc),
                 Box::new(body),
             ),
+            token: None,
         }
     }
-    pub fn new_vardef(name: String, var: Var, rhs: Option<Node>) -> Self {
+    pub fn new_vardef(name: intern::Symbol, var: Var, rhs: Option<Node>) -> Self {
         Self {
             op: match rhs {
                 Some(node) => NodeType::VarDef(name, var, Some(Box::new(node))),
                 _ => NodeType::VarDef(name, var, None),
             },
+            token: None,
         }
     }
     pub fn new_deref(ctype: Type, lhs: Node) -> Self {
         Self {
             op: NodeType::Deref(ctype, Box::new(lhs)),
+            token: None,
         }
     }
     pub fn new_addr(ctype: Type, lhs: Node) -> Self {
         Self {
             op: NodeType::Addr(ctype, Box::new(lhs)),
+            token: None,
         }
     }
     pub fn new_equal(lhs: Node, rhs: Node) -> Self {
         Self {
             op: NodeType::Equal(Box::new(lhs), Box::new(rhs)),
+            token: None,
         }
     }
     pub fn new_neq(lhs: Node, rhs: Node) -> Self {
         Self {
             op: NodeType::Ne(Box::new(lhs), Box::new(rhs)),
+            token: None,
         }
     }
     pub fn new_dowhile(body: Node, cond: Node) -> Self {
         Self {
             op: NodeType::DoWhile(Box::new(body), Box::new(cond)),
+            token: None,
         }
     }
     pub fn new_stmtexpr(ctype: Type, body: Node) -> Self {
         Self {
             op: NodeType::StmtExpr(ctype, Box::new(body)),
+            token: None,
         }
     }
     pub fn new_dot(ctype: Type, expr: Node, member: String) -> Self {
         Self {
             op: NodeType::Dot(ctype, Box::new(expr), member),
+            token: None,
         }
     }
     pub fn new_not(expr: Node) -> Self {
         Self {
             op: NodeType::Not(Box::new(expr)),
+            token: None,
         }
     }
     pub fn new_ternary(ctype: Type, cond: Node, then: Node, els: Node) -> Self {
         Self {
             op: NodeType::Ternary(ctype, Box::new(cond), Box::new(then), Box::new(els)),
+            token: None,
         }
     }
     pub fn new_tuple(ctype: Type, lhs: Node, rhs: Node) -> Self {
         Self {
             op: NodeType::TupleExpr(ctype, Box::new(lhs), Box::new(rhs)),
+            token: None,
         }
     }
     pub fn new_incdec(ctype: Type, selector: i32, expr: Node) -> Self {
         Self {
             op: NodeType::IncDec(ctype, selector, Box::new(expr)),
+            token: None,
         }
     }
     pub fn new_decl(ctype: Type, ident: String, args: Vec<Node>) -> Self {
         Self {
             op: NodeType::Decl(ctype, ident, args),
+            token: None,
         }
     }
     pub fn new_varref(var: Var) -> Self {
         Self {
             op: NodeType::VarRef(var),
+            token: None,
         }
     }
     pub fn new_break() -> Self {
         Self {
             op: NodeType::Break,
+            token: None,
         }
     }
     pub fn new_continue() -> Self {
         Self {
             op: NodeType::Continue,
+            token: None,
         }
     }
     pub fn new_cast(ctype: Type, expr: Node) -> Self {
         Self {
             op: NodeType::Cast(ctype, Box::new(expr)),
+            token: None,
         }
     }
-    pub fn new_switch(cond: Node, body: Node, case_conds: Vec<Node>) -> Self {
+    pub fn new_switch(cond: Node, body: Node, case_conds: Vec<Node>, has_default: bool) -> Self {
         Self {
-            op: NodeType::Switch(Box::new(cond), Box::new(body), case_conds),
+            op: NodeType::Switch(Box::new(cond), Box::new(body), case_conds, has_default),
+            token: None,
         }
     }
     pub fn new_case(val: Node, body: Node) -> Self {
         Self {
             op: NodeType::Case(Box::new(val), Box::new(body)),
+            token: None,
+        }
+    }
+    pub fn new_default(body: Node) -> Self {
+        Self {
+            op: NodeType::Default(Box::new(body)),
+            token: None,
         }
     }
     pub fn new_arrini(arrini: Vec<(Node, Node)>) -> Self {
         Self {
             op: NodeType::ArrIni(arrini),
+            token: None,
         }
     }
     pub fn new_null() -> Self {
-        Self { op: NodeType::NULL }
+        Self {
+            op: NodeType::NULL,
+            token: None,
+        }
     }
 }
 
@@ -482,6 +646,8 @@ pub struct Var {
     pub labelname: Option<String>,
     pub strname: Option<String>,
     pub init: Option<Vec<String>>,
+    // declared `register`, so its address shouldn't be taken.
+    pub is_register: bool,
 }
 
 impl Var {
@@ -500,23 +666,28 @@ impl Var {
             labelname,
             strname,
             init,
+            is_register: false,
         }
     }
-    fn calc_offset(&mut self) -> i32 {
-        let mut offset = *STACKSIZE.lock().unwrap();
-        offset = roundup(offset, self.ctype.align);
-        offset += self.ctype.size;
-        self.offset = offset;
-        return offset;
+    // offsets saturate at i32::MAX so that an oversized frame is
+    // reported by the function definition instead of overflowing.
+    fn calc_offset(&mut self, stacksize: i32) -> i32 {
+        let offset = stacksize as i64;
+        let align = self.ctype.align.max(1) as i64;
+        let offset = (offset + align - 1) / align * align + self.ctype.size as i64;
+        self.offset = offset.min(i32::MAX as i64) as i32;
+        self.offset
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Env {
-    tags: LinkedHashMap<String, Type>,
-    typedefs: LinkedHashMap<String, Type>,
-    enums: HashMap<String, i32>,
-    vars: LinkedHashMap<String, Var>,
+    tags: LinkedHashMap<intern::Symbol, Type>,
+    typedefs: LinkedHashMap<intern::Symbol, Type>,
+    enums: HashMap<intern::Symbol, (i32, Type)>,
+    vars: LinkedHashMap<intern::Symbol, Var>,
+    // locals and parameters of this scope not referenced yet.
+    unused: LinkedHashMap<intern::Symbol, (Option<Token>, bool)>,
     next: Option<Box<Env>>,
 }
 
@@ -527,67 +698,188 @@ impl Env {
             typedefs: LinkedHashMap::new(),
             enums: HashMap::new(),
             vars: LinkedHashMap::new(),
+            unused: LinkedHashMap::new(),
             next: match env {
                 Some(_env) => Some(Box::new(_env)),
                 None => None,
             },
         }
     }
-    fn env_inc() {
-        let env = std::mem::replace(&mut *ENV.lock().unwrap(), Env::new_env(None));
-        *ENV.lock().unwrap() = Env::new_env(Some(env));
+    fn env_inc(ctx: &mut Context) {
+        let env = std::mem::take(&mut ctx.env);
+        ctx.env = Env::new_env(Some(env));
+    }
+    fn env_dec(ctx: &mut Context) {
+        let env = std::mem::take(&mut ctx.env);
+        env.warn_unused(&ctx.names);
+        ctx.env = *env.next.unwrap();
+    }
+    // Returns the number of scopes, the global one included.
+    fn depth(ctx: &Context) -> usize {
+        let mut depth = 1;
+        let mut env = &ctx.env;
+        while let Some(next) = &env.next {
+            depth += 1;
+            env = next;
+        }
+        depth
+    }
+    // Drops the innermost scopes until `depth` are left. Unused variables
+    // are not reported since the scopes were left by an error.
+    fn env_unwind(ctx: &mut Context, depth: usize) {
+        for _ in depth..Env::depth(ctx) {
+            ctx.env = *ctx.env.next.take().unwrap();
+        }
+    }
+    fn track_unused(ctx: &mut Context, sym: intern::Symbol, token: Option<Token>, is_param: bool) {
+        ctx.env.unused.insert(sym, (token, is_param));
     }
-    fn env_dec() {
-        let env = std::mem::replace(&mut *ENV.lock().unwrap(), Env::new_env(None));
-        *ENV.lock().unwrap() = *env.next.unwrap();
+    // Marks the variable `sym` resolves to as referenced.
+    fn mark_used(ctx: &mut Context, sym: intern::Symbol) {
+        let mut env_ref = Some(&mut ctx.env);
+        while let Some(e) = env_ref {
+            if e.vars.contains_key(&sym) {
+                e.unused.remove(&sym);
+                return;
+            }
+            env_ref = e.next.as_deref_mut();
+        }
     }
-    fn add_var(ident: String, var: &mut Var) {
+    fn warn_unused(&self, names: &Interner) {
+        let warn_var = *WARN_UNUSED_VARIABLE.lock().unwrap();
+        let warn_param = *WARN_UNUSED_PARAMETER.lock().unwrap();
+        for (sym, (token, is_param)) in &self.unused {
+            let name = names.name(*sym);
+            let token = match token {
+                Some(token) => token,
+                None => continue,
+            };
+            if *is_param && warn_param {
+                token.warn(&format!("unused parameter '{}'", name));
+            } else if !*is_param && warn_var {
+                token.warn(&format!("unused variable '{}'", name));
+            }
+        }
+    }
+    fn add_var(ctx: &mut Context, sym: intern::Symbol, var: &mut Var) {
         if var.is_local {
-            *STACKSIZE.lock().unwrap() = var.calc_offset();
+            ctx.stacksize = var.calc_offset(ctx.stacksize);
         }
-        ENV.lock().unwrap().vars.insert(ident, var.clone());
+        ctx.env.vars.insert(sym, var.clone());
+    }
+    fn add_typedef(ctx: &mut Context, sym: intern::Symbol, ctype: Type) {
+        ctx.env.typedefs.insert(sym, ctype);
     }
-    fn add_typedef(ident: String, ctype: Type) {
-        ENV.lock().unwrap().typedefs.insert(ident, ctype);
+    fn add_tags(ctx: &mut Context, tag: intern::Symbol, ctype: Type) {
+        ctx.env.tags.insert(tag, ctype);
     }
-    fn add_tags(tag: String, ctype: Type) {
-        ENV.lock().unwrap().tags.insert(tag, ctype);
+    fn add_enumerator(ctx: &mut Context, sym: intern::Symbol, val: i32, ctype: Type) {
+        ctx.env.enums.insert(sym, (val, ctype));
     }
-    fn add_enum(tokenset: &mut TokenSet) {
-        tokenset.assert_ty(TokenRightCurlyBrace);
+    // enum-specifier = "enum" ident? ("{" enumerator ("," enumerator)* ","? "}")?
+    // The "enum" keyword has already been consumed.
+    fn add_enum(tokenset: &mut TokenSet) -> Result<Type, CompileError> {
+        let mut tag = intern::EMPTY;
+        if tokenset.consume_ty(TokenIdent) {
+            tokenset.pos -= 1;
+            tag = tokenset.ident()?;
+        }
+        let tag_name = tokenset.name(tag);
+        if !tokenset.consume_ty(TokenRightCurlyBrace) {
+            if tag == intern::EMPTY {
+                return Err(tokenset.error(String::from("bad enum definition")));
+            }
+            let ctype = env_find!(tokenset.ctx, tag, tags, NULL_TY.clone());
+            if let Ty::ENUM(..) = ctype.ty {
+                return Ok(ctype);
+            }
+            // a forward reference, which behaves as a plain int.
+            return Ok(Type::new(
+                Ty::ENUM(tag_name, vec![]),
+                None,
+                None,
+                4,
+                4,
+                0,
+                0,
+            ));
+        }
+        let mut members = vec![];
         let mut assign_num = 0;
         loop {
-            let enum_mem = tokenset.ident();
+            let enum_mem = tokenset.ident()?;
             if tokenset.consume_ty(TokenAssign) {
-                assign_num = tokenset.getval();
-                tokenset.assert_ty(TokenNum);
+                if let NodeType::Num(val) = const_expr(tokenset)?.op {
+                    assign_num = val;
+                }
             }
-            tokenset.assert_ty(TokenComma);
-            ENV.lock().unwrap().enums.insert(enum_mem, assign_num);
+            // later enumerators may refer to this one in their values.
+            Env::add_enumerator(tokenset.ctx, enum_mem, assign_num, INT_TY.clone());
+            members.push((enum_mem, assign_num));
+            // the last member may or may not have a trailing comma
+            if tokenset.consume_ty(TokenLeftCurlyBrace) {
+                break;
+            }
+            tokenset.assert_ty(TokenComma)?;
             if tokenset.consume_ty(TokenLeftCurlyBrace) {
                 break;
             }
             assign_num += 1;
         }
-        tokenset.assert_ty(TokenSemi);
+        let names = members
+            .iter()
+            .map(|(sym, val)| (tokenset.name(*sym), *val))
+            .collect();
+        let ctype = Type::new(Ty::ENUM(tag_name, names), None, None, 4, 4, 0, 0);
+        for (sym, val) in members {
+            Env::add_enumerator(tokenset.ctx, sym, val, ctype.clone());
+        }
+        if tag != intern::EMPTY {
+            Env::add_tags(tokenset.ctx, tag, ctype.clone());
+        }
+        Ok(ctype)
     }
-    fn find_enum(ident: &str) -> i32 {
-        let mut res = -1;
-        let env = std::mem::replace(&mut *ENV.lock().unwrap(), Env::new_env(None));
-        let mut env_ref = &env;
-        loop {
-            if let Some(num) = env_ref.enums.get(ident) {
-                res = *num;
-                break;
+    fn find_enum(ctx: &Context, sym: intern::Symbol) -> Option<(i32, Type)> {
+        let mut env_ref = Some(&ctx.env);
+        while let Some(e) = env_ref {
+            if let Some(num) = e.enums.get(&sym) {
+                return Some(num.clone());
             }
-            if let Some(next_env) = &env_ref.next {
-                env_ref = &next_env;
-            } else {
-                break;
+            env_ref = e.next.as_deref();
+        }
+        None
+    }
+    // Returns the closest visible name to `ident`, if any is close enough
+    // to be a likely typo.
+    fn suggest(ctx: &Context, ident: &str) -> Option<String> {
+        let mut best: Option<(usize, String)> = None;
+        let mut env_ref = Some(&ctx.env);
+        while let Some(e) = env_ref {
+            let syms = e.vars.keys().chain(e.typedefs.keys()).chain(e.enums.keys());
+            for name in syms.map(|sym| ctx.names.name(*sym)) {
+                let dist = levenshtein(ident, name);
+                if dist == 0 || dist > std::cmp::max(1, ident.len().div_ceil(3)) {
+                    continue;
+                }
+                match &best {
+                    Some((d, _)) if *d <= dist => {}
+                    _ => {
+                        best = Some((dist, name.to_string()));
+                    }
+                }
             }
+            env_ref = e.next.as_deref();
+        }
+        best.map(|(_, name)| name)
+    }
+}
+
+fn did_you_mean(ctx: &Context, ident: &str) -> String {
+    match Env::suggest(ctx, ident) {
+        Some(name) => {
+            format!("; did you mean '{}'?", name)
         }
-        *ENV.lock().unwrap() = env;
-        return res;
+        None => String::new(),
     }
 }
 
@@ -595,66 +887,132 @@ pub fn roundup(x: i32, align: i32) -> i32 {
     return (x + align - 1) & !(align - 1);
 }
 
-pub fn decl_specifiers(tokenset: &mut TokenSet) -> Type {
+pub fn decl_specifiers(tokenset: &mut TokenSet) -> Result<Type, CompileError> {
+    // the storage classes of local variables don't change the code
+    if tokenset.consume_ty(TokenRegister) || tokenset.consume_ty(TokenAuto) {
+        return decl_specifiers(tokenset);
+    }
+    if tokenset.consume_qualifiers() {
+        let mut ty = decl_specifiers(tokenset)?;
+        ty.is_volatile = true;
+        return Ok(ty);
+    }
     if tokenset.consume_ty(TokenIdent) {
         tokenset.pos -= 1;
-        let name = tokenset.ident();
-        return env_find!(name, typedefs, NULL_TY.clone());
+        let sym = tokenset.ident()?;
+        return Ok(env_find!(tokenset.ctx, sym, typedefs, NULL_TY.clone()));
     }
     if tokenset.consume_ty(TokenInt) {
-        return INT_TY.clone();
+        return Ok(INT_TY.clone());
     }
     if tokenset.consume_ty(TokenChar) {
-        return CHAR_TY.clone();
+        return Ok(CHAR_TY.clone());
     }
     if tokenset.consume_ty(TokenStruct) {
-        let mut mb_vec = vec![];
-        let mut tag = String::new();
+        let mut mb_vec: Vec<(String, Type)> = vec![];
+        let mut tag = intern::EMPTY;
         // tag
         if tokenset.consume_ty(TokenIdent) {
             tokenset.pos -= 1;
-            tag = tokenset.ident();
+            tag = tokenset.ident()?;
         }
+        let tag_name = tokenset.name(tag);
 
         // struct member
         if tokenset.consume_ty(TokenRightCurlyBrace) {
             while !tokenset.consume_ty(TokenLeftCurlyBrace) {
-                if let NodeType::VarDef(name, var, _) = declaration(tokenset, false).op {
-                    mb_vec.push((name, var.ctype));
+                let token = tokenset.tokens[tokenset.pos].clone();
+                if let Some((name, ctype)) = mb_vec.last() {
+                    // `T x[]` takes no space and is only valid as the last member.
+                    if let (Ty::ARY, 0) = (&ctype.ty, ctype.len) {
+                        tokenset.ctx.errors.push(token.error(format!(
+                            "flexible array member '{}' must be the last member",
+                            name
+                        )));
+                    }
+                }
+                for node in declarators(declaration(tokenset, false)?) {
+                    if let NodeType::VarDef(sym, var, _) = node.op {
+                        mb_vec.push((tokenset.name(sym), var.ctype));
+                    }
                 }
             }
         }
-        match (mb_vec.is_empty(), tag.is_empty()) {
+        match (mb_vec.is_empty(), tag == intern::EMPTY) {
             (true, true) => {
-                // error("bat struct definition.");
-                // for debug.
-                panic!("bat struct definition.");
+                return Err(tokenset.error(String::from("bad struct definition")));
             }
+            // a struct not defined yet is incomplete and has no members
             (true, false) => {
-                return env_find!(tag.clone(), tags, NULL_TY.clone());
+                return Ok(env_find!(
+                    tokenset.ctx,
+                    tag,
+                    tags,
+                    new_struct(tag_name, vec![])
+                ));
             }
             (false, c) => {
-                let struct_type = new_struct(tag.clone(), mb_vec);
+                let struct_type = new_struct(tag_name, mb_vec);
                 if !c {
-                    Env::add_tags(tag, struct_type.clone());
+                    Env::add_tags(tokenset.ctx, tag, struct_type.clone());
                 }
-                return struct_type;
+                return Ok(struct_type);
             }
         }
     }
+    // typeof(expr) or typeof(type-name). The expression isn't decayed, so
+    // the type of an array is an array.
     if tokenset.consume_ty(TokenTypeof) {
-        tokenset.assert_ty(TokenRightBrac);
-        let expr = assign(tokenset);
-        tokenset.assert_ty(TokenLeftBrac);
-        return get_type(&expr);
+        tokenset.assert_ty(TokenRightBrac)?;
+        let ctype = match is_typename_at(tokenset, tokenset.pos) {
+            true => type_name(tokenset)?,
+            false => get_type(&assign(tokenset)?, tokenset.ctx),
+        };
+        tokenset.assert_ty(TokenLeftBrac)?;
+        return Ok(ctype);
+    }
+    if tokenset.consume_ty(TokenEnum) {
+        return Env::add_enum(tokenset);
     }
     if tokenset.consume_ty(TokenBool) {
-        return BOOL_TY.clone();
+        return Ok(BOOL_TY.clone());
     }
     if tokenset.consume_ty(TokenVoid) {
-        return VOID_TY.clone();
+        return Ok(VOID_TY.clone());
     }
-    return NULL_TY.clone();
+    Ok(NULL_TY.clone())
+}
+
+// Returns true if the token at `pos` begins a type name.
+fn is_typename_at(tokenset: &TokenSet, pos: usize) -> bool {
+    match tokenset.tokens[pos].ty {
+        TokenInt | TokenChar | TokenVoid | TokenStruct | TokenTypeof | TokenBool
+        | TokenVolatile | TokenEnum | TokenRegister | TokenAuto => true,
+        TokenIdent => {
+            let ctype = env_find!(
+                tokenset.ctx,
+                tokenset.tokens[pos].sym,
+                typedefs,
+                NULL_TY.clone()
+            );
+            if let Ty::NULL = ctype.ty {
+                return false;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+// type-name = decl_specifiers "*"* ("[" const_expr "]")*
+fn type_name(tokenset: &mut TokenSet) -> Result<Type, CompileError> {
+    let mut ty = decl_specifiers(tokenset)?;
+    ty.is_volatile |= tokenset.consume_qualifiers();
+    while tokenset.consume_ty(TokenStar) {
+        ty = ty.ptr_to();
+        ty.is_volatile = tokenset.consume_qualifiers();
+    }
+    read_array(tokenset, ty)
 }
 
 pub fn new_struct(tag: String, mut mb_vec: Vec<(String, Type)>) -> Type {
@@ -708,96 +1066,279 @@ fn string_literal(tokenset: &mut TokenSet) -> Node {
     // A string literal is converted to a reference to an anonymous
     // global variable of type char array.
     let strname = tokenset.getstring();
-    let ctype = CHAR_TY.clone().ary_of(strname.len() as i32 + 1);
+    let ctype = CHAR_TY.clone().ary_of(strname.chars().count() as i32 + 1);
     tokenset.pos += 1;
-    let labelname = format!(".L.str{}", new_label());
+    tokenset.ctx.str_label += 1;
+    let labelname = format!(".L.str{}", tokenset.ctx.str_label);
     let var = Var::new(ctype, 0, false, Some(labelname), Some(strname), None);
-    GVARS.lock().unwrap().push(var.clone());
+    tokenset.ctx.gvars.push(var.clone());
     return Node::new_varref(var);
 }
 
-fn local_variable(tokenset: &mut TokenSet) -> Node {
-    let name = tokenset.ident();
-    let var = env_find!(name.clone(), vars, NULL_VAR.clone());
+fn local_variable(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let sym = tokenset.ident()?;
+    let var = env_find!(tokenset.ctx, sym, vars, NULL_VAR.clone());
     if let Ty::NULL = var.ctype.ty {
-        let enum_num = Env::find_enum(&name);
-        if enum_num != -1 {
-            return Node::new_num(enum_num);
-        }
-        panic!("{} is not defined.", name);
-    }
-    return Node::new_varref(var);
+        if let Some((enum_num, ctype)) = Env::find_enum(tokenset.ctx, sym) {
+            return Ok(Node::new_cast(ctype, Node::new_num(enum_num)));
+        }
+        // keep parsing as if it were an int variable.
+        let name = tokenset.ctx.names.name(sym);
+        let e = token.error(format!(
+            "'{}' is not defined{}",
+            name,
+            did_you_mean(tokenset.ctx, name)
+        ));
+        tokenset.ctx.errors.push(e);
+        return Ok(Node::new_varref(Var::new(
+            INT_TY.clone(),
+            0,
+            true,
+            None,
+            None,
+            None,
+        )));
+    }
+    Env::mark_used(tokenset.ctx, sym);
+    Ok(Node::new_varref(var).with_token(&token))
 }
 
-fn function_call(tokenset: &mut TokenSet) -> Node {
-    let token = &tokenset.tokens[tokenset.pos - 2];
-    let name = String::from(&PROGRAMS.lock().unwrap()[token.program_id][token.pos..token.end]);
-    let var = env_find!(name.clone(), vars, NULL_VAR.clone());
+fn function_call(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos - 2].clone();
+    let sym = token.sym;
+    let mut var = env_find!(tokenset.ctx, sym, vars, NULL_VAR.clone());
     if let Ty::NULL = var.ctype.ty {
-        eprintln!("Warning: \"{}\" function is not defined.", name);
+        if let Some(ctype) = forward_type(tokenset, sym) {
+            var.ctype = ctype.func_returning();
+        }
     }
-    // function call
+    if let Ty::NULL = var.ctype.ty {
+        let name = tokenset.ctx.names.name(sym);
+        eprintln!(
+            "Warning: \"{}\" function is not defined{}",
+            name,
+            did_you_mean(tokenset.ctx, name)
+        );
+        // implicitly declared function returns int.
+        var.ctype = INT_TY.clone().func_returning();
+    }
+    let args = call_args(tokenset)?;
+    // a call through a variable, like `fp(1)`
+    if var.ctype.ty != Ty::FUNC {
+        Env::mark_used(tokenset.ctx, sym);
+        let fptr = Node::new_varref(var).with_token(&token);
+        return Ok(Node::new_callptr(NULL_TY.clone(), fptr, args).with_token(&token));
+    }
+    let name = tokenset.name(sym);
+    Ok(Node::new_call(*var.ctype.ptr_to.unwrap(), name, args))
+}
+
+// Reads the arguments of a call; the "(" has already been consumed.
+fn call_args(tokenset: &mut TokenSet) -> Result<Vec<Node>, CompileError> {
     let mut args = vec![];
     while !tokenset.consume_ty(TokenLeftBrac) {
         if !args.is_empty() {
-            tokenset.assert_ty(TokenComma);
+            tokenset.assert_ty(TokenComma)?;
         }
-        args.push(assign(tokenset));
+        args.push(assign(tokenset)?);
     }
-    return Node::new_call(var.ctype, name, args);
+    Ok(args)
+}
+
+// Returns the return type of a function defined later in the file
+// by parsing the specifiers of its definition.
+fn forward_type(tokenset: &mut TokenSet, sym: intern::Symbol) -> Option<Type> {
+    let start = *tokenset.ctx.funcs.get(&sym)?;
+    let pos = tokenset.pos;
+    tokenset.pos = start;
+    tokenset.consume_ty(TokenExtern);
+    let ctype = decl_specifiers(tokenset).ok().map(|mut ctype| {
+        ctype.is_volatile |= tokenset.consume_qualifiers();
+        while tokenset.consume_ty(TokenStar) {
+            ctype = ctype.ptr_to();
+            ctype.is_volatile = tokenset.consume_qualifiers();
+        }
+        ctype
+    });
+    tokenset.pos = pos;
+    ctype
 }
 
-fn switch_loop_inc() {
-    SWITCHES.lock().unwrap().push(vec![]);
+// The labels of a switch statement being parsed.
+#[derive(Default)]
+pub struct SwitchLabels {
+    cases: Vec<Node>,
+    // case value -> line of its label
+    lines: HashMap<i32, usize>,
+    // line of the default label
+    default: Option<usize>,
 }
 
-fn switch_loop_dec() -> Vec<Node> {
-    if let Some(cases) = SWITCHES.lock().unwrap().pop() {
-        return cases;
+fn switch_loop_inc(ctx: &mut Context) {
+    ctx.switches.push(SwitchLabels::default());
+}
+
+fn switch_loop_dec(ctx: &mut Context) -> SwitchLabels {
+    if let Some(labels) = ctx.switches.pop() {
+        labels
     } else {
         eprintln!("cannot find jmp point of switch.");
         std::process::exit(0);
     }
 }
 
-fn case_emit(val: Node) {
-    if let None = SWITCHES.lock().unwrap().last() {
-        eprintln!("cannot find jmp point of switch.");
-        std::process::exit(0);
+// Records a case label before its statement is parsed, so that a
+// duplicate is reported at the later label even when the labels nest.
+fn case_label(ctx: &mut Context, token: &Token, val: &Node) -> Result<(), CompileError> {
+    let labels = match ctx.switches.last_mut() {
+        Some(labels) => labels,
+        None => {
+            return Err(token.error(String::from("case label not within a switch statement")));
+        }
+    };
+    if let Some(val) = eval(val) {
+        if let Some(line) = labels.lines.insert(val, token.line) {
+            return Err(token.error(format!(
+                "duplicate case value {} (previous case at line {})",
+                val, line
+            )));
+        }
     }
-    SWITCHES.lock().unwrap().last_mut().unwrap().push(val);
+    Ok(())
 }
 
-pub fn new_label() -> i32 {
-    *LABEL.lock().unwrap() += 1;
-    return *LABEL.lock().unwrap();
+fn case_emit(ctx: &mut Context, val: Node) {
+    ctx.switches.last_mut().unwrap().cases.push(val);
 }
 
-fn const_expr(tokenset: &mut TokenSet) -> Node {
-    let expr = expr(tokenset);
-    if let NodeType::Num(_) = &expr.op {
-        return expr;
-    } else {
-        eprintln!("expected Number.");
-        std::process::exit(0);
+fn default_label(ctx: &mut Context, token: &Token) -> Result<(), CompileError> {
+    let labels = match ctx.switches.last_mut() {
+        Some(labels) => labels,
+        None => {
+            return Err(token.error(String::from(
+                "'default' label not within a switch statement",
+            )));
+        }
+    };
+    if let Some(line) = labels.default.replace(token.line) {
+        return Err(token.error(format!(
+            "multiple default labels in one switch (previous default at line {})",
+            line
+        )));
+    }
+    Ok(())
+}
+
+// Evaluates an integer constant expression. Enum constants have already
+// been replaced with numbers cast to their enum type by local_variable.
+pub fn eval(node: &Node) -> Option<i32> {
+    match &node.op {
+        NodeType::Num(val) => Some(*val),
+        NodeType::BinaryTree(_, op, lhs, rhs) => {
+            let lhs = eval(lhs)?;
+            let rhs = eval(rhs)?;
+            match op {
+                TokenAdd => Some(lhs.wrapping_add(rhs)),
+                TokenSub => Some(lhs.wrapping_sub(rhs)),
+                TokenStar => Some(lhs.wrapping_mul(rhs)),
+                TokenDiv => lhs.checked_div(rhs),
+                TokenMod => lhs.checked_rem(rhs),
+                TokenShl => Some(lhs.wrapping_shl(rhs as u32)),
+                TokenShr => Some(lhs.wrapping_shr(rhs as u32)),
+                TokenLt => Some((lhs < rhs) as i32),
+                TokenLe => Some((lhs <= rhs) as i32),
+                TokenAmpersand => Some(lhs & rhs),
+                TokenOr => Some(lhs | rhs),
+                TokenXor => Some(lhs ^ rhs),
+                TokenLogAnd => Some((lhs != 0 && rhs != 0) as i32),
+                TokenLogOr => Some((lhs != 0 || rhs != 0) as i32),
+                _ => None,
+            }
+        }
+        NodeType::Equal(lhs, rhs) => Some((eval(lhs)? == eval(rhs)?) as i32),
+        NodeType::Ne(lhs, rhs) => Some((eval(lhs)? != eval(rhs)?) as i32),
+        NodeType::Not(expr) => Some((eval(expr)? == 0) as i32),
+        NodeType::Cast(ctype, expr) => match ctype.ty {
+            Ty::BOOL => Some((eval(expr)? != 0) as i32),
+            Ty::CHAR => Some(eval(expr)? as i8 as i32),
+            Ty::INT | Ty::ENUM(..) => eval(expr),
+            _ => None,
+        },
+        NodeType::Ternary(_, cond, then, els) => {
+            let cond = eval(cond)?;
+            if cond != 0 {
+                if then.op == NodeType::NULL {
+                    return Some(cond);
+                }
+                return eval(then);
+            }
+            eval(els)
+        }
+        _ => None,
+    }
+}
+
+// Rejects a division or modulo by a constant zero inside a constant
+// expression, which eval() cannot fold.
+fn check_div_zero(node: &Node) -> Result<(), CompileError> {
+    match &node.op {
+        NodeType::BinaryTree(_, op, lhs, rhs) => {
+            check_div_zero(lhs)?;
+            check_div_zero(rhs)?;
+            if (*op == TokenDiv || *op == TokenMod) && eval(rhs) == Some(0) {
+                return Err(node.error(String::from("division by zero in constant expression")));
+            }
+        }
+        NodeType::Equal(lhs, rhs) | NodeType::Ne(lhs, rhs) => {
+            check_div_zero(lhs)?;
+            check_div_zero(rhs)?;
+        }
+        NodeType::Not(expr) => {
+            check_div_zero(expr)?;
+        }
+        NodeType::Ternary(_, cond, then, els) => {
+            check_div_zero(cond)?;
+            // a constant condition leaves the other branch unevaluated
+            match eval(cond) {
+                Some(0) => check_div_zero(els)?,
+                Some(_) => check_div_zero(then)?,
+                None => {
+                    check_div_zero(then)?;
+                    check_div_zero(els)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn const_expr(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let start = tokenset.pos;
+    let expr = conditional(tokenset)?;
+    check_div_zero(&expr)?;
+    match eval(&expr) {
+        Some(val) => Ok(Node::new_num(val).with_token(&tokenset.tokens[start])),
+        None => Err(tokenset.tokens[start].error(String::from("expected constant expression"))),
     }
 }
 
-fn primary(tokenset: &mut TokenSet) -> Node {
+fn primary(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
     // ( expr )
     if tokenset.consume_ty(TokenRightBrac) {
         if tokenset.consume_ty(TokenRightCurlyBrace) {
             tokenset.pos -= 1;
-            let body = Node::new_stmtexpr(VOID_TY.clone(), compound_stmt(tokenset, true));
-            tokenset.assert_ty(TokenLeftBrac);
-            return body;
+            let body = Node::new_stmtexpr(VOID_TY.clone(), compound_stmt(tokenset, true)?);
+            tokenset.assert_ty(TokenLeftBrac)?;
+            return Ok(body);
         }
-        let lhs = expr(tokenset);
-        tokenset.assert_ty(TokenLeftBrac);
-        return lhs;
+        let lhs = expr(tokenset)?;
+        tokenset.assert_ty(TokenLeftBrac)?;
+        return Ok(lhs);
     }
     if tokenset.consume_ty(TokenNum) {
-        return Node::new_num(tokenset.tokens[tokenset.pos - 1].val);
+        return Ok(Node::new_num(tokenset.tokens[tokenset.pos - 1].val));
     }
     if tokenset.consume_ty(TokenIdent) {
         // variable
@@ -808,23 +1349,22 @@ fn primary(tokenset: &mut TokenSet) -> Node {
         return function_call(tokenset);
     }
     if tokenset.consume_ty(TokenString(String::new())) {
-        return string_literal(tokenset);
+        return Ok(string_literal(tokenset));
     }
     // {a_1, a_2, ...}
     if tokenset.consume_ty(TokenRightCurlyBrace) {
-        let mut var = std::mem::replace(&mut *ARRINI.lock().unwrap(), NULL_VAR.clone());
-        if let Ty::ARY = var.ctype.ty {
+        if let Some(mut var) = tokenset.ctx.arrini.take() {
             let mut arrrhs = vec![];
             loop {
-                arrrhs.push(logor(tokenset));
+                arrrhs.push(logor(tokenset)?);
                 if !tokenset.consume_ty(TokenComma) {
                     break;
                 }
             }
-            tokenset.assert_ty(TokenLeftCurlyBrace);
+            tokenset.assert_ty(TokenLeftCurlyBrace)?;
             // for array def ex int a[] = ...
             var.ctype.size = var.ctype.ary_to.as_ref().unwrap().size * arrrhs.len() as i32;
-            var.calc_offset();
+            var.calc_offset(tokenset.ctx.stacksize);
             let mut arrini = vec![];
             let mut i = 0;
             for rhs in arrrhs {
@@ -838,258 +1378,344 @@ fn primary(tokenset: &mut TokenSet) -> Node {
                 arrini.push((lhs, rhs));
                 i += 1;
             }
-            *ARRINI.lock().unwrap() = var;
-            return Node::new_arrini(arrini);
+            tokenset.ctx.arrini = Some(var);
+            return Ok(Node::new_arrini(arrini));
         } else {
-            panic!("array init error.");
+            return Err(tokenset.tokens[tokenset.pos - 1]
+                .error(String::from("brace initializer is only allowed for arrays")));
         }
     }
-    // error(&format!("parse.rs: primary parse fail. and got {}", tokenset[*pos].input));
-    // for debug.
-    let token = &tokenset.tokens[tokenset.pos];
-    panic!(
-        "parse.rs: primary parse fail. and got {}",
-        &PROGRAMS.lock().unwrap()[token.program_id][token.pos..]
-    );
+    Err(tokenset.error(format!(
+        "expected expression but got '{}'",
+        tokenset.tokens[tokenset.pos].text()
+    )))
 }
 
-fn postfix(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = primary(tokenset);
+fn postfix(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = primary(tokenset)?.with_token(&token);
 
     loop {
+        // `x++ ++` is parsed, and rejected by sema as `x++` is not an lvalue
         if tokenset.consume_ty(TokenInc) {
-            lhs = Node::new_incdec(NULL_TY.clone(), 1, lhs);
+            lhs = Node::new_incdec(NULL_TY.clone(), 1, lhs).with_token(&token);
+            continue;
         }
         if tokenset.consume_ty(TokenDec) {
-            lhs = Node::new_incdec(NULL_TY.clone(), 2, lhs);
+            lhs = Node::new_incdec(NULL_TY.clone(), 2, lhs).with_token(&token);
+            continue;
         }
+        // member access errors point at the '.' or '->'
+        let op = tokenset.tokens[tokenset.pos].clone();
         // struct member
         if tokenset.consume_ty(TokenDot) {
-            let name = tokenset.ident();
-            lhs = Node::new_dot(NULL_TY.clone(), lhs, name);
+            let name = tokenset.ident()?;
+            let name = tokenset.name(name);
+            lhs = Node::new_dot(NULL_TY.clone(), lhs, name).with_token(&op);
         // struct member arrow
         } else if tokenset.consume_ty(TokenArrow) {
-            let name = tokenset.ident();
-            let expr = Node::new_deref(INT_TY.clone(), lhs);
-            lhs = Node::new_dot(NULL_TY.clone(), expr, name);
+            let name = tokenset.ident()?;
+            let name = tokenset.name(name);
+            let expr = Node::new_deref(INT_TY.clone(), lhs).with_token(&token);
+            lhs = Node::new_dot(NULL_TY.clone(), expr, name).with_token(&op);
         // array
         } else if tokenset.consume_ty(TokenRightmiddleBrace) {
-            let id = assign(tokenset);
-            let lhs2 = Node::new_bit(INT_TY.clone(), TokenAdd, lhs, id);
-            lhs = Node::new_deref(INT_TY.clone(), lhs2);
-            tokenset.assert_ty(TokenLeftmiddleBrace);
+            let id = assign(tokenset)?;
+            let lhs2 = Node::new_bit(INT_TY.clone(), TokenAdd, lhs, id).with_token(&token);
+            lhs = Node::new_deref(INT_TY.clone(), lhs2).with_token(&token);
+            tokenset.assert_ty(TokenLeftmiddleBrace)?;
+        // call through a function pointer, like `(*fp)(1)`
+        } else if tokenset.consume_ty(TokenRightBrac) {
+            let args = call_args(tokenset)?;
+            lhs = Node::new_callptr(NULL_TY.clone(), lhs, args).with_token(&token);
         } else {
-            return lhs;
+            return Ok(lhs);
         }
     }
 }
 
-fn unary(tokenset: &mut TokenSet) -> Node {
+fn unary(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    // (type-name) unary
+    if token.ty == TokenRightBrac && is_typename_at(tokenset, tokenset.pos + 1) {
+        tokenset.pos += 1;
+        let ctype = type_name(tokenset)?;
+        tokenset.assert_ty(TokenLeftBrac)?;
+        return Ok(Node::new_cast(ctype, unary(tokenset)?).with_token(&token));
+    }
     if tokenset.consume_ty(TokenInc) {
-        let lhs = unary(tokenset);
-        let rhs = Node::new_bit(NULL_TY.clone(), TokenAdd, lhs.clone(), Node::new_num(1));
-        return Node::new_assign(NULL_TY.clone(), lhs, rhs);
+        let lhs = unary(tokenset)?;
+        let rhs = Node::new_bit(NULL_TY.clone(), TokenAdd, lhs.clone(), Node::new_num(1))
+            .with_token(&token);
+        return Ok(Node::new_assign(NULL_TY.clone(), lhs, rhs).with_token(&token));
     }
     if tokenset.consume_ty(TokenDec) {
-        let lhs = unary(tokenset);
-        let rhs = Node::new_bit(NULL_TY.clone(), TokenSub, lhs.clone(), Node::new_num(1));
-        return Node::new_assign(NULL_TY.clone(), lhs, rhs);
+        let lhs = unary(tokenset)?;
+        let rhs = Node::new_bit(NULL_TY.clone(), TokenSub, lhs.clone(), Node::new_num(1))
+            .with_token(&token);
+        return Ok(Node::new_assign(NULL_TY.clone(), lhs, rhs).with_token(&token));
     }
     if tokenset.consume_ty(TokenSub) {
-        return Node::new_bit(NULL_TY.clone(), TokenSub, Node::new_num(0), unary(tokenset));
+        return Ok(Node::new_bit(
+            NULL_TY.clone(),
+            TokenSub,
+            Node::new_num(0),
+            unary(tokenset)?,
+        )
+        .with_token(&token));
     }
     if tokenset.consume_ty(TokenStar) {
-        return Node::new_deref(INT_TY.clone(), unary(tokenset));
+        return Ok(Node::new_deref(INT_TY.clone(), unary(tokenset)?).with_token(&token));
     }
     if tokenset.consume_ty(TokenAmpersand) {
-        return Node::new_addr(INT_TY.clone(), unary(tokenset));
+        return Ok(Node::new_addr(NULL_TY.clone(), unary(tokenset)?).with_token(&token));
     }
     if tokenset.consume_ty(TokenSizeof) {
-        return Node::new_num(get_type(&unary(tokenset)).size);
+        let ctype = sizeof_operand(tokenset, &token)?;
+        return Ok(Node::new_num(ctype.size).with_token(&token));
     }
     if tokenset.consume_ty(TokenAlignof) {
-        return Node::new_num(get_type(&unary(tokenset)).align);
+        let ctype = sizeof_operand(tokenset, &token)?;
+        return Ok(Node::new_num(ctype.align).with_token(&token));
     }
     if tokenset.consume_ty(TokenNot) {
-        return Node::new_not(unary(tokenset));
+        return Ok(Node::new_not(unary(tokenset)?).with_token(&token));
     }
     if tokenset.consume_ty(TokenTilde) {
-        return Node::new_bit(
+        return Ok(Node::new_bit(
             NULL_TY.clone(),
             TokenXor,
-            unary(tokenset),
+            unary(tokenset)?,
             Node::new_num(-1),
-        );
+        )
+        .with_token(&token));
     }
     return postfix(tokenset);
 }
 
-fn mul(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = unary(tokenset);
+// Returns the type of the operand of sizeof or _Alignof, which is
+// either a parenthesized type name or an expression. An expression
+// operand is typed without array-to-pointer decay.
+fn sizeof_operand(tokenset: &mut TokenSet, op: &Token) -> Result<Type, CompileError> {
+    let ctype = if tokenset.tokens[tokenset.pos].ty == TokenRightBrac
+        && is_typename_at(tokenset, tokenset.pos + 1)
+    {
+        tokenset.pos += 1;
+        let ctype = type_name(tokenset)?;
+        tokenset.assert_ty(TokenLeftBrac)?;
+        ctype
+    } else {
+        get_type(&unary(tokenset)?, tokenset.ctx)
+    };
+    if let Ty::STRUCT(_, members) = &ctype.ty {
+        if members.is_empty() {
+            tokenset.ctx.errors.push(op.error(format!(
+                "invalid application of '{}' to incomplete type '{}'",
+                op.text(),
+                ctype
+            )));
+        }
+    }
+    Ok(ctype)
+}
+
+fn mul(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = unary(tokenset)?;
 
     loop {
         if tokenset.consume_ty(TokenStar) {
-            lhs = Node::new_bit(NULL_TY.clone(), TokenStar, lhs, unary(tokenset));
+            lhs =
+                Node::new_bit(NULL_TY.clone(), TokenStar, lhs, unary(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenDiv) {
-            lhs = Node::new_bit(NULL_TY.clone(), TokenDiv, lhs, unary(tokenset));
+            lhs =
+                Node::new_bit(NULL_TY.clone(), TokenDiv, lhs, unary(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenMod) {
-            lhs = Node::new_bit(NULL_TY.clone(), TokenMod, lhs, unary(tokenset));
+            lhs =
+                Node::new_bit(NULL_TY.clone(), TokenMod, lhs, unary(tokenset)?).with_token(&token);
         } else {
-            return lhs;
+            return Ok(lhs);
         }
     }
 }
 
-fn add(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = mul(tokenset);
+fn add(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = mul(tokenset)?;
 
     loop {
         if !tokenset.consume_ty(TokenAdd) && !tokenset.consume_ty(TokenSub) {
-            return lhs;
+            return Ok(lhs);
         }
         let ty = tokenset.tokens[tokenset.pos - 1].ty.clone();
-        let rhs = mul(tokenset);
-        lhs = Node::new_bit(NULL_TY.clone(), ty, lhs, rhs);
+        let rhs = mul(tokenset)?;
+        lhs = Node::new_bit(NULL_TY.clone(), ty, lhs, rhs).with_token(&token);
     }
 }
 
-fn shift(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = add(tokenset);
+fn shift(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = add(tokenset)?;
 
     loop {
         if tokenset.consume_ty(TokenShl) {
-            lhs = Node::new_bit(NULL_TY.clone(), TokenShl, lhs, add(tokenset));
+            lhs = Node::new_bit(NULL_TY.clone(), TokenShl, lhs, add(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenShr) {
-            lhs = Node::new_bit(NULL_TY.clone(), TokenShr, lhs, add(tokenset));
+            lhs = Node::new_bit(NULL_TY.clone(), TokenShr, lhs, add(tokenset)?).with_token(&token);
         } else {
-            return lhs;
+            return Ok(lhs);
         }
     }
 }
 
-fn relational(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = shift(tokenset);
+fn relational(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = shift(tokenset)?;
 
     loop {
         if tokenset.consume_ty(TokenLt) {
-            lhs = Node::new_bit(INT_TY.clone(), TokenLt, lhs, shift(tokenset));
+            lhs = Node::new_bit(INT_TY.clone(), TokenLt, lhs, shift(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenRt) {
-            lhs = Node::new_bit(INT_TY.clone(), TokenLt, shift(tokenset), lhs);
+            lhs = Node::new_bit(INT_TY.clone(), TokenLt, shift(tokenset)?, lhs).with_token(&token);
         } else if tokenset.consume_ty(TokenLe) {
-            lhs = Node::new_bit(INT_TY.clone(), TokenLe, lhs, shift(tokenset));
+            lhs = Node::new_bit(INT_TY.clone(), TokenLe, lhs, shift(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenGe) {
-            lhs = Node::new_bit(INT_TY.clone(), TokenLe, shift(tokenset), lhs);
+            lhs = Node::new_bit(INT_TY.clone(), TokenLe, shift(tokenset)?, lhs).with_token(&token);
         } else {
-            return lhs;
+            return Ok(lhs);
         }
     }
 }
 
-fn equarity(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = relational(tokenset);
+fn equarity(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = relational(tokenset)?;
 
     loop {
         if tokenset.consume_ty(TokenEqual) {
-            lhs = Node::new_equal(lhs, relational(tokenset));
+            lhs = Node::new_equal(lhs, relational(tokenset)?).with_token(&token);
         } else if tokenset.consume_ty(TokenNe) {
-            lhs = Node::new_neq(lhs, relational(tokenset));
+            lhs = Node::new_neq(lhs, relational(tokenset)?).with_token(&token);
         } else {
-            return lhs;
+            return Ok(lhs);
         }
     }
 }
 
-fn bitand(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = equarity(tokenset);
+fn bitand(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = equarity(tokenset)?;
 
     while tokenset.consume_ty(TokenAmpersand) {
-        lhs = Node::new_bit(INT_TY.clone(), TokenAmpersand, lhs, equarity(tokenset));
+        lhs = Node::new_bit(INT_TY.clone(), TokenAmpersand, lhs, equarity(tokenset)?)
+            .with_token(&token);
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn bitxor(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = bitand(tokenset);
+fn bitxor(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = bitand(tokenset)?;
 
     while tokenset.consume_ty(TokenXor) {
-        lhs = Node::new_bit(INT_TY.clone(), TokenXor, lhs, bitand(tokenset));
+        lhs = Node::new_bit(INT_TY.clone(), TokenXor, lhs, bitand(tokenset)?).with_token(&token);
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn bitor(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = bitxor(tokenset);
+fn bitor(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = bitxor(tokenset)?;
 
     while tokenset.consume_ty(TokenOr) {
-        lhs = Node::new_bit(INT_TY.clone(), TokenOr, lhs, bitxor(tokenset));
+        lhs = Node::new_bit(INT_TY.clone(), TokenOr, lhs, bitxor(tokenset)?).with_token(&token);
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn logand(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = bitor(tokenset);
+fn logand(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = bitor(tokenset)?;
 
     while tokenset.consume_ty(TokenLogAnd) {
-        lhs = Node::new_bit(INT_TY.clone(), TokenLogAnd, lhs, bitor(tokenset));
+        lhs = Node::new_bit(INT_TY.clone(), TokenLogAnd, lhs, bitor(tokenset)?).with_token(&token);
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn logor(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = logand(tokenset);
+fn logor(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = logand(tokenset)?;
 
     while tokenset.consume_ty(TokenLogOr) {
-        lhs = Node::new_bit(INT_TY.clone(), TokenLogOr, lhs, logand(tokenset));
+        lhs = Node::new_bit(INT_TY.clone(), TokenLogOr, lhs, logand(tokenset)?).with_token(&token);
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn conditional(tokenset: &mut TokenSet) -> Node {
-    let cond = logor(tokenset);
+fn conditional(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let cond = logor(tokenset)?;
     if tokenset.consume_ty(TokenQuestion) {
-        let then = expr(tokenset);
-        tokenset.assert_ty(TokenColon);
-        let els = conditional(tokenset);
-        return Node::new_ternary(NULL_TY.clone(), cond, then, els);
-    }
-    return cond;
+        // GNU `a ?: b` yields `a` itself when it is true. A NULL "then"
+        // node stands for the condition value, which is evaluated once.
+        if tokenset.consume_ty(TokenColon) {
+            let els = conditional(tokenset)?;
+            return Ok(
+                Node::new_ternary(NULL_TY.clone(), cond, Node::new_null(), els).with_token(&token),
+            );
+        }
+        let then = expr(tokenset)?;
+        tokenset.assert_ty(TokenColon)?;
+        let els = conditional(tokenset)?;
+        return Ok(Node::new_ternary(NULL_TY.clone(), cond, then, els).with_token(&token));
+    }
+    Ok(cond)
 }
 
-fn assign(tokenset: &mut TokenSet) -> Node {
-    let mut lhs = conditional(tokenset);
+fn assign(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let mut lhs = conditional(tokenset)?;
 
     if let Some(op) = assignment_op(tokenset) {
-        let rhs = assign(tokenset);
+        let rhs = assign(tokenset)?;
         match op {
             TokenAssign => {
-                lhs = Node::new_assign(NULL_TY.clone(), lhs, rhs);
+                lhs = Node::new_assign(NULL_TY.clone(), lhs, rhs).with_token(&token);
             }
             _ => {
-                let llhs = Node::new_bit(NULL_TY.clone(), op, lhs.clone(), rhs);
-                lhs = Node::new_assign(NULL_TY.clone(), lhs, llhs);
+                let llhs = Node::new_bit(NULL_TY.clone(), op, lhs.clone(), rhs).with_token(&token);
+                lhs = Node::new_assign(NULL_TY.clone(), lhs, llhs).with_token(&token);
             }
         }
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn expr(tokenset: &mut TokenSet) -> Node {
-    let lhs = assign(tokenset);
+fn expr(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    let lhs = assign(tokenset)?;
     if tokenset.consume_ty(TokenComma) {
-        return Node::new_tuple(NULL_TY.clone(), lhs, expr(tokenset));
+        return Ok(Node::new_tuple(NULL_TY.clone(), lhs, expr(tokenset)?).with_token(&token));
     }
-    return lhs;
+    Ok(lhs)
 }
 
-fn declarator(tokenset: &mut TokenSet, mut ty: Type) -> Node {
+fn read_pointer(tokenset: &mut TokenSet, mut ty: Type) -> Type {
+    ty.is_volatile |= tokenset.consume_qualifiers();
     while tokenset.consume_ty(TokenStar) {
         ty = ty.ptr_to();
+        ty.is_volatile = tokenset.consume_qualifiers();
     }
+    ty
+}
 
+fn declarator(tokenset: &mut TokenSet, ty: Type) -> Result<Node, CompileError> {
+    let ty = read_pointer(tokenset, ty);
     return direct_decl(tokenset, ty);
 }
 
-fn read_array(tokenset: &mut TokenSet, mut ty: Type) -> Type {
+fn read_array(tokenset: &mut TokenSet, mut ty: Type) -> Result<Type, CompileError> {
     let mut ary_size = vec![];
 
     while tokenset.consume_ty(TokenRightmiddleBrace) {
@@ -1097,19 +1723,15 @@ fn read_array(tokenset: &mut TokenSet, mut ty: Type) -> Type {
             ary_size.push(0);
             continue;
         }
-        let len = expr(tokenset);
-        if let NodeType::Num(val) = &len.op {
-            ary_size.push(*val);
-            tokenset.assert_ty(TokenLeftmiddleBrace);
+        let start = tokenset.pos;
+        let len = expr(tokenset)?;
+        check_div_zero(&len)?;
+        if let Some(val) = eval(&len) {
+            ary_size.push(val);
+            tokenset.assert_ty(TokenLeftmiddleBrace)?;
             continue;
         }
-        // error(&format!("array declaration is invalid at {}.", tokenset[*pos].input));
-        // for debug.
-        let token = &tokenset.tokens[tokenset.pos];
-        panic!(
-            "array declaration is invalid at {}.",
-            &PROGRAMS.lock().unwrap()[token.program_id][token.pos..]
-        );
+        return Err(tokenset.tokens[start].error(String::from("array size must be a constant")));
     }
 
     if ary_size.len() > 0 {
@@ -1118,20 +1740,44 @@ fn read_array(tokenset: &mut TokenSet, mut ty: Type) -> Type {
         }
     }
 
-    return ty;
+    Ok(ty)
 }
 
-fn decl_init(tokenset: &mut TokenSet, node: &mut Node) {
+fn decl_init(tokenset: &mut TokenSet, node: &mut Node) -> Result<(), CompileError> {
     if let NodeType::VarDef(_, ref var, ref mut init) = node.op {
         if tokenset.consume_ty(TokenAssign) {
             if let Ty::ARY = var.ctype.ty {
-                *ARRINI.lock().unwrap() = var.clone();
+                tokenset.ctx.arrini = Some(var.clone());
             }
-            let rhs = assign(tokenset);
+            let rhs = assign(tokenset)?;
             *init = Some(Box::new(rhs));
         }
     }
-    return;
+    Ok(())
+}
+
+// Reads the parameter list of a function declarator up to the ")".
+// Arguments are not checked against the parameters of a function
+// pointer, so only the syntax matters.
+fn skip_params(tokenset: &mut TokenSet) -> Result<(), CompileError> {
+    // `(void)` is an empty parameter list
+    if tokenset.tokens[tokenset.pos].ty == TokenVoid
+        && tokenset.tokens[tokenset.pos + 1].ty == TokenLeftBrac
+    {
+        tokenset.pos += 1;
+    }
+    let mut first = true;
+    while !tokenset.consume_ty(TokenLeftBrac) {
+        if !first {
+            tokenset.assert_ty(TokenComma)?;
+        }
+        first = false;
+        let ty = decl_specifiers(tokenset)?;
+        let ty = read_pointer(tokenset, ty);
+        tokenset.consume_ty(TokenIdent);
+        read_array(tokenset, ty)?;
+    }
+    Ok(())
 }
 
 fn new_ptr_to_replace_type(ctype: &Type, true_ty: Type) -> Type {
@@ -1139,6 +1785,10 @@ fn new_ptr_to_replace_type(ctype: &Type, true_ty: Type) -> Type {
         Ty::NULL => {
             return true_ty;
         }
+        // the element size is only known now, e.g. `int (*a[2])(int)`
+        Ty::ARY => {
+            new_ptr_to_replace_type(ctype.ary_to.as_ref().unwrap(), true_ty).ary_of(ctype.len)
+        }
         _ => {
             return Type::new(
                 ctype.ty.clone(),
@@ -1156,69 +1806,138 @@ fn new_ptr_to_replace_type(ctype: &Type, true_ty: Type) -> Type {
     }
 }
 
-fn direct_decl(tokenset: &mut TokenSet, ty: Type) -> Node {
+fn direct_decl(tokenset: &mut TokenSet, ty: Type) -> Result<Node, CompileError> {
     let mut ident_node;
 
     if tokenset.consume_ty(TokenIdent) {
         tokenset.pos -= 1;
-        let name = tokenset.ident();
+        let token = tokenset.tokens[tokenset.pos].clone();
+        let name = tokenset.ident()?;
         let mut var = NULL_VAR.clone();
-        var.ctype = read_array(tokenset, ty);
-        ident_node = Node::new_vardef(name, var, None);
+        var.ctype = read_array(tokenset, ty)?;
+        ident_node = Node::new_vardef(name, var, None).with_token(&token);
     } else if tokenset.consume_ty(TokenRightBrac) {
-        ident_node = declarator(tokenset, NULL_TY.clone());
-        tokenset.assert_ty(TokenLeftBrac);
+        // The declarator in parentheses applies to what follows it, e.g.
+        // `int *(*f)(int)` is a pointer to a function returning `int *`.
+        ident_node = declarator(tokenset, NULL_TY.clone())?;
+        tokenset.assert_ty(TokenLeftBrac)?;
 
-        let true_ty = read_array(tokenset, ty);
+        let true_ty = match tokenset.consume_ty(TokenRightBrac) {
+            true => {
+                skip_params(tokenset)?;
+                ty.func_returning()
+            }
+            false => read_array(tokenset, ty)?,
+        };
         let ident_node_true_ty = new_ptr_to_replace_type(&ident_node.nodesctype(None), true_ty);
 
         if let NodeType::VarDef(name, mut var, init) = ident_node.op {
             var.ctype = ident_node_true_ty;
             ident_node = Node {
                 op: NodeType::VarDef(name, var, init),
+                token: ident_node.token,
             };
         } else {
             panic!("direct_decl fun error.");
         }
     } else {
-        // for debug
-        let token = &tokenset.tokens[tokenset.pos];
-        panic!(
-            "bad direct declarator at {}",
-            &PROGRAMS.lock().unwrap()[token.program_id][token.pos..]
-        );
-        // error(&format!("bad direct declarator at {}", &tokenset[*pos].input[..]));
+        return Err(tokenset.error(format!(
+            "expected identifier but got '{}'",
+            tokenset.tokens[tokenset.pos].text()
+        )));
     }
-    decl_init(tokenset, &mut ident_node);
-    return ident_node;
+    Ok(ident_node)
 }
 
-fn declaration(tokenset: &mut TokenSet, newvar: bool) -> Node {
+// Only pointers to void are allowed, not objects of type void.
+fn check_void_var(token: &Token, name: &str, ctype: &Type) -> Result<(), CompileError> {
+    let mut ty = ctype;
+    while let Ty::ARY = ty.ty {
+        ty = ty.ary_to.as_ref().unwrap();
+    }
+    if let Ty::VOID = ty.ty {
+        return Err(token.error(format!("variable '{}' declared void", name)));
+    }
+    Ok(())
+}
+
+// Splits the result of `declaration` into its declarators.
+fn declarators(node: Node) -> Vec<Node> {
+    if let NodeType::CompStmt(nodes) = node.op {
+        return nodes;
+    }
+    vec![node]
+}
+
+fn declaration(tokenset: &mut TokenSet, newvar: bool) -> Result<Node, CompileError> {
+    let is_register = tokenset.tokens[tokenset.pos].ty == TokenRegister;
     // declaration type
-    let ty = decl_specifiers(tokenset);
+    let ty = decl_specifiers(tokenset)?;
+    // only declares a tag or enumerators
+    if tokenset.consume_ty(TokenSemi) {
+        return Ok(Node::new_null());
+    }
+
+    // every declarator shares the base type, e.g. `int a, *b, c[3];`
+    let mut nodes = vec![];
+    loop {
+        let token = tokenset.tokens[tokenset.pos].clone();
+        let mut ident_node = declarator(tokenset, ty.clone())?;
+        if let NodeType::VarDef(_, var, _) = &mut ident_node.op {
+            var.is_register = is_register;
+        }
+        // A variable with a bad initializer is still defined, so that its
+        // uses aren't reported as undefined too.
+        if let Err(e) = decl_init(tokenset, &mut ident_node) {
+            tokenset.ctx.arrini = None;
+            if newvar {
+                define_var(tokenset, &token, ident_node)?;
+            }
+            return Err(e);
+        }
+        // for struct member and typedef
+        if newvar {
+            nodes.push(define_var(tokenset, &token, ident_node)?);
+        } else {
+            nodes.push(ident_node);
+        }
+        if !tokenset.consume_ty(TokenComma) {
+            break;
+        }
+    }
+    tokenset.assert_ty(TokenSemi)?;
 
-    let ident_node = declarator(tokenset, ty);
-    tokenset.assert_ty(TokenSemi);
-    // panic!("{:#?}", ident_node);
+    if nodes.len() == 1 {
+        return Ok(nodes.pop().unwrap());
+    }
+    Ok(Node::new_stmt(nodes))
+}
 
-    // for struct member and typedef
-    if !newvar {
-        return ident_node;
+fn define_var(
+    tokenset: &mut TokenSet,
+    token: &Token,
+    ident_node: Node,
+) -> Result<Node, CompileError> {
+    if let NodeType::VarDef(sym, var, _) = &ident_node.op {
+        check_void_var(token, tokenset.ctx.names.name(*sym), &var.ctype)?;
     }
     match ident_node.op {
-        NodeType::VarDef(name, mut var, None) => {
-            Env::add_var(name, &mut var);
-            return Node::new_null();
+        NodeType::VarDef(sym, mut var, None) => {
+            Env::track_unused(tokenset.ctx, sym, ident_node.token, false);
+            Env::add_var(tokenset.ctx, sym, &mut var);
+            Ok(Node::new_null())
         }
-        NodeType::VarDef(name, mut var, Some(init)) => {
+        NodeType::VarDef(sym, mut var, Some(init)) => {
             // for array {..} init
-            let var2 = std::mem::replace(&mut *ARRINI.lock().unwrap(), NULL_VAR.clone());
-            if let Ty::ARY = var2.ctype.ty {
+            if let Some(var2) = tokenset.ctx.arrini.take() {
                 var = var2;
             }
-            Env::add_var(name, &mut var);
-            let varnode = Node::new_varref(var);
-            return Node::new_expr(Node::new_assign(NULL_TY.clone(), varnode, *init));
+            Env::track_unused(tokenset.ctx, sym, ident_node.token.clone(), false);
+            Env::add_var(tokenset.ctx, sym, &mut var);
+            let mut varnode = Node::new_varref(var);
+            varnode.token = ident_node.token;
+            let assign = Node::new_assign(NULL_TY.clone(), varnode, *init.clone());
+            Ok(Node::new_expr(assign.with_token_of(&init)))
         }
         _ => {
             panic!("declaration node type must be VarDef.");
@@ -1226,130 +1945,162 @@ fn declaration(tokenset: &mut TokenSet, newvar: bool) -> Node {
     }
 }
 
-fn expr_stmt(tokenset: &mut TokenSet) -> Node {
-    let lhs = expr(tokenset);
+fn expr_stmt(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let lhs = expr(tokenset)?;
     tokenset.consume_ty(TokenSemi);
-    return Node::new_expr(lhs);
+    Ok(Node::new_expr(lhs))
 }
 
-pub fn stmt(tokenset: &mut TokenSet) -> Node {
+// Every statement carries the token it starts with, so that errors
+// found in it later can point somewhere even if its parts have no
+// position of their own.
+pub fn stmt(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    let token = tokenset.tokens[tokenset.pos].clone();
+    Ok(stmt_node(tokenset)?.with_token(&token))
+}
+
+fn stmt_node(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
     match tokenset.tokens[tokenset.pos].ty {
         TokenRet => {
             tokenset.pos += 1;
-            let lhs = expr(tokenset);
-            tokenset.assert_ty(TokenSemi);
-            return Node::new_ret(lhs);
+            if tokenset.consume_ty(TokenSemi) {
+                return Ok(Node::new_ret(Node::new_null()));
+            }
+            let lhs = expr(tokenset)?;
+            tokenset.assert_ty(TokenSemi)?;
+            Ok(Node::new_ret(lhs))
         }
         TokenIf => {
             tokenset.pos += 1;
-            tokenset.assert_ty(TokenRightBrac);
-            let cond = expr(tokenset);
-            tokenset.assert_ty(TokenLeftBrac);
-            let then = stmt(tokenset);
+            tokenset.assert_ty(TokenRightBrac)?;
+            let cond = expr(tokenset)?;
+            tokenset.assert_ty(TokenLeftBrac)?;
+            let then = stmt(tokenset)?;
             if tokenset.consume_ty(TokenElse) {
                 if tokenset.consume_ty(TokenIf) {
                     tokenset.pos -= 1;
-                    let elifthen = stmt(tokenset);
-                    return Node::new_if(cond, then, Some(elifthen));
+                    let elifthen = stmt(tokenset)?;
+                    return Ok(Node::new_if(cond, then, Some(elifthen)));
                 }
-                let elthen = stmt(tokenset);
-                return Node::new_if(cond, then, Some(elthen));
+                let elthen = stmt(tokenset)?;
+                Ok(Node::new_if(cond, then, Some(elthen)))
             } else {
-                return Node::new_if(cond, then, None);
+                Ok(Node::new_if(cond, then, None))
             }
         }
         TokenFor => {
             tokenset.pos += 1;
-            tokenset.assert_ty(TokenRightBrac);
-            Env::env_inc();
+            tokenset.assert_ty(TokenRightBrac)?;
+            Env::env_inc(tokenset.ctx);
             let mut init = Node::new_null();
             if tokenset.is_typename() {
                 tokenset.pos -= 1;
-                init = declaration(tokenset, true);
+                init = declaration(tokenset, true)?;
             } else if !tokenset.consume_ty(TokenSemi) {
-                init = expr_stmt(tokenset);
+                init = expr_stmt(tokenset)?;
             }
             let mut cond = Node::new_null();
             if !tokenset.consume_ty(TokenSemi) {
-                cond = expr(tokenset);
-                tokenset.assert_ty(TokenSemi);
+                cond = expr(tokenset)?;
+                tokenset.assert_ty(TokenSemi)?;
             }
             let mut inc = Node::new_null();
             if !tokenset.consume_ty(TokenLeftBrac) {
-                inc = expr_stmt(tokenset);
-                tokenset.assert_ty(TokenLeftBrac);
+                inc = expr_stmt(tokenset)?;
+                tokenset.assert_ty(TokenLeftBrac)?;
             }
-            let body = stmt(tokenset);
-            Env::env_dec();
-            return Node::new_for(init, cond, inc, body);
+            let body = stmt(tokenset)?;
+            Env::env_dec(tokenset.ctx);
+            Ok(Node::new_for(init, cond, inc, body))
         }
         TokenWhile => {
             tokenset.pos += 1;
-            tokenset.assert_ty(TokenRightBrac);
-            let cond = expr(tokenset);
-            tokenset.assert_ty(TokenLeftBrac);
-            let body = stmt(tokenset);
-            return Node::new_for(Node::new_null(), cond, Node::new_null(), body);
+            tokenset.assert_ty(TokenRightBrac)?;
+            let cond = expr(tokenset)?;
+            tokenset.assert_ty(TokenLeftBrac)?;
+            let body = stmt(tokenset)?;
+            Ok(Node::new_for(
+                Node::new_null(),
+                cond,
+                Node::new_null(),
+                body,
+            ))
         }
         TokenDo => {
             tokenset.pos += 1;
-            let body = stmt(tokenset);
-            tokenset.assert_ty(TokenWhile);
-            tokenset.assert_ty(TokenRightBrac);
-            let cond = expr(tokenset);
-            tokenset.assert_ty(TokenLeftBrac);
-            tokenset.assert_ty(TokenSemi);
-            return Node::new_dowhile(body, cond);
+            let body = stmt(tokenset)?;
+            tokenset.assert_ty(TokenWhile)?;
+            tokenset.assert_ty(TokenRightBrac)?;
+            let cond = expr(tokenset)?;
+            tokenset.assert_ty(TokenLeftBrac)?;
+            tokenset.assert_ty(TokenSemi)?;
+            Ok(Node::new_dowhile(body, cond))
         }
         TokenSwitch => {
             tokenset.pos += 1;
-            switch_loop_inc();
-            tokenset.assert_ty(TokenRightBrac);
-            let cond = expr(tokenset);
-            tokenset.assert_ty(TokenLeftBrac);
-            let body = stmt(tokenset);
-            let case_conds = switch_loop_dec();
-            return Node::new_switch(cond, body, case_conds);
+            switch_loop_inc(tokenset.ctx);
+            tokenset.assert_ty(TokenRightBrac)?;
+            let cond = expr(tokenset)?;
+            tokenset.assert_ty(TokenLeftBrac)?;
+            let body = stmt(tokenset)?;
+            let labels = switch_loop_dec(tokenset.ctx);
+            Ok(Node::new_switch(
+                cond,
+                body,
+                labels.cases,
+                labels.default.is_some(),
+            ))
         }
         TokenCase => {
+            let token = tokenset.tokens[tokenset.pos].clone();
             tokenset.pos += 1;
-            let val = const_expr(tokenset);
-            tokenset.assert_ty(TokenColon);
-            let body = stmt(tokenset);
-            case_emit(val.clone());
-            return Node::new_case(val, body);
+            let val = const_expr(tokenset)?;
+            tokenset.assert_ty(TokenColon)?;
+            case_label(tokenset.ctx, &token, &val)?;
+            let body = stmt(tokenset)?;
+            case_emit(tokenset.ctx, val.clone());
+            Ok(Node::new_case(val, body))
+        }
+        TokenDefault => {
+            let token = tokenset.tokens[tokenset.pos].clone();
+            tokenset.pos += 1;
+            tokenset.assert_ty(TokenColon)?;
+            default_label(tokenset.ctx, &token)?;
+            let body = stmt(tokenset)?;
+            Ok(Node::new_default(body))
         }
         TokenRightCurlyBrace => {
             return compound_stmt(tokenset, true);
         }
-        TokenInt | TokenChar | TokenStruct | TokenTypeof | TokenBool => {
+        TokenInt | TokenChar | TokenStruct | TokenTypeof | TokenBool | TokenVoid
+        | TokenVolatile | TokenEnum | TokenRegister | TokenAuto => {
             return declaration(tokenset, true);
         }
         TokenSemi => {
             tokenset.pos += 1;
-            return Node::new_null();
+            Ok(Node::new_null())
         }
         TokenTypedef => {
+            let token = tokenset.tokens[tokenset.pos].clone();
             tokenset.pos += 1;
-            let lhs = declaration(tokenset, false);
-            if let NodeType::VarDef(name, var, None) = lhs.op {
-                Env::add_typedef(name, var.ctype);
-                return Node::new_null();
+            for node in declarators(declaration(tokenset, false)?) {
+                if let NodeType::VarDef(name, var, None) = node.op {
+                    Env::add_typedef(tokenset.ctx, name, var.ctype);
+                } else {
+                    return Err(token.error(String::from("typedef cannot have an initializer")));
+                }
             }
-            panic!("typedef error.");
-        }
-        TokenEnum => {
-            tokenset.pos += 1;
-            Env::add_enum(tokenset);
-            return Node::new_null();
+            Ok(Node::new_null())
         }
         TokenBreak => {
             tokenset.pos += 1;
-            return Node::new_break();
+            tokenset.assert_ty(TokenSemi)?;
+            Ok(Node::new_break())
         }
         TokenContinue => {
             tokenset.pos += 1;
-            return Node::new_continue();
+            tokenset.assert_ty(TokenSemi)?;
+            Ok(Node::new_continue())
         }
         _ => {
             if tokenset.consume_ty(TokenIdent) {
@@ -1364,38 +2115,72 @@ pub fn stmt(tokenset: &mut TokenSet) -> Node {
     }
 }
 
-pub fn compound_stmt(tokenset: &mut TokenSet, newenv: bool) -> Node {
+pub fn compound_stmt(tokenset: &mut TokenSet, newenv: bool) -> Result<Node, CompileError> {
     let mut compstmts = vec![];
-    tokenset.assert_ty(TokenRightCurlyBrace);
+    tokenset.assert_ty(TokenRightCurlyBrace)?;
     if newenv {
-        Env::env_inc();
+        Env::env_inc(tokenset.ctx);
     }
     while !tokenset.consume_ty(TokenLeftCurlyBrace) {
-        compstmts.push(stmt(tokenset));
+        let depth = Env::depth(tokenset.ctx);
+        match stmt(tokenset) {
+            Ok(node) => {
+                compstmts.push(node);
+            }
+            Err(e) => {
+                // leave the scopes the statement entered, e.g. of a `for`
+                Env::env_unwind(tokenset.ctx, depth);
+                // give up on this function and let toplevel record the error.
+                if tokenset.tokens[tokenset.pos].ty == TokenEof
+                    || tokenset.ctx.errors.len() + 1 >= MAX_ERRORS
+                {
+                    return Err(e);
+                }
+                tokenset.ctx.errors.push(e);
+                skip_to_sync_point(tokenset, false);
+            }
+        }
     }
-    Env::env_dec();
-    return Node::new_stmt(compstmts);
+    Env::env_dec(tokenset.ctx);
+    Ok(Node::new_stmt(compstmts))
 }
 
-pub fn param_declaration(tokenset: &mut TokenSet) -> Var {
+pub fn param_declaration(tokenset: &mut TokenSet) -> Result<Var, CompileError> {
     // type
-    let ty = decl_specifiers(tokenset);
-    let node = declarator(tokenset, ty);
+    let ty = decl_specifiers(tokenset)?;
+    // a prototype may leave parameters unnamed, as in `int f(int, char *);`
+    let start = tokenset.pos;
+    let mut ctype = ty.clone();
+    tokenset.consume_qualifiers();
+    while tokenset.consume_ty(TokenStar) {
+        ctype = ctype.ptr_to();
+        tokenset.consume_qualifiers();
+    }
+    if let TokenComma | TokenLeftBrac = tokenset.tokens[tokenset.pos].ty {
+        let mut var = NULL_VAR.clone();
+        var.ctype = ctype;
+        Env::add_var(tokenset.ctx, intern::EMPTY, &mut var);
+        return Ok(var);
+    }
+    tokenset.pos = start;
+    let node = declarator(tokenset, ty)?;
 
-    if let NodeType::VarDef(name, mut var, _) = node.op {
+    if let NodeType::VarDef(sym, mut var, _) = node.op {
         if let Ty::ARY = &var.ctype.ty {
             var.ctype = var.ctype.ary_to.unwrap().clone().ptr_to();
         }
-        var.labelname = Some(name.clone());
-        Env::add_var(name, &mut var);
-        return var;
+        var.labelname = Some(tokenset.name(sym));
+        Env::track_unused(tokenset.ctx, sym, node.token, true);
+        Env::add_var(tokenset.ctx, sym, &mut var);
+        Ok(var)
     } else {
         panic!("{:?} should be NodeType::VarDef", node);
     }
 }
 
-fn calc_gvarinit(node: &Node, initvec: &mut Vec<String>) {
-    let ctype = node.nodesctype(None);
+// The directives are sized by `ctype`, the type of the object being
+// initialized, rather than by the type of the initializer.
+fn calc_gvarinit(node: &Node, ctype: &Type, initvec: &mut Vec<String>) -> Result<(), CompileError> {
     match &node.op {
         NodeType::Num(num) => {
             if ctype.size == 8 {
@@ -1407,112 +2192,438 @@ fn calc_gvarinit(node: &Node, initvec: &mut Vec<String>) {
             }
         }
         NodeType::ArrIni(arrini) => {
+            let elem = match &ctype.ary_to {
+                Some(elem) => elem.as_ref().clone(),
+                None => node.nodesctype(None),
+            };
             for (_, rhs) in arrini {
                 if let NodeType::VarRef(var) = &rhs.op {
                     initvec.push(format!(".quad {}", var.labelname.as_ref().unwrap()));
                 } else {
-                    calc_gvarinit(&rhs, initvec);
+                    calc_gvarinit(rhs, &elem, initvec)?;
                 }
             }
         }
-        _ => {}
+        // a char array initialized by a string literal holds its bytes,
+        // and a pointer points to the literal.
+        NodeType::VarRef(var) if var.strname.is_some() => {
+            if let Ty::ARY = ctype.ty {
+                let mut bytes = var.strname.as_ref().unwrap().chars();
+                for _ in 0..ctype.size {
+                    let byte = bytes.next().map_or(0, |c| c as u32 as u8);
+                    initvec.push(format!(".byte {}", byte));
+                }
+            } else {
+                initvec.push(format!(".quad {}", var.labelname.as_ref().unwrap()));
+            }
+        }
+        // a constant condition selects the branch to emit.
+        NodeType::Ternary(_, cond, then, els) if eval(cond).is_some() => {
+            if eval(cond).unwrap() != 0 {
+                calc_gvarinit(then, ctype, initvec)?;
+            } else {
+                calc_gvarinit(els, ctype, initvec)?;
+            }
+        }
+        _ => match eval(node) {
+            Some(val) => calc_gvarinit(&Node::new_num(val), ctype, initvec)?,
+            None => {
+                return Err(node.error(String::from(
+                    "initializer element is not a compile-time constant",
+                )));
+            }
+        },
     }
+    Ok(())
 }
 
-pub fn toplevel(tokenset: &mut TokenSet) -> Node {
-    // enum
-    if tokenset.consume_ty(TokenEnum) {
-        Env::add_enum(tokenset);
-        return Node::new_null();
+pub fn toplevel(tokenset: &mut TokenSet) -> Result<Node, CompileError> {
+    // a stray `;`, as after a function body, declares nothing
+    if tokenset.consume_ty(TokenSemi) {
+        return Ok(Node::new_null());
     }
-
     let is_extern = tokenset.consume_ty(TokenExtern);
     let is_typedef = tokenset.consume_ty(TokenTypedef);
 
     // Ctype
-    let mut ctype = decl_specifiers(tokenset);
-
-    while tokenset.consume_ty(TokenStar) {
-        ctype = ctype.ptr_to();
+    let base = decl_specifiers(tokenset)?;
+    // only declares a tag or enumerators
+    if !is_typedef && tokenset.consume_ty(TokenSemi) {
+        return Ok(Node::new_null());
     }
+    let mut ctype = read_pointer(tokenset, base.clone());
 
     // identifier
-    let ident = tokenset.ident();
+    let mut token = tokenset.tokens[tokenset.pos].clone();
+    let mut sym = tokenset.ident()?;
 
     // function
     if tokenset.consume_ty(TokenRightBrac) {
+        let ident = tokenset.name(sym);
         if is_typedef {
-            // error(&format!("typedef {} has function definition.", name));
-            // for debug.
-            panic!("typedef {} has function definition.", ident);
+            return Err(token.error(format!("typedef '{}' has function definition", ident)));
         }
-        *STACKSIZE.lock().unwrap() = 0;
+        tokenset.ctx.stacksize = 0;
         // add new function to Env
-        let mut var = Var::new(ctype.clone(), 0, false, Some(ident.clone()), None, None);
-        Env::add_var(ident.clone(), &mut var);
+        let fn_ty = ctype.clone().func_returning();
+        let mut var = Var::new(fn_ty, 0, false, Some(ident.clone()), None, None);
+        Env::add_var(tokenset.ctx, sym, &mut var);
 
-        Env::env_inc();
+        Env::env_inc(tokenset.ctx);
         // argument
         let mut args = vec![];
+        // `(void)` is an empty parameter list
+        if tokenset.tokens[tokenset.pos].ty == TokenVoid
+            && tokenset.tokens[tokenset.pos + 1].ty == TokenLeftBrac
+        {
+            tokenset.pos += 1;
+        }
         while !tokenset.consume_ty(TokenLeftBrac) {
             if !args.is_empty() {
-                tokenset.assert_ty(TokenComma);
+                tokenset.assert_ty(TokenComma)?;
             }
-            args.push(param_declaration(tokenset));
-        }
+            args.push(param_declaration(tokenset)?);
+        }
+        let decl = Symbol {
+            ctype: ctype.clone(),
+            params: Some(args.iter().map(|arg| arg.ctype.clone()).collect()),
+            defined: tokenset.tokens[tokenset.pos].ty != TokenSemi,
+        };
+        declare_symbol(tokenset.ctx, &token, sym, decl);
         // function decl
         if tokenset.consume_ty(TokenSemi) {
-            return Node::new_null();
+            tokenset.ctx.env.unused.clear();
+            Env::env_dec(tokenset.ctx);
+            return Ok(Node::new_null());
         }
         // function def
-        let body = compound_stmt(tokenset, false);
-        return Node::new_func(ctype, ident, args, body, *STACKSIZE.lock().unwrap());
+        let body = compound_stmt(tokenset, false)?;
+        // the frame is rounded up to 16 bytes in gen_ir
+        let stacksize = tokenset.ctx.stacksize;
+        if stacksize > i32::MAX - 15 {
+            return Err(token.error(format!(
+                "stack frame of '{}' exceeds {} bytes",
+                ident,
+                i32::MAX
+            )));
+        }
+        return Ok(Node::new_func(ctype, ident, args, body, stacksize));
+    }
+
+    // every declarator shares the base type, e.g. `int x, *y;`
+    loop {
+        let ty = read_array(tokenset, ctype)?;
+        global_declarator(tokenset, &token, sym, ty, is_typedef, is_extern)?;
+        if !tokenset.consume_ty(TokenComma) {
+            break;
+        }
+        ctype = read_pointer(tokenset, base.clone());
+        token = tokenset.tokens[tokenset.pos].clone();
+        sym = tokenset.ident()?;
+    }
+    tokenset.assert_ty(TokenSemi)?;
+    Ok(Node::new_null())
+}
+
+// Declares a file-scope typedef, extern or global variable.
+fn global_declarator(
+    tokenset: &mut TokenSet,
+    token: &Token,
+    sym: intern::Symbol,
+    ctype: Type,
+    is_typedef: bool,
+    is_extern: bool,
+) -> Result<(), CompileError> {
+    let ident = tokenset.name(sym);
+    if is_typedef {
+        Env::add_typedef(tokenset.ctx, sym, ctype);
+    } else if is_extern {
+        let decl = Symbol {
+            ctype: ctype.clone(),
+            params: None,
+            defined: false,
+        };
+        declare_symbol(tokenset.ctx, token, sym, decl);
+        let mut var = Var::new(ctype.clone(), 0, false, Some(ident), None, None);
+        Env::add_var(tokenset.ctx, sym, &mut var);
     } else {
-        ctype = read_array(tokenset, ctype);
-        if is_typedef {
-            tokenset.assert_ty(TokenSemi);
-            Env::add_typedef(ident, ctype);
-        } else if is_extern {
-            tokenset.assert_ty(TokenSemi);
-            let mut var = Var::new(ctype.clone(), 0, false, Some(ident.clone()), None, None);
-            Env::add_var(ident, &mut var);
-        } else {
-            let mut var = Var::new(ctype.clone(), 0, false, Some(ident.clone()), None, None);
-            // global init
-            let gvar_rhs;
-            if tokenset.consume_ty(TokenAssign) {
-                if let Ty::ARY = var.ctype.ty {
-                    *ARRINI.lock().unwrap() = var.clone();
-                    gvar_rhs = conditional(tokenset);
-                    *ARRINI.lock().unwrap() = NULL_VAR.clone();
-                } else {
-                    gvar_rhs = conditional(tokenset);
+        check_void_var(token, &ident, &ctype)?;
+        let mut var = Var::new(ctype.clone(), 0, false, Some(ident.clone()), None, None);
+        // global init. A variable with a bad initializer is still
+        // declared, so that its uses aren't reported as undefined too.
+        let mut parse_error = None;
+        if tokenset.consume_ty(TokenAssign) {
+            if let Ty::ARY = var.ctype.ty {
+                tokenset.ctx.arrini = Some(var.clone());
+            }
+            let rhs = conditional(tokenset);
+            tokenset.ctx.arrini = None;
+            match rhs {
+                Ok(gvar_rhs) => {
+                    // `char s[] = "..."` takes the size of the string
+                    if let (Ty::ARY, NodeType::VarRef(str_var)) = (&var.ctype.ty, &gvar_rhs.op) {
+                        if var.ctype.len == 0 && str_var.strname.is_some() {
+                            var.ctype = str_var.ctype.clone();
+                        }
+                    }
+                    let mut initvec = vec![];
+                    match check_div_zero(&gvar_rhs)
+                        .and_then(|_| calc_gvarinit(&gvar_rhs, &var.ctype, &mut initvec))
+                    {
+                        Ok(()) => var.init = Some(initvec),
+                        Err(e) => tokenset.ctx.errors.push(e),
+                    }
                 }
-                let mut initvec = vec![];
-                calc_gvarinit(&gvar_rhs, &mut initvec);
-                var.init = Some(initvec);
+                // the rest of the declaration can't be read either
+                Err(e) => parse_error = Some(e),
             }
-            // global variable
-            tokenset.assert_ty(TokenSemi);
-            Env::add_var(ident, &mut var);
-            GVARS.lock().unwrap().push(var);
         }
-        return Node::new_null();
+        // global variable
+        let decl = Symbol {
+            ctype: ctype.clone(),
+            params: None,
+            defined: var.init.is_some(),
+        };
+        declare_symbol(tokenset.ctx, token, sym, decl);
+        Env::add_var(tokenset.ctx, sym, &mut var);
+        // tentative definitions like `int x; int x = 1;` are merged.
+        match tokenset
+            .ctx
+            .gvars
+            .iter_mut()
+            .find(|gvar| gvar.labelname == Some(ident.clone()))
+        {
+            Some(gvar) => {
+                if var.init.is_some() {
+                    gvar.init = var.init;
+                }
+            }
+            None => {
+                tokenset.ctx.gvars.push(var);
+            }
+        }
+        if let Some(e) = parse_error {
+            return Err(e);
+        }
     }
+    Ok(())
 }
 
-pub fn parse(tokenset: &mut TokenSet, program: &mut Program) {
-    *ENV.lock().unwrap() = Env::new_env(None);
+// A file-scope function or variable, remembered so that later
+// declarations of the same name can be checked against it.
+pub struct Symbol {
+    ctype: Type,
+    // parameter types of a function, which are unknown for `f()`.
+    params: Option<Vec<Type>>,
+    // has a body or an initializer.
+    defined: bool,
+}
+
+// Records a file-scope declaration. If the name was already declared,
+// the declarations must agree and at most one of them may be a
+// definition. A conflict is recorded as an error and parsing goes on,
+// since the declaration itself is well formed.
+fn declare_symbol(ctx: &mut Context, token: &Token, sym: intern::Symbol, decl: Symbol) {
+    if let Err(e) = merge_symbol(ctx, token, sym, decl) {
+        ctx.errors.push(e);
+    }
+}
+
+fn merge_symbol(
+    ctx: &mut Context,
+    token: &Token,
+    sym: intern::Symbol,
+    decl: Symbol,
+) -> Result<(), CompileError> {
+    let name = ctx.names.name(sym);
+    let prev = match ctx.symbols.get_mut(&sym) {
+        Some(prev) => prev,
+        None => {
+            ctx.symbols.insert(sym, decl);
+            return Ok(());
+        }
+    };
+    if prev.params.is_some() != decl.params.is_some() {
+        return Err(token.error(format!("'{}' redeclared as different kind of symbol", name)));
+    }
+    let mut compatible = same_type(prev.ctype.clone(), decl.ctype.clone());
+    if let (Some(p1), Some(p2)) = (&prev.params, &decl.params) {
+        if !p1.is_empty() && !p2.is_empty() {
+            compatible &= p1.len() == p2.len()
+                && p1
+                    .iter()
+                    .zip(p2)
+                    .all(|(t1, t2)| same_type(t1.clone(), t2.clone()));
+        }
+    }
+    if !compatible {
+        return Err(token.error(format!("conflicting types for '{}'", name)));
+    }
+    if prev.defined && decl.defined {
+        return Err(token.error(format!("redefinition of '{}'", name)));
+    }
+    prev.defined |= decl.defined;
+    if let Some(params) = &decl.params {
+        if !params.is_empty() {
+            prev.params = decl.params.clone();
+        }
+    }
+    Ok(())
+}
+
+// Skips tokens so that parsing can resume after an error. A statement
+// ends at `;` or at the end of a `{...}` block. When `toplevel` is false,
+// the `}` closing the enclosing block is left for the caller.
+fn skip_to_sync_point(tokenset: &mut TokenSet, toplevel: bool) {
+    let mut depth = 0;
+    loop {
+        match tokenset.tokens[tokenset.pos].ty {
+            TokenEof => {
+                return;
+            }
+            TokenSemi if depth == 0 && !in_parens(tokenset, tokenset.pos + 1) => {
+                tokenset.pos += 1;
+                return;
+            }
+            TokenRightCurlyBrace => {
+                depth += 1;
+            }
+            TokenLeftCurlyBrace => {
+                if depth == 0 && !toplevel {
+                    return;
+                }
+                depth -= 1;
+                if depth <= 0 {
+                    tokenset.pos += 1;
+                    return;
+                }
+            }
+            _ => {}
+        }
+        tokenset.pos += 1;
+    }
+}
+
+// Returns true if the tokens from `pos` close a parenthesis opened
+// before it, as after the `;` of a `for` header.
+fn in_parens(tokenset: &TokenSet, pos: usize) -> bool {
+    let mut depth = 0;
+    for token in &tokenset.tokens[pos..] {
+        match token.ty {
+            TokenRightBrac => {
+                depth += 1;
+            }
+            TokenLeftBrac if depth == 0 => {
+                return true;
+            }
+            TokenLeftBrac => {
+                depth -= 1;
+            }
+            TokenRightCurlyBrace | TokenLeftCurlyBrace | TokenEof => {
+                return false;
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+// Records every file-scope function definition before parsing so that
+// a function can be called above its definition.
+fn scan_functions(tokenset: &mut TokenSet) {
+    let tokens = &tokenset.tokens;
+    let funcs = &mut tokenset.ctx.funcs;
+    funcs.clear();
+    let mut depth = 0;
+    let mut start = tokenset.pos;
+    let mut i = tokenset.pos;
+    while tokens[i].ty != TokenEof {
+        match tokens[i].ty {
+            TokenSemi if depth == 0 => {
+                start = i + 1;
+            }
+            TokenRightCurlyBrace => {
+                depth += 1;
+            }
+            TokenLeftCurlyBrace => {
+                depth -= 1;
+            }
+            TokenIdent if depth == 0 && tokens[i + 1].ty == TokenRightBrac => {
+                // find the `)` closing the parameter list
+                let mut j = i + 2;
+                let mut parens = 1;
+                while parens > 0 && tokens[j].ty != TokenEof {
+                    match tokens[j].ty {
+                        TokenRightBrac => parens += 1,
+                        TokenLeftBrac => parens -= 1,
+                        _ => {}
+                    }
+                    j += 1;
+                }
+                if tokens[j].ty == TokenRightCurlyBrace {
+                    funcs.entry(tokens[i].sym).or_insert(start);
+                    // skip the function body
+                    let mut braces = 0;
+                    while tokens[j].ty != TokenEof {
+                        match tokens[j].ty {
+                            TokenRightCurlyBrace => braces += 1,
+                            TokenLeftCurlyBrace => braces -= 1,
+                            _ => {}
+                        }
+                        j += 1;
+                        if braces == 0 {
+                            break;
+                        }
+                    }
+                    start = j;
+                }
+                i = j;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+pub fn parse(tokenset: &mut TokenSet, program: &mut Program) -> Result<(), Vec<CompileError>> {
+    // forget everything about a previously parsed program
+    tokenset.ctx.env = Env::default();
+    tokenset.ctx.symbols.clear();
+    tokenset.ctx.gvars.clear();
+    tokenset.ctx.switches.clear();
+    tokenset.ctx.arrini = None;
+    tokenset.ctx.errors.clear();
+    tokenset.ctx.loop_depth = 0;
+    tokenset.ctx.switch_depth = 0;
+    scan_functions(tokenset);
 
     loop {
         match tokenset.consume_ty(TokenEof) {
             true => {
                 break;
             }
-            false => {
-                program.nodes.push(toplevel(tokenset));
-            }
+            false => match toplevel(tokenset) {
+                Ok(node) => {
+                    program.nodes.push(node);
+                }
+                Err(e) => {
+                    tokenset.ctx.errors.push(e);
+                    if tokenset.ctx.errors.len() >= MAX_ERRORS {
+                        break;
+                    }
+                    skip_to_sync_point(tokenset, true);
+                    Env::env_unwind(tokenset.ctx, 1);
+                    tokenset.ctx.switches.clear();
+                    tokenset.ctx.arrini = None;
+                }
+            },
         }
     }
-    program.gvars = std::mem::replace(&mut GVARS.lock().unwrap(), vec![]);
+    program.gvars = std::mem::take(&mut tokenset.ctx.gvars);
+    let errors = std::mem::take(&mut tokenset.ctx.errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(())
 }
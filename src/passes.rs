@@ -0,0 +1,98 @@
+use super::copyprop::copyprop;
+use super::cse::cse;
+use super::dce::{dce, DceStats};
+use super::jumps::thread_jumps;
+use super::mir::Program;
+use super::sema::fold_constants;
+use super::strength::strength_reduce;
+
+// The optimization passes by -O level.
+//
+// Each stage of the pipeline has its list of passes, in the order they
+// run, along with the lowest level running each: -O0 runs none of them,
+// -O1 the ones which only simplify, and -O2 the ones which reuse values
+// too. The stages are the syntax tree after sema, the IR, the IR in
+// two-address form, and the IR after register allocation.
+
+pub const MAX_LEVEL: u32 = 2;
+
+// The counts the passes report with -fstats, of the passes which ran.
+#[derive(Default)]
+pub struct Stats {
+    pub dce: Option<DceStats>,
+    pub reduced: Option<usize>,
+    pub reused: Option<usize>,
+    pub copies: Option<usize>,
+    pub slots: usize,
+    pub threaded: Option<usize>,
+}
+
+fn sum(a: &mut Option<usize>, b: Option<usize>) {
+    if let Some(b) = b {
+        *a = Some(a.unwrap_or(0) + b);
+    }
+}
+
+impl Stats {
+    pub fn add(&mut self, other: Stats) {
+        if let Some(other) = other.dce {
+            let dce = self.dce.get_or_insert_with(DceStats::default);
+            dce.blocks += other.blocks;
+            dce.stores += other.stores;
+        }
+        sum(&mut self.reduced, other.reduced);
+        sum(&mut self.reused, other.reused);
+        sum(&mut self.copies, other.copies);
+        self.slots += other.slots;
+        sum(&mut self.threaded, other.threaded);
+    }
+    pub fn print(&self) {
+        if let Some(dce) = &self.dce {
+            eprintln!(
+                "dce: {} unreachable blocks and {} dead stores removed",
+                dce.blocks, dce.stores
+            );
+        }
+        if let Some(reduced) = self.reduced {
+            eprintln!("strength: {} operations reduced", reduced);
+        }
+        if let Some(reused) = self.reused {
+            eprintln!("cse: {} computations reused", reused);
+        }
+        if let Some(copies) = self.copies {
+            eprintln!("copyprop: {} copies removed", copies);
+        }
+        eprintln!("regalloc: {} spill slots", self.slots);
+        if let Some(threaded) = self.threaded {
+            eprintln!("jumps: {} jumps threaded", threaded);
+        }
+    }
+}
+
+pub type Pass = fn(&mut Program, &mut Stats);
+
+pub const AST_PASSES: &[(u32, Pass)] = &[(1, |program, _| fold_constants(program))];
+
+pub const IR_PASSES: &[(u32, Pass)] = &[
+    (1, |program, stats| stats.dce = Some(dce(program))),
+    (1, |program, stats| {
+        stats.reduced = Some(strength_reduce(program))
+    }),
+    (2, |program, stats| stats.reused = Some(cse(program))),
+];
+
+pub const TWO_ADDRESS_PASSES: &[(u32, Pass)] =
+    &[(2, |program, stats| stats.copies = Some(copyprop(program)))];
+
+pub const ALLOCATED_PASSES: &[(u32, Pass)] = &[(1, |program, stats| {
+    stats.threaded = Some(thread_jumps(program))
+})];
+
+// Runs the passes of `passes` enabled at `level`.
+pub fn run(passes: &[(u32, Pass)], level: u32, program: &mut Program, stats: &mut Stats) {
+    for (min_level, pass) in passes {
+        if level >= *min_level {
+            pass(program, stats);
+        }
+    }
+}
@@ -1,7 +1,6 @@
-use super::lib::*;
 use super::token::{TokenType::*, *};
+use super::*;
 use std::collections::HashMap;
-use std::sync::Mutex;
 
 pub static NONE_TOKEN: Token = Token {
     ty: TokenNoSignal,
@@ -10,17 +9,19 @@ pub static NONE_TOKEN: Token = Token {
     pos: 0,
     end: 0,
     line: 0,
+    noexpand: false,
+    sym: 0,
 };
 
-lazy_static! {
-    pub static ref PATH: Mutex<HashMap<usize, String>> = Mutex::new(HashMap::new());
-}
-
 struct Env {
     pub input: Vec<Token>,
     pub output: Vec<Token>,
     pub pos: usize,
     pub defined: HashMap<String, Macro>,
+    // Macros being expanded, with the input index where each expansion
+    // ends. A macro is not expanded again within its own expansion.
+    pub expanding: Vec<(String, usize)>,
+    pub in_arg: bool,
     pub next: Option<Box<Env>>,
 }
 
@@ -31,6 +32,8 @@ impl Env {
             output: vec![],
             pos: 0,
             defined: HashMap::new(),
+            expanding: vec![],
+            in_arg: false,
             next: next,
         }
     }
@@ -68,38 +71,49 @@ impl Env {
             }
         }
     }
-    fn assert_ty(&mut self, ty: TokenType) {
-        // assert!(self.consume_ty(ty));
+    // Consumes a token of type `ty`, or reports `msg` at the current one.
+    fn expect_ty(&mut self, ty: TokenType, msg: &str) -> Result<(), CompileError> {
         if !self.consume_ty(ty) {
-            panic!("{:?}", self.input[self.pos].ty);
+            return Err(self.input[self.pos].error(String::from(msg)));
         }
+        Ok(())
     }
-    fn define(&mut self) {
-        let name = self.ident();
-        if self.consume_ty(TokenRightBrac) {
-            Macro::define_funclike(self, name);
+    fn define(&mut self) -> Result<(), CompileError> {
+        let name = self.ident()?;
+        // A function-like macro needs `(` right after its name;
+        // `#define X (1+2)` is an object-like macro.
+        let name_end = self.input[self.pos - 1].end;
+        if self.input[self.pos].pos == name_end && self.consume_ty(TokenRightBrac) {
+            Macro::define_funclike(self, name)
         } else {
             Macro::define_objlike(self, name);
+            Ok(())
         }
     }
-    fn include(&mut self) {
-        match self.input[self.pos].ty {
+    fn include(&mut self) -> Result<(), CompileError> {
+        // self.input[self.pos-1] = include
+        let parent = self.input[self.pos - 1].program_id;
+        let line = self.input[self.pos - 1].line;
+        let token = self.input[self.pos].clone();
+        match token.ty {
             TokenString(_) => {
-                let path = self.input[self.pos].getstring();
+                let path = token.getstring();
                 self.pos += 1;
                 // input program
-                add_program(path.clone());
-                let program_id = PROGRAMS.lock().unwrap().len() - 1;
-                PATH.lock().unwrap().insert(program_id, path);
-                let mut nv = tokenize(program_id, false);
+                let program_id = match add_program(path.clone(), Some((parent, line))) {
+                    Ok(program_id) => program_id,
+                    Err(_) => {
+                        return Err(token.error(format!("failed to read file: {}", path)));
+                    }
+                };
+                let mut nv = tokenize(program_id, false)?;
                 self.output.append(&mut nv);
+                Ok(())
             }
-            _ => {
-                error(None, 0, &format!("string expected after #include"));
-            }
+            _ => Err(token.error(String::from("string expected after #include"))),
         }
     }
-    fn ident(&mut self) -> String {
+    fn ident(&mut self) -> Result<String, CompileError> {
         match self.input[self.pos].ty {
             TokenIdent => {
                 let name = String::from(
@@ -108,32 +122,24 @@ impl Env {
                         ..self.input[self.pos].pos + self.input[self.pos].val as usize],
                 );
                 self.pos += 1;
-                return name;
-            }
-            _ => {
-                error(
-                    get_path(self.input[self.pos].program_id),
-                    self.input[self.pos].line,
-                    &format!("macro name expected."),
-                );
-                panic!("macro name expected.");
+                Ok(name)
             }
+            _ => Err(self.input[self.pos].error(String::from("macro name expected"))),
         }
     }
     fn peek(&mut self) -> Token {
         return self.input[self.pos].clone();
     }
-    fn read_arg(&mut self) -> Vec<Token> {
+    // Reads an argument of the macro invocation at `name`.
+    fn read_arg(&mut self, name: &Token) -> Result<Vec<Token>, CompileError> {
         let mut v = vec![];
         let mut level = 0;
-        let mut token = self.peek();
-        let program_id = token.program_id;
-        let line = token.line;
+        let mut token;
         while !self.eof() {
             token = self.peek();
             if level == 0 {
                 if token.ty == TokenComma || token.ty == TokenLeftBrac {
-                    return v;
+                    return Ok(v);
                 }
             }
             self.pos += 1;
@@ -144,95 +150,129 @@ impl Env {
             }
             v.push(token);
         }
-        error(
-            get_path(program_id),
-            line,
-            &format!(
-                "unclonsed macro arguments at {:?}...",
-                &self.input[self.pos..self.pos + 5]
-            ),
-        );
-        panic!("");
+        Err(name.error(String::from("unterminated argument list invoking macro")))
     }
-    fn read_args(&mut self) -> Vec<Vec<Token>> {
+    fn read_args(&mut self, name: &Token) -> Result<Vec<Vec<Token>>, CompileError> {
         let mut v = vec![];
         if self.consume_ty(TokenLeftBrac) {
-            return v;
+            return Ok(v);
         }
-        v.push(self.read_arg());
+        v.push(self.read_arg(name)?);
         while !self.consume_ty(TokenLeftBrac) {
-            self.assert_ty(TokenComma);
-            v.push(self.read_arg());
+            self.expect_ty(TokenComma, "expected ',' or ')' in macro arguments")?;
+            v.push(self.read_arg(name)?);
         }
-        return v;
+        Ok(v)
     }
-    fn apply_objlike(&mut self, mut m: Macro) {
+    fn apply_objlike(&mut self, mut m: Macro) -> Vec<Token> {
         // self.input[self.pos-1] = define identifier
         let num = self.input[self.pos - 1].line;
         let program_id = self.input[self.pos - 1].program_id;
         let body = std::mem::replace(&mut m.body, vec![]);
+        let mut v = vec![];
         for token in body {
-            if self.emit_special_macro(&token, num, program_id) {
+            if let Some(special) = special_macro(&token, num, program_id) {
+                v.push(special);
                 continue;
             }
-            self.emit(token);
+            v.push(token);
         }
-        return;
+        v
     }
-    fn apply_funclike(&mut self, m: Macro, name: String) {
+    fn apply_funclike(&mut self, m: Macro, name: String) -> Result<Vec<Token>, CompileError> {
         let args;
         // self.input[self.pos-1] = define identifier
-        let line = self.input[self.pos - 1].line;
-        let program_id = self.input[self.pos - 1].program_id;
+        let macro_name = self.input[self.pos - 1].clone();
+        let line = macro_name.line;
+        let program_id = macro_name.program_id;
 
-        self.assert_ty(TokenRightBrac);
-        args = self.read_args();
-        if args.len() != m.params.unwrap().len() {
-            error(
-                get_path(program_id),
-                line,
-                &format!("number of parameter does not match at {}", name),
-            );
+        self.expect_ty(TokenRightBrac, "expected '(' after function-like macro")?;
+        args = self.read_args(&macro_name)?;
+        let params = m.params.unwrap().len();
+        if args.len() != params {
+            return Err(macro_name.error(format!(
+                "macro '{}' takes {} arguments, but {} given",
+                name,
+                params,
+                args.len()
+            )));
         }
+        let mut v = vec![];
         for token in m.body {
-            if self.emit_special_macro(&token, line, program_id) {
+            if let Some(special) = special_macro(&token, line, program_id) {
+                v.push(special);
                 continue;
             }
 
             if token.ty == TokenParam(false) {
-                self.output.append(&mut args[token.val as usize].clone());
+                v.append(&mut self.expand_arg(args[token.val as usize].clone())?);
                 continue;
             } else if token.ty == TokenParam(true) {
-                self.emit(stringize(&args[token.val as usize]));
+                v.push(stringize(&args[token.val as usize]));
                 continue;
             }
 
-            self.emit(token);
+            v.push(token);
         }
+        Ok(v)
     }
-    fn apply(&mut self, m: Macro, name: String) {
-        // OBJLIKE
-        if m.ty == MacroType::ObjLike {
-            self.apply_objlike(m);
-            return;
-        } else {
-            // FUNCLIKE
-            self.apply_funclike(m, name);
-            return;
+    // Replaces the macro invocation starting at `start` with its expansion
+    // and rewinds so that the expansion is scanned again for macros.
+    fn apply(&mut self, m: Macro, name: String, start: usize) -> Result<(), CompileError> {
+        let v = match m.ty {
+            MacroType::ObjLike => self.apply_objlike(m),
+            MacroType::FunLike => self.apply_funclike(m, name.clone())?,
+        };
+        let end = start + v.len();
+        let delta = end as isize - self.pos as isize;
+        for (_, e) in &mut self.expanding {
+            if *e >= self.pos {
+                *e = (*e as isize + delta) as usize;
+            } else if *e > start {
+                // the invocation reaches past an enclosing expansion
+                *e = start;
+            }
         }
+        self.input.splice(start..self.pos, v);
+        self.expanding.push((name, end));
+        self.pos = start;
+        Ok(())
     }
-    fn emit_special_macro(&mut self, token: &Token, line: usize, program_id: usize) -> bool {
-        if is_ident(token, "__LINE__") {
-            self.emit(Macro::new_num(
-                line as i32,
-                program_id,
-                token.pos,
-                token.end,
-            ));
-            return true;
+    // Macro arguments are fully expanded before they are substituted,
+    // with the macros enclosing the invocation still hidden.
+    fn expand_arg(&mut self, arg: Vec<Token>) -> Result<Vec<Token>, CompileError> {
+        self.in_expansion();
+        let mut env = Env::new(arg, None);
+        env.defined = self.defined.clone();
+        for (name, _) in &self.expanding {
+            env.expanding.push((name.clone(), usize::MAX));
         }
-        return false;
+        env.in_arg = true;
+        // directives are not read in macro arguments
+        expand(&mut env)?;
+        Ok(env.output)
+    }
+    // Returns true if `name` is being expanded at the current position.
+    fn is_expanding(&mut self, name: &str) -> bool {
+        self.in_expansion() && self.expanding.iter().any(|(n, _)| n == name)
+    }
+    fn in_expansion(&mut self) -> bool {
+        let pos = self.pos;
+        self.expanding.retain(|(_, end)| *end > pos);
+        !self.expanding.is_empty()
+    }
+}
+
+fn special_macro(token: &Token, line: usize, program_id: usize) -> Option<Token> {
+    if is_ident(token, "__LINE__") {
+        return Some(Macro::new_num(
+            line as i32,
+            program_id,
+            token.pos,
+            token.end,
+        ));
     }
+    None
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -271,21 +311,22 @@ impl Macro {
     ) -> Token {
         return Token::new(TokenParam(stringize), n, program_id, pos, end, line);
     }
-    fn define_funclike(env: &mut Env, name: String) {
+    fn define_funclike(env: &mut Env, name: String) -> Result<(), CompileError> {
         let mut params = vec![];
         loop {
-            let name = env.ident();
+            let name = env.ident()?;
             params.push(name);
             if env.consume_ty(TokenLeftBrac) {
                 break;
             }
-            env.assert_ty(TokenComma);
+            env.expect_ty(TokenComma, "expected ',' or ')' in macro parameter list")?;
         }
         let body = env.read_until_eol();
         let mut m = Macro::new(MacroType::FunLike, Some(params), body);
         m.replace_macro_params();
         m.replace_hash_ident();
         env.defined.insert(name, m);
+        Ok(())
     }
     // Replaces macro parameter tokens with TK_PARAM tokens.
     fn replace_macro_params(&mut self) {
@@ -361,27 +402,44 @@ fn is_ident(token: &Token, s: &str) -> bool {
     return token.ty == TokenIdent && &name == s;
 }
 
-pub fn get_path(program_id: usize) -> Option<String> {
-    return Some(PATH.lock().unwrap().get(&program_id).unwrap().clone());
+// Reads the file at `path` as a new program and returns its program id.
+// `included_from` is the (program_id, line) of the `#include` directive
+// that requested it, if any.
+pub fn add_program(
+    path: String,
+    included_from: Option<(usize, usize)>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let content = read_file(&path[..])?;
+    Ok(add_source(path, content, included_from))
 }
 
-pub fn add_program(path: String) {
-    match read_file(&path[..]) {
-        Ok(content) => {
-            let mut program = content;
-            remove_backslash_or_crlf_newline(&mut program);
-            PROGRAMS.lock().unwrap().push(program.clone());
-        }
-        Err(_) => {
-            println!("failed to read file.");
-            std::process::exit(1);
+// Adds `content` as a new program read from `path`.
+pub fn add_source(path: String, content: String, included_from: Option<(usize, usize)>) -> usize {
+    let mut program = content;
+    remove_backslash_or_crlf_newline(&mut program);
+    PROGRAMS.lock().unwrap().push(program);
+    add_file(path, included_from)
+}
+
+// Returns the paths of all headers opened by `#include`, in the order
+// they were first included.
+pub fn included_paths() -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    for file in FILES.lock().unwrap().iter() {
+        if file.included_from.is_some() && !paths.contains(&file.path) {
+            paths.push(file.path.clone());
         }
     }
+    paths
 }
 
-pub fn preprocess(tokens: Vec<Token>) -> Vec<Token> {
+pub fn preprocess(tokens: Vec<Token>) -> Result<Vec<Token>, CompileError> {
     let mut env = Env::new(tokens, None);
+    expand(&mut env)?;
+    Ok(env.output)
+}
 
+fn expand(env: &mut Env) -> Result<(), CompileError> {
     while !env.eof() {
         // ident
         if let TokenIdent = env.input[env.pos].ty {
@@ -391,19 +449,28 @@ pub fn preprocess(tokens: Vec<Token>) -> Vec<Token> {
                     [token.pos..token.pos + token.val as usize],
             );
             let mut m: Macro = Default::default();
+            let start = env.pos;
+            let hidden = env.is_expanding(&name);
             env.pos += 1;
             if let Some(m2) = env.defined.get(&name) {
                 m = m2.clone();
             }
-            if m.default_judge() {
-                env.output.push(token);
+            if m.default_judge() || token.noexpand {
+                env.emit(token);
+            } else if hidden {
+                // An identifier found inside its own expansion is never
+                // expanded again, even when rescanned as part of another
+                // macro's expansion.
+                let mut token = token;
+                token.noexpand = true;
+                env.emit(token);
             } else {
-                env.apply(m, name);
+                env.apply(m, name, start)?;
             }
             continue;
         }
-        // #
-        if let TokenSharp = env.input[env.pos].ty {
+        // # (a `#` produced by a macro expansion is not a directive)
+        if env.input[env.pos].ty == TokenSharp && !env.in_arg && !env.in_expansion() {
             env.pos += 1;
         } else {
             let token = env.input[env.pos].clone();
@@ -414,28 +481,17 @@ pub fn preprocess(tokens: Vec<Token>) -> Vec<Token> {
         // define
         if let TokenDefine = env.input[env.pos].ty {
             env.pos += 1;
-            env.define();
+            env.define()?;
             continue;
         }
         // include
         if let TokenInclude = env.input[env.pos].ty {
             env.pos += 1;
-            env.include();
+            env.include()?;
             continue;
         }
         let token = &env.input[env.pos];
-        let program_id = token.program_id;
-        let line = token.line;
-        error(
-            get_path(program_id),
-            line,
-            &format!(
-                "macro expected at {}...",
-                &PROGRAMS.lock().unwrap()[env.input[env.pos].program_id]
-                    [env.input[env.pos].pos..env.input[env.pos].pos + 5]
-            ),
-        );
+        return Err(token.error(String::from("invalid preprocessing directive")));
     }
-
-    return env.output;
+    Ok(())
 }
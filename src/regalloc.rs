@@ -4,28 +4,49 @@
 // registers. This pass maps them to finite number of registers.
 // Here is the algorithm:
 //
-// First, we find the definition and the last use for each register.
-// A register is considered "live" in the range. At the definition of
-// some register R, if all physical registers are already allocated,
-// one of them (including R itself) needs to be spilled to the stack.
-// As long as one register is spilled, the algorithm is logically
-// correct. As a heuristic, we spill a register whose last use is
-// furthest.
+// First, we find the live range of each register, see liveness.rs. The
+// ranges are visited in the order they start, and each one takes a
+// real register no longer holding a live range. If all of them are
+// taken, one of the live registers (including the new one) needs to be
+// spilled to the stack. As a heuristic, we spill a register whose live
+// range ends furthest. A register live across a call only takes one
+// of the registers the call preserves, and the ones of those taken are
+// recorded for the prologue to save.
 //
-// We then insert load and store instructions for spilled registesr.
-// The last register (num_regs-1'th register) is reserved for that
-// purpose.
+// Call arguments are given their argument registers when possible,
+// before the other registers are allocated.
+//
+// Spilled registers are rewritten into loads and stores of their stack
+// slots around the instructions using them, which leaves short live
+// ranges in their place, and the allocation is retried.
+//
+// The previous allocator is still available with -fregalloc=iterative.
 
 use super::gen_ir::{IrOp::*, *};
 use super::liveness;
 use super::mir::*;
 use super::parse::roundup;
-use linked_hash_map::LinkedHashMap;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::rc::Rc;
+use std::sync::Mutex;
 
 static REG_SIZE: usize = 7;
+// rdi, rsi, rdx, rcx, r8 and r9
+const ARG_REGS: usize = 6;
+
+// Calls clobber r10 and r11, so a value live across a call is kept in
+// rbx or r12-r15, which the called function preserves.
+const CALLEE_SAVED: usize = 2;
+
+fn usable(reglife: &RegLife, rn: usize) -> bool {
+    !reglife.crosses_call || rn >= CALLEE_SAVED
+}
+
+lazy_static! {
+    // -fregalloc=iterative
+    pub static ref ITERATIVE_REGALLOC: Mutex<bool> = Mutex::new(false);
+}
 
 // in IR, A = B op C  ---> A = B; A = A op C;
 fn three_two(bb: &Rc<RefCell<BB>>) {
@@ -48,7 +69,8 @@ fn three_two(bb: &Rc<RefCell<BB>>) {
             None,
             -1,
             -1,
-        );
+        )
+        .with_line(ir.line);
         n_irs.push(ir1);
         // A = A op C;
         ir.r1 = ir.r0.clone();
@@ -58,170 +80,317 @@ fn three_two(bb: &Rc<RefCell<BB>>) {
     bb.borrow_mut().irs = n_irs;
 }
 
-// settings of register
-fn regs_setting(fun: &mut Function, reglifes: Vec<RegLife>) {
-    // save register as vn
-    let mut regs = vec![-1; REG_SIZE];
-    let mut stacksize = roundup(fun.stacksize, 8);
-    let mut end_hash = HashMap::new(); // key -> vn; value -> end
+// Linear scan. The live ranges holding a register are kept in `active`,
+// ordered by their end, and give their register back once the range
+// being allocated starts after them. If none is free, the range ending
+// furthest away is spilled, which is either the new one or one taking
+// its register. The whole function is scanned in one go, and returns the
+// registers assigned and the stack slot of each spilled range. Spilled
+// ranges which don't overlap share a slot.
+fn linear_scan(
+    reglifes: &[RegLife],
+    unspillable: &HashSet<i32>,
+) -> (HashMap<i32, i32>, HashMap<i32, usize>) {
     let mut reg_map = HashMap::new();
-    let mut spill_offset_map = HashMap::new();
+    let mut spilled = HashMap::new();
+    let mut free: BTreeSet<i32> = (0..REG_SIZE as i32).collect();
+    let mut active: Vec<&RegLife> = vec![];
+    // the end of the last range spilled to each slot
+    let mut slot_ends: Vec<i32> = vec![];
 
-    // decide various datas of registers
     for reglife in reglifes {
-        let mut rn = -1;
-        end_hash.insert(reglife.vn, reglife.end);
-        let mut found = false;
-        // find an unused real register
-        for i in 0..REG_SIZE - 1 {
-            if regs[i] < 0 || reglife.start > end_hash[&regs[i]] {
-                rn = i as i32;
-                regs[i] = reglife.vn;
-                found = true;
-                break;
-            }
+        while !active.is_empty() && active[0].end < reglife.start {
+            free.insert(reg_map[&active.remove(0).vn]);
         }
-        if !found {
-            // choose to spill out
-            let mut spill_id = 0;
-            regs[REG_SIZE - 1] = reglife.vn;
-            for i in 0..regs.len() {
-                if end_hash[&regs[spill_id]] < end_hash[&regs[i]] {
-                    spill_id = i;
+        // the lowest numbered register first, like the other allocator
+        let rn = match free.iter().find(|rn| usable(reglife, **rn as usize)) {
+            Some(rn) => free.take(&rn.clone()).unwrap(),
+            None => {
+                let victim = active
+                    .iter()
+                    .copied()
+                    .filter(|life| usable(reglife, reg_map[&life.vn] as usize))
+                    .chain(std::iter::once(reglife))
+                    .filter(|life| !unspillable.contains(&life.vn))
+                    .max_by_key(|life| life.end)
+                    .expect("register allocation failed: every live register is a reload");
+                let slot = match slot_ends.iter().position(|end| *end < victim.start) {
+                    Some(slot) => slot,
+                    None => {
+                        slot_ends.push(0);
+                        slot_ends.len() - 1
+                    }
+                };
+                slot_ends[slot] = victim.end;
+                spilled.insert(victim.vn, slot);
+                if victim.vn == reglife.vn {
+                    continue;
                 }
+                active.retain(|life| life.vn != victim.vn);
+                reg_map.remove(&victim.vn).unwrap()
             }
-            // allocate register spilled out to stack
-            stacksize += 8;
-            spill_offset_map.insert(regs[spill_id], stacksize);
-            reg_map.insert(regs[spill_id], REG_SIZE as i32 - 1);
-            rn = spill_id as i32;
-            regs[spill_id] = reglife.vn;
-        }
+        };
         reg_map.insert(reglife.vn, rn);
+        let pos = active.partition_point(|life| life.end <= reglife.end);
+        active.insert(pos, reglife);
     }
-    fun.stacksize = stacksize;
-    // settings
-    for bb in &mut fun.bbs {
-        let param = bb.borrow().param.vn > 0;
-        if param {
-            let vn = bb.borrow().param.vn;
-            bb.borrow_mut().param.rn = reg_map[&vn];
-            bb.borrow_mut().param.spill = reg_map[&vn] == REG_SIZE as i32 - 1;
-            let param_spill = bb.borrow().param.spill;
-            if param_spill {
-                bb.borrow_mut().param.spill_offset = spill_offset_map[&vn];
+    (reg_map, spilled)
+}
+
+// The allocator before linear scan, kept for comparison: registers are
+// taken first-come, and when none is free the register used again
+// furthest away is spilled to a slot of its own and the allocation is
+// started over. Returns the registers or the register to spill.
+fn assign(reglifes: &[RegLife], unspillable: &HashSet<i32>) -> Result<HashMap<i32, i32>, i32> {
+    // index of the live range each real register holds
+    let mut regs: Vec<Option<usize>> = vec![None; REG_SIZE];
+    let mut reg_map = HashMap::new();
+
+    for (i, reglife) in reglifes.iter().enumerate() {
+        for reg in regs.iter_mut() {
+            if reg.is_some_and(|j| reglifes[j].end < reglife.start) {
+                *reg = None;
             }
         }
-        for ir in &mut bb.borrow_mut().irs {
-            if ir.r0.active() {
-                ir.r0.rn = reg_map[&ir.r0.vn];
-                ir.r0.spill = ir.r0.rn == REG_SIZE as i32 - 1;
-                if ir.r0.spill {
-                    ir.r0.spill_offset = spill_offset_map[&ir.r0.vn];
-                }
-            }
-            if ir.r1.active() {
-                ir.r1.rn = reg_map[&ir.r1.vn];
-                ir.r1.spill = ir.r1.rn == REG_SIZE as i32 - 1;
-                if ir.r1.spill {
-                    ir.r1.spill_offset = spill_offset_map[&ir.r1.vn];
-                }
-            }
-            if ir.r2.active() {
-                ir.r2.rn = reg_map[&ir.r2.vn];
-                ir.r2.spill = ir.r2.rn == REG_SIZE as i32 - 1;
-                if ir.r2.spill {
-                    ir.r2.spill_offset = spill_offset_map[&ir.r2.vn];
-                }
+        if let Some(rn) = (0..REG_SIZE).find(|rn| regs[*rn].is_none() && usable(reglife, *rn)) {
+            regs[rn] = Some(i);
+            reg_map.insert(reglife.vn, rn as i32);
+            continue;
+        }
+        // a register not used again before the end of its range has
+        // nothing to reload within it
+        let next_use = |life: &RegLife| {
+            life.uses
+                .iter()
+                .find(|ic| **ic >= reglife.start)
+                .copied()
+                .unwrap_or(i32::MAX)
+        };
+        let victim = (0..REG_SIZE)
+            .filter(|rn| usable(reglife, *rn))
+            .map(|rn| &reglifes[regs[rn].unwrap()])
+            .chain(std::iter::once(reglife))
+            .filter(|life| !unspillable.contains(&life.vn))
+            .max_by_key(|life| next_use(life));
+        match victim {
+            Some(life) => {
+                return Err(life.vn);
             }
-            if ir.bbarg.active() {
-                ir.bbarg.rn = reg_map[&ir.bbarg.vn];
-                ir.bbarg.spill = ir.bbarg.rn == REG_SIZE as i32 - 1;
-                if ir.bbarg.spill {
-                    ir.bbarg.spill_offset = spill_offset_map[&ir.bbarg.vn];
-                }
+            None => {
+                panic!("register allocation failed: every live register is a reload");
             }
-            if let IrCall(_, args) = &mut ir.op {
-                for i in 0..args.len() {
-                    args[i].rn = reg_map[&args[i].vn];
-                    args[i].spill = args[i].rn == REG_SIZE as i32 - 1;
-                    if args[i].spill {
-                        args[i].spill_offset = spill_offset_map[&args[i].vn];
+        }
+    }
+    Ok(reg_map)
+}
+
+// Precoloring. A call argument is computed into an allocated register
+// and copied to its argument register at the call, unless its value
+// can be kept in the argument register from the start: its live range
+// ends at the call, nothing in the range overwrites the argument
+// register, and the range overlaps no other one given the register.
+// Returns the argument registers given, numbered after the allocated
+// ones.
+fn precolor(fun: &Function, reglifes: &[RegLife]) -> HashMap<i32, i32> {
+    // the argument registers each instruction overwrites, by the number
+    // live_ranges gives it, and the call arguments
+    let mut clobbers: Vec<Vec<usize>> = vec![vec![]];
+    let mut call_args: HashMap<i32, Vec<(i32, usize)>> = HashMap::new();
+    for bb in liveness::block_order(fun) {
+        for ir in &bb.borrow().irs {
+            let ic = clobbers.len() as i32;
+            let clobber = match &ir.op {
+                IrCall(_, args) => {
+                    for (i, arg) in args.iter().enumerate() {
+                        if arg.active() {
+                            call_args.entry(arg.vn).or_default().push((ic, i));
+                        }
                     }
+                    (0..ARG_REGS).collect()
                 }
+                // cqo and imul write rdx, the shifts take a count not
+                // constant in cl
+                IrMul(_) | IrDiv(_) | IrMod(_) => vec![2],
+                IrShl(_) | IrShr | IrSar if ir.r2.active() => vec![3],
+                // reads the parameter from its register
+                IrStoreArg(_) => vec![ir.imm2 as usize],
+                _ => vec![],
+            };
+            clobbers.push(clobber);
+        }
+    }
+
+    let mut colors = HashMap::new();
+    let mut taken: Vec<Vec<&RegLife>> = vec![vec![]; ARG_REGS];
+    for life in reglifes {
+        let (call, i) = match call_args.get(&life.vn).map(|args| &args[..]) {
+            Some([arg]) if arg.1 < ARG_REGS => *arg,
+            _ => {
+                continue;
             }
+        };
+        let clobbered = (life.start..call).any(|ic| clobbers[ic as usize].contains(&i));
+        let overlaps = taken[i]
+            .iter()
+            .any(|other| life.start <= other.end && other.start <= life.end);
+        if life.end != call || clobbered || overlaps {
+            continue;
         }
+        taken[i].push(life);
+        colors.insert(life.vn, (REG_SIZE + i) as i32);
     }
+    colors
 }
 
-fn spillout_load(n_irs: &mut Vec<Ir>, r: &Reg) {
-    if !r.active() || !r.spill {
-        return;
-    }
-    n_irs.push(Ir::new(
-        IrLoadSpill,
-        r.clone(),
-        Reg::dummy(),
+fn spill_ir(op: IrOp, r0: Reg, r1: Reg, offset: i32, line: usize) -> Ir {
+    Ir::new(
+        op,
+        r0,
+        r1,
         Reg::dummy(),
         Reg::dummy(),
         None,
         None,
-        r.spill_offset,
+        offset,
         -1,
-    ));
+    )
+    .with_line(line)
 }
 
-fn spillout_store(n_irs: &mut Vec<Ir>, r: Reg) {
-    if !r.active() || !r.spill {
-        return;
+// Moves the spilled registers to their stack slots, given as offsets
+// from rbp. Every instruction reading one reloads it into a fresh
+// register first, and every instruction writing one stores the fresh
+// register after. The fresh registers live for a single instruction, so
+// they are never spilled themselves. Call arguments are read from the
+// slots directly.
+fn spill(fun: &mut Function, offsets: &HashMap<i32, i32>, unspillable: &mut HashSet<i32>) {
+    for bb in &fun.bbs {
+        let irs = std::mem::take(&mut bb.borrow_mut().irs);
+        let mut n_irs = vec![];
+        for mut ir in irs {
+            let line = ir.line;
+            let mut loads = vec![];
+            let mut stores = vec![];
+            if let IrCall(_, args) = &mut ir.op {
+                for r in args.iter_mut() {
+                    if let Some(offset) = offsets.get(&r.vn) {
+                        *r = Reg::stack(*offset);
+                    }
+                }
+            }
+            let mut tmps: HashMap<i32, Reg> = HashMap::new();
+            // replaces a spilled register by its fresh one
+            let mut replace = |r: &mut Reg| -> Option<(Reg, i32)> {
+                let offset = *offsets.get(&r.vn)?;
+                let tmp = tmps.entry(r.vn).or_insert_with(|| {
+                    let mut tmp = fun.new_reg();
+                    tmp.spill = true;
+                    tmp.spill_offset = offset;
+                    unspillable.insert(tmp.vn);
+                    tmp
+                });
+                *r = tmp.clone();
+                Some((tmp.clone(), offset))
+            };
+            for r in ir.uses_mut() {
+                if let Some((tmp, offset)) = replace(r) {
+                    if !loads.iter().any(|load: &Ir| load.r0.vn == tmp.vn) {
+                        loads.push(spill_ir(IrLoadSpill, tmp, Reg::dummy(), offset, line));
+                    }
+                }
+            }
+            for r in ir.defs_mut() {
+                if let Some((tmp, offset)) = replace(r) {
+                    stores.push(spill_ir(IrStoreSpill, Reg::dummy(), tmp, offset, line));
+                }
+            }
+            n_irs.append(&mut loads);
+            n_irs.push(ir);
+            n_irs.append(&mut stores);
+        }
+        bb.borrow_mut().irs = n_irs;
     }
-    let spill_offset = r.spill_offset;
-    n_irs.push(Ir::new(
-        IrStoreSpill,
-        Reg::dummy(),
-        r,
-        Reg::dummy(),
-        Reg::dummy(),
-        None,
-        None,
-        spill_offset,
-        -1,
-    ));
 }
 
-pub fn alloc_regs(program: &mut Program) {
-    // make three address form and register settings
-    for fun in &mut program.funs {
-        for bb in &mut fun.bbs {
-            three_two(bb);
-        }
-        let mut borned_map = LinkedHashMap::new();
-        let mut died_map = HashMap::new();
-        let mut ic = 1;
-        for bb in &fun.bbs {
-            liveness::regs_life(bb, &mut ic, &mut borned_map, &mut died_map);
+// Allocates the registers of the function and returns the number of
+// stack slots spilled registers took.
+fn alloc_fun(fun: &mut Function) -> usize {
+    let iterative = *ITERATIVE_REGALLOC.lock().unwrap();
+    // a block parameter is written by the jumps to the block, which
+    // can't store it, so it is kept in a register
+    let mut unspillable: HashSet<i32> = fun
+        .bbs
+        .iter()
+        .map(|bb| bb.borrow().param.vn)
+        .filter(|vn| *vn > 0)
+        .collect();
+    let mut slots = 0;
+    fun.resume_regno();
+    let reg_map: HashMap<i32, i32> = loop {
+        let mut reglifes = liveness::live_ranges(fun);
+        let colors = precolor(fun, &reglifes);
+        reglifes.retain(|life| !colors.contains_key(&life.vn));
+        let spilled = if iterative {
+            match assign(&reglifes, &unspillable) {
+                Ok(reg_map) => break reg_map.into_iter().chain(colors).collect(),
+                Err(vn) => HashMap::from([(vn, 0)]),
+            }
+        } else {
+            match linear_scan(&reglifes, &unspillable) {
+                (reg_map, spilled) if spilled.is_empty() => {
+                    break reg_map.into_iter().chain(colors).collect()
+                }
+                (_, spilled) => spilled,
+            }
+        };
+        // slots are taken above the ones of the previous rounds
+        let base = roundup(fun.stacksize, 8);
+        let n = spilled.values().max().unwrap() + 1;
+        fun.stacksize = base + 8 * n as i32;
+        slots += n;
+        let offsets = spilled
+            .into_iter()
+            .map(|(vn, slot)| (vn, base + 8 * (slot as i32 + 1)))
+            .collect();
+        spill(fun, &offsets, &mut unspillable);
+    };
+    let saved: BTreeSet<usize> = reg_map
+        .values()
+        .map(|rn| *rn as usize)
+        .filter(|rn| (CALLEE_SAVED..REG_SIZE).contains(rn))
+        .collect();
+    fun.saved_regs = saved.into_iter().collect();
+    for bb in &fun.bbs {
+        let mut bb = bb.borrow_mut();
+        if bb.param.active() {
+            bb.param.rn = reg_map[&bb.param.vn];
         }
-        let mut reglifes = vec![];
-        for (vn, start) in borned_map {
-            reglifes.push(RegLife::new(vn, start, died_map[&vn]));
+        for ir in &mut bb.irs {
+            for r in ir.defs_mut() {
+                r.rn = reg_map[&r.vn];
+            }
+            for r in ir.uses_mut() {
+                r.rn = reg_map[&r.vn];
+            }
         }
-        regs_setting(fun, reglifes);
     }
+    slots
+}
 
-    // add spill instruction of load or store
+// make two address form
+pub fn to_two_address(program: &mut Program) {
     for fun in &mut program.funs {
         for bb in &mut fun.bbs {
-            let irs = std::mem::replace(&mut bb.borrow_mut().irs, vec![]);
-            let mut n_irs = vec![];
-            for ir in irs {
-                spillout_load(&mut n_irs, &ir.r1);
-                spillout_load(&mut n_irs, &ir.r2);
-                spillout_load(&mut n_irs, &ir.bbarg);
-                let r0 = ir.r0.clone();
-                n_irs.push(ir);
-                spillout_store(&mut n_irs, r0);
-            }
-            bb.borrow_mut().irs = n_irs;
+            three_two(bb);
         }
     }
 }
+
+// Returns the number of stack slots taken by spilled registers.
+pub fn alloc_regs(program: &mut Program) -> usize {
+    let mut slots = 0;
+    for fun in &mut program.funs {
+        slots += alloc_fun(fun);
+    }
+    slots
+}
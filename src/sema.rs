@@ -1,7 +1,8 @@
+use super::mir::*;
 use super::parse::{NodeType::*, INT_TY, *};
 use super::token::TokenType::*;
-// use super::lib::*;
-use super::mir::*;
+use super::*;
+use std::collections::{HashMap, HashSet};
 
 // Semantics analyzer. This pass plays a few important roles as shown
 // below:
@@ -15,6 +16,9 @@ use super::mir::*;
 //   "pointer to T" in most contexts.
 //
 // - Reject bad assignments, such as `1=2+3`.
+//
+// Errors are recorded rather than aborting the walk, so that every
+// error in the program can be reported at once.
 
 pub fn maybe_decay(node: Node, decay: bool) -> Node {
     let ctype = node.nodesctype(None);
@@ -25,6 +29,8 @@ pub fn maybe_decay(node: Node, decay: bool) -> Node {
                 node,
             );
         }
+        // a function designator is a pointer to the function
+        Ty::FUNC if decay => Node::new_addr(ctype.ptr_to(), node),
         _ => {
             return node;
         }
@@ -36,9 +42,10 @@ pub fn binwalk(
     lhs: &Node,
     rhs: &Node,
     lhs_or_rhs: i32,
+    ctx: &mut Context,
 ) -> Node {
-    let lhs2 = walk(lhs);
-    let rhs2 = walk(rhs);
+    let lhs2 = walk(lhs, ctx);
+    let rhs2 = walk(rhs, ctx);
     if lhs_or_rhs == -1 {
         return f(lhs2.nodesctype(Some(INT_TY.clone())), lhs2, rhs2);
     } else {
@@ -46,12 +53,50 @@ pub fn binwalk(
     }
 }
 
-pub fn walk(node: &Node) -> Node {
-    return do_walk(node, true);
+// Walks the body of a loop or switch, counted in `depth` while it is
+// walked.
+fn walk_body(depth: fn(&mut Context) -> &mut i32, body: &Node, ctx: &mut Context) -> Node {
+    *depth(ctx) += 1;
+    let body2 = walk(body, ctx);
+    *depth(ctx) -= 1;
+    body2
+}
+
+// Records a semantic error at the given node.
+fn report(node: &Node, msg: &str, ctx: &mut Context) {
+    ctx.errors.push(node.error(String::from(msg)));
+}
+
+// Checks that the node can be assigned to. Arrays and functions are
+// lvalues of a sort but can't be modified.
+fn check_lval(node: &Node, ctx: &mut Context) {
+    if let Err(e) = node.checklval() {
+        ctx.errors.push(e);
+        return;
+    }
+    if let VarRef(var) = &node.op {
+        let name = match &node.token {
+            Some(token) => token.text(),
+            None => String::new(),
+        };
+        if var.ctype.ty == Ty::ARY {
+            report(
+                node,
+                &format!("cannot assign to '{}' of array type '{}'", name, var.ctype),
+                ctx,
+            );
+        } else if var.ctype.ty == Ty::FUNC {
+            report(node, &format!("cannot assign to function '{}'", name), ctx);
+        }
+    }
+}
+
+pub fn walk(node: &Node, ctx: &mut Context) -> Node {
+    return do_walk(node, true, ctx);
 }
 
-pub fn walk_nodecay(node: &Node) -> Node {
-    return do_walk(node, false);
+pub fn walk_nodecay(node: &Node, ctx: &mut Context) -> Node {
+    return do_walk(node, false, ctx);
 }
 
 fn bin_ptr_swap(ctype: &mut Type, lhs: &mut Node, rhs: &mut Node) {
@@ -65,7 +110,7 @@ fn bin_ptr_swap(ctype: &mut Type, lhs: &mut Node, rhs: &mut Node) {
             return;
         }
     }
-    let scale_ptr = ctype.ptr_to.as_ref().unwrap().size as i32;
+    let scale_ptr = ctype.stride();
     *rhs = Node::new_bit(
         INT_TY.clone(),
         TokenStar,
@@ -74,12 +119,12 @@ fn bin_ptr_swap(ctype: &mut Type, lhs: &mut Node, rhs: &mut Node) {
     );
 }
 
-fn same_type(ty1: Type, ty2: Type) -> bool {
+pub fn same_type(ty1: Type, ty2: Type) -> bool {
     if ty1.ty != ty2.ty {
         return false;
     }
     match ty1.ty {
-        Ty::PTR => {
+        Ty::PTR | Ty::FUNC => {
             return same_type(*ty1.ptr_to.unwrap(), *ty2.ptr_to.unwrap());
         }
         Ty::ARY => {
@@ -91,33 +136,236 @@ fn same_type(ty1: Type, ty2: Type) -> bool {
     }
 }
 
-pub fn get_type(node: &Node) -> Type {
-    return walk_nodecay(node).nodesctype(None);
+pub fn get_type(node: &Node, ctx: &mut Context) -> Type {
+    let mut node = walk_nodecay(node, ctx);
+    annotate_types(&mut node);
+    node.nodesctype(None)
 }
 
-fn check_int(node: &Node) {
+fn check_int(node: &Node, ctx: &mut Context) {
     let ctype = node.nodesctype(None);
-    if ctype.ty != Ty::INT && ctype.ty != Ty::CHAR && ctype.ty != Ty::BOOL {
-        panic!("{:?} is not an Integer.", node);
+    if !is_integer(&ctype) {
+        report(node, "operand must be an integer", ctx);
+    }
+}
+
+fn check_void(node: &Node, ctx: &mut Context) {
+    if let Ty::VOID = node.nodesctype(Some(INT_TY.clone())).ty {
+        report(node, "void value not ignored as it ought to be", ctx);
+    }
+}
+
+// Enums behave as int in arithmetic.
+fn is_integer(ty: &Type) -> bool {
+    matches!(ty.ty, Ty::INT | Ty::CHAR | Ty::BOOL | Ty::ENUM(..))
+}
+
+// Checks that `rhs` can be assigned to an object of type `lty`.
+// Mixing integers and pointers only warns, as in most C compilers, but
+// pointers to different types can't be mixed unless one is `void *`.
+fn check_assign(node: &Node, lty: &Type, rhs: &Node, ctx: &mut Context) {
+    let rty = rhs.nodesctype(Some(INT_TY.clone()));
+    match (&lty.ty, &rty.ty) {
+        (Ty::PTR, _) if is_integer(&rty) => {
+            // a constant 0 is a null pointer constant.
+            if !*WARN_INT_CONVERSION.lock().unwrap() || eval(rhs) == Some(0) {
+                return;
+            }
+            node.warn(&format!(
+                "incompatible integer to pointer conversion assigning to '{}' from '{}'",
+                lty, rty
+            ));
+        }
+        (_, Ty::PTR) if is_integer(lty) && lty.ty != Ty::BOOL => {
+            if !*WARN_INT_CONVERSION.lock().unwrap() {
+                return;
+            }
+            node.warn(&format!(
+                "incompatible pointer to integer conversion assigning to '{}' from '{}'",
+                lty, rty
+            ));
+        }
+        (Ty::PTR, Ty::PTR) => {
+            let lto = lty.ptr_to.as_ref().unwrap();
+            let rto = rty.ptr_to.as_ref().unwrap();
+            if lto.ty == Ty::VOID || rto.ty == Ty::VOID || same_type(lty.clone(), rty.clone()) {
+                return;
+            }
+            report(
+                node,
+                &format!(
+                    "incompatible pointer types assigning to '{}' from '{}'",
+                    lty, rty
+                ),
+                ctx,
+            );
+        }
+        // structs are assigned only from a struct of the same tag
+        (Ty::STRUCT(..), _) | (_, Ty::STRUCT(..)) if lty.ty != rty.ty => {
+            report(
+                node,
+                &format!("incompatible types assigning to '{}' from '{}'", lty, rty),
+                ctx,
+            );
+        }
+        (Ty::STRUCT(..), _) if rhs.checklval().is_err() && !matches!(rhs.op, Assign(..)) => {
+            report(node, "cannot assign a struct which is not in memory", ctx);
+        }
+        (Ty::ENUM(..), Ty::ENUM(..)) if lty.ty != rty.ty => {
+            node.warn(&format!(
+                "implicit conversion from '{}' to different enumeration type '{}'",
+                rty, lty
+            ));
+        }
+        _ => {}
+    }
+}
+
+// Warns about the enumerators that no case label of a switch over an
+// enum covers. A switch with a `default` label is not checked.
+fn check_switch_cover(node: &Node, cond: &Node, case_conds: &[Node]) {
+    if let Ty::ENUM(_, members) = cond.nodesctype(None).ty {
+        let values: Vec<i32> = case_conds.iter().filter_map(eval).collect();
+        let missing: Vec<String> = members
+            .iter()
+            .filter(|(_, val)| !values.contains(val))
+            .map(|(name, _)| format!("'{}'", name))
+            .collect();
+        if !missing.is_empty() {
+            node.warn(&format!(
+                "enumeration values not handled in switch: {}",
+                missing.join(", ")
+            ));
+        }
+    }
+}
+
+// The values an operand of a type narrower than int can hold.
+fn int_range(ctype: &Type) -> Option<(i32, i32)> {
+    match ctype.ty {
+        Ty::CHAR => Some((i8::MIN as i32, i8::MAX as i32)),
+        Ty::BOOL => Some((0, 1)),
+        _ => None,
+    }
+}
+
+// Checks the case labels of a switch over a char or _Bool operand. A label
+// outside the range of the operand type can never match, and two labels
+// that are equal once converted to that type are rejected.
+fn check_case_range(cond: &Node, case_conds: &[Node], ctx: &mut Context) {
+    let ctype = cond.nodesctype(None);
+    let (min, max) = match int_range(&ctype) {
+        Some(range) => range,
+        None => {
+            return;
+        }
+    };
+    let mut seen: HashMap<i32, i32> = HashMap::new();
+    for case in case_conds {
+        let val = match eval(case) {
+            Some(val) => val,
+            None => {
+                continue;
+            }
+        };
+        if val < min || val > max {
+            case.warn(&format!(
+                "case value {} is out of range of '{}' ({}..{}) and can never match",
+                val, ctype, min, max
+            ));
+        }
+        let converted = match ctype.ty {
+            Ty::BOOL => (val != 0) as i32,
+            _ => val as i8 as i32,
+        };
+        match seen.insert(converted, val) {
+            // exact duplicates are rejected by the parser already
+            Some(prev) if prev == val => {}
+            Some(prev) => {
+                report(
+                    case,
+                    &format!(
+                        "duplicate case value {} after conversion to '{}' (previous label was {})",
+                        converted, ctype, prev
+                    ),
+                    ctx,
+                );
+            }
+            None => {}
+        }
+    }
+}
+
+// Returns true if control never reaches the end of the statement.
+fn no_fallthrough(node: &Node) -> bool {
+    match &node.op {
+        Ret(_) | Break | Continue => true,
+        // a case label only starts the statement it is attached to.
+        Case(_, body) | Default(body) => no_fallthrough(body),
+        IfThen(_, then, Some(els)) => no_fallthrough(then) && no_fallthrough(els),
+        CompStmt(stmts) => {
+            let mut dead = false;
+            for stmt in stmts {
+                if let Case(..) | Default(_) = stmt.op {
+                    dead = false;
+                }
+                dead = dead || no_fallthrough(stmt);
+            }
+            dead
+        }
+        _ => false,
+    }
+}
+
+// Warns about the first statement of a block that follows a return,
+// break or continue. Case labels make the code after them reachable
+// again, so switch fallthrough is not reported.
+fn warn_unreachable(stmts: &[Node]) {
+    let mut dead = false;
+    for stmt in stmts {
+        if let Case(..) | Default(_) = stmt.op {
+            dead = false;
+        }
+        if dead && stmt.op != NULL {
+            stmt.warn("code will never be executed");
+            return;
+        }
+        dead = dead || no_fallthrough(stmt);
     }
 }
 
-pub fn do_walk(node: &Node, decay: bool) -> Node {
+pub fn do_walk(node: &Node, decay: bool, ctx: &mut Context) -> Node {
+    // nodes rebuilt by sema keep the position of the original node.
+    walk_node(node, decay, ctx).with_token_of(node)
+}
+
+fn walk_node(node: &Node, decay: bool, ctx: &mut Context) -> Node {
     match &node.op {
         Num(val) => {
             return Node::new_num(*val);
         }
         BinaryTree(_, op, lhs, rhs) => {
-            let mut lhs2 = walk(lhs);
-            let mut rhs2 = walk(rhs);
+            let mut lhs2 = walk(lhs, ctx);
+            let mut rhs2 = walk(rhs, ctx);
+            check_void(&lhs2, ctx);
+            check_void(&rhs2, ctx);
             let mut ctype = INT_TY.clone();
             ctype = lhs2.nodesctype(Some(ctype));
+            if (*op == TokenDiv || *op == TokenMod) && eval(&rhs2) == Some(0) {
+                if *op == TokenDiv {
+                    node.warn("division by zero is undefined");
+                } else {
+                    node.warn("remainder by zero is undefined");
+                }
+            }
             match op {
                 TokenAdd => {
-                    bin_ptr_swap(&mut ctype, &mut lhs2, &mut rhs2);
-                    if let Ty::PTR = rhs2.nodesctype(None).ty {
-                        panic!("pointer + pointer is not defined.");
+                    // checked before the swap scales the right operand
+                    if let (Ty::PTR, Ty::PTR) = (lhs2.nodesctype(None).ty, rhs2.nodesctype(None).ty)
+                    {
+                        report(node, "pointer + pointer is not defined", ctx);
                     }
+                    bin_ptr_swap(&mut ctype, &mut lhs2, &mut rhs2);
                     return Node::new_bit(ctype, op.clone(), lhs2, rhs2);
                 }
                 TokenSub => {
@@ -126,11 +374,20 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
                     match (&lty.ty, &rty.ty) {
                         (Ty::PTR, Ty::PTR) => {
                             if !same_type(lty, rty) {
-                                panic!("both type of operand of ptr - ptr should be same")
+                                report(
+                                    node,
+                                    "both operands of ptr - ptr must have the same type",
+                                    ctx,
+                                );
                             }
-                            let node = Node::new_bit(ctype.clone(), TokenSub, lhs2, rhs2);
-                            let scale_ptr = ctype.ptr_to.as_ref().unwrap().size as i32;
-                            return Node::new_bit(ctype, TokenDiv, node, Node::new_num(scale_ptr));
+                            let node = Node::new_bit(INT_TY.clone(), TokenSub, lhs2, rhs2);
+                            let scale_ptr = ctype.stride();
+                            return Node::new_bit(
+                                INT_TY.clone(),
+                                TokenDiv,
+                                node,
+                                Node::new_num(scale_ptr),
+                            );
                         }
                         _ => {
                             bin_ptr_swap(&mut ctype, &mut lhs2, &mut rhs2);
@@ -139,31 +396,34 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
                     return Node::new_bit(ctype, op.clone(), lhs2, rhs2);
                 }
                 _ => {
-                    check_int(&lhs2);
-                    check_int(&rhs2);
+                    check_int(&lhs2, ctx);
+                    check_int(&rhs2, ctx);
                     return Node::new_bit(ctype, op.clone(), lhs2, rhs2);
                 }
             }
         }
         Ret(lhs) => {
-            return Node::new_ret(walk(lhs));
+            return Node::new_ret(walk(lhs, ctx));
         }
         Expr(lhs) => {
-            return Node::new_expr(walk(lhs));
+            return Node::new_expr(walk(lhs, ctx));
         }
         CompStmt(lhsv) => {
             let mut v = vec![];
             for lhs in lhsv {
-                v.push(walk(lhs));
+                v.push(walk(lhs, ctx));
+            }
+            if *WARN_UNREACHABLE_CODE.lock().unwrap() {
+                warn_unreachable(lhsv);
             }
             return Node::new_stmt(v);
         }
         StmtExpr(_, body) => {
             let mut ctype = VOID_TY.clone();
-            let body = walk(body);
+            let body = walk(body, ctx);
             if let NodeType::CompStmt(stmts) = &body.op {
-                if let Some(node) = stmts.last() {
-                    ctype = node.nodesctype(None);
+                if let Some(Node { op: Expr(expr), .. }) = stmts.last() {
+                    ctype = expr.nodesctype(None);
                 }
             } else {
                 panic!("body.op of StmtExpr must be CompStmt.");
@@ -175,12 +435,14 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
         }
         Assign(_, lhs, rhs) => {
             if let NodeType::ArrIni(_) = rhs.op {
-                return walk(rhs);
+                return walk(rhs, ctx);
             }
-            let lhs_ = walk_nodecay(lhs);
-            lhs_.checklval();
-            let mut rhs_ = walk(rhs);
+            let lhs_ = walk_nodecay(lhs, ctx);
+            check_lval(&lhs_, ctx);
+            let mut rhs_ = walk(rhs, ctx);
+            check_void(&rhs_, ctx);
             let lty_ = lhs_.nodesctype(None);
+            check_assign(node, &lty_, &rhs_, ctx);
             if lty_.ty == Ty::BOOL {
                 rhs_ = Node::new_cast(BOOL_TY.clone(), rhs_);
             }
@@ -188,31 +450,50 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
         }
         IfThen(cond, then, elthen) => match elthen {
             Some(elth) => {
-                return Node::new_if(walk(cond), walk(then), Some(walk(elth)));
+                return Node::new_if(walk(cond, ctx), walk(then, ctx), Some(walk(elth, ctx)));
             }
             _ => {
-                return Node::new_if(walk(cond), walk(then), None);
+                return Node::new_if(walk(cond, ctx), walk(then, ctx), None);
             }
         },
         Call(ctype, name, args) => {
             let mut v = vec![];
             for arg in args {
-                v.push(walk(arg));
+                v.push(walk(arg, ctx));
             }
             return Node::new_call(ctype.clone(), name.clone(), v);
         }
+        CallPtr(_, fptr, args) => {
+            let fptr = walk(fptr, ctx);
+            let ctype = fptr.nodesctype(None);
+            let ret = match ctype.ptr_to.as_deref() {
+                Some(func) if ctype.ty == Ty::PTR && func.ty == Ty::FUNC => {
+                    func.ptr_to.as_deref().unwrap().clone()
+                }
+                _ => {
+                    report(node, "called object is not a function", ctx);
+                    INT_TY.clone()
+                }
+            };
+            let args = args.iter().map(|arg| walk(arg, ctx)).collect();
+            Node::new_callptr(ret, fptr, args)
+        }
         For(init, cond, inc, body) => {
-            return Node::new_for(walk(init), walk(cond), walk(inc), walk(body));
+            let (init2, cond2, inc2) = (walk(init, ctx), walk(cond, ctx), walk(inc, ctx));
+            Node::new_for(
+                init2,
+                cond2,
+                inc2,
+                walk_body(|ctx| &mut ctx.loop_depth, body, ctx),
+            )
         }
         Deref(_, lhs) => {
-            let lhs2 = walk(lhs);
+            let lhs2 = walk(lhs, ctx);
             let ctype = lhs2.nodesctype(None);
             match ctype.ty {
                 Ty::PTR => {
                     if let Ty::VOID = ctype.ptr_to.as_ref().unwrap().as_ref().ty {
-                        // error("cannot dereference void pointer.");
-                        // for debug.
-                        panic!("cannot dereference void pointer.");
+                        report(node, "cannot dereference void pointer", ctx);
                     }
                     return maybe_decay(
                         Node::new_deref(ctype.ptr_to.as_ref().unwrap().as_ref().clone(), lhs2),
@@ -220,75 +501,135 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
                     );
                 }
                 _ => {
-                    // error(&format!("operand must be a pointer."));
-                    // for debug.
-                    panic!("operand must be a pointer.");
+                    report(node, "operand must be a pointer", ctx);
+                    Node::new_deref(INT_TY.clone(), lhs2)
                 }
             }
         }
         Addr(_, lhs) => {
-            let lhs2 = walk(lhs);
-            lhs2.checklval();
+            // `&arr` points to the whole array, so the operand must not decay.
+            let lhs2 = walk_nodecay(lhs, ctx);
+            if lhs2.checklval().is_err() {
+                report(&lhs2, "cannot take the address of an rvalue", ctx);
+            }
+            if let VarRef(var) = &lhs2.op {
+                if var.is_register {
+                    node.warn(&format!(
+                        "address of register variable '{}' requested",
+                        lhs2.token.as_ref().map_or(String::new(), |t| t.text())
+                    ));
+                }
+            }
             return Node::new_addr(lhs2.nodesctype(None).ptr_to(), lhs2);
         }
         Equal(lhs, rhs) => {
-            return Node::new_equal(walk(lhs), walk(rhs));
+            return Node::new_equal(walk(lhs, ctx), walk(rhs, ctx));
         }
         Ne(lhs, rhs) => {
-            return Node::new_neq(walk(lhs), walk(rhs));
+            return Node::new_neq(walk(lhs, ctx), walk(rhs, ctx));
         }
         DoWhile(body, cond) => {
-            return Node::new_dowhile(walk(body), walk(cond));
+            let body2 = walk_body(|ctx| &mut ctx.loop_depth, body, ctx);
+            Node::new_dowhile(body2, walk(cond, ctx))
         }
-        Switch(cond, body, case_conds) => {
-            return Node::new_switch(walk(cond), walk(body), case_conds.clone());
+        Switch(cond, body, case_conds, has_default) => {
+            let cond2 = walk(cond, ctx);
+            check_case_range(&cond2, case_conds, ctx);
+            if *WARN_SWITCH.lock().unwrap() && !has_default {
+                check_switch_cover(node, &cond2, case_conds);
+            }
+            let body2 = walk_body(|ctx| &mut ctx.switch_depth, body, ctx);
+            Node::new_switch(cond2, body2, case_conds.clone(), *has_default)
+        }
+        Cast(ctype, expr) => {
+            let expr2 = walk(expr, ctx);
+            let from = expr2.nodesctype(None);
+            if ctype.ty != Ty::VOID && !(is_scalar(ctype) && is_scalar(&from)) {
+                report(
+                    node,
+                    &format!("invalid cast from '{}' to '{}'", from, ctype),
+                    ctx,
+                );
+            }
+            Node::new_cast(ctype.clone(), expr2)
         }
         Case(val, body) => {
-            return Node::new_case(*val.clone(), walk(body));
+            return Node::new_case(*val.clone(), walk(body, ctx));
         }
+        Default(body) => Node::new_default(walk(body, ctx)),
         Dot(_, expr, name) => {
-            let expr2 = walk(expr);
-            match expr2.nodesctype(None).ty {
+            let expr2 = walk(expr, ctx);
+            let struct_type = expr2.nodesctype(None);
+            match &struct_type.ty {
                 Ty::STRUCT(_, mb_map) => {
+                    // the member type carries its offset in the struct,
+                    // which gen_lval adds to the struct address.
                     if let Some(ctype) = mb_map.get(name) {
                         let lhs = Node::new_dot(ctype.clone(), expr2, name.clone());
                         return maybe_decay(lhs, decay);
                     }
-                    // error(&format!("member missing."));
-                    // for debug.
-                    panic!("member missing.");
+                    if mb_map.is_empty() {
+                        report(
+                            node,
+                            &format!("member access into incomplete type '{}'", struct_type),
+                            ctx,
+                        );
+                        return Node::new_dot(INT_TY.clone(), expr2, name.clone());
+                    }
+                    let members: Vec<String> =
+                        mb_map.keys().map(|mb| format!("'{}'", mb)).collect();
+                    report(
+                        node,
+                        &format!(
+                            "no member named '{}' in '{}'; members are: {}",
+                            name,
+                            struct_type,
+                            members.join(", ")
+                        ),
+                        ctx,
+                    );
                 }
                 _ => {
-                    // error(&format!("struct expected before ."));
-                    // for debug.
-                    panic!("struct expected before . {:?}", expr2.clone());
+                    report(
+                        node,
+                        &format!("request for member '{}' in something not a structure", name),
+                        ctx,
+                    );
                 }
             }
+            Node::new_dot(INT_TY.clone(), expr2, name.clone())
         }
         Not(expr) => {
-            let expr2 = walk(expr);
+            let expr2 = walk(expr, ctx);
+            check_void(&expr2, ctx);
             return Node::new_not(expr2);
         }
         Ternary(_, cond, then, els) => {
-            let cond2 = walk(cond);
-            let then2 = walk(then);
-            let els2 = walk(els);
-            return Node::new_ternary(then2.nodesctype(Some(INT_TY.clone())), cond2, then2, els2);
+            let cond2 = walk(cond, ctx);
+            check_void(&cond2, ctx);
+            let then2 = walk(then, ctx);
+            let els2 = walk(els, ctx);
+            // `a ?: b` has the type of `a`.
+            let ctype = match then2.op {
+                NULL => cond2.nodesctype(Some(INT_TY.clone())),
+                _ => then2.nodesctype(Some(INT_TY.clone())),
+            };
+            Node::new_ternary(ctype, cond2, then2, els2)
         }
         TupleExpr(_, lhs, rhs) => {
-            return binwalk(Node::new_tuple, lhs, rhs, 1);
+            return binwalk(Node::new_tuple, lhs, rhs, 1, ctx);
         }
         IncDec(_, selector, expr) => {
-            let lhs = walk(expr);
-            lhs.checklval();
+            let lhs = walk(expr, ctx);
+            check_lval(&lhs, ctx);
             return Node::new_incdec(lhs.nodesctype(None), *selector, lhs);
         }
         ArrIni(arrini) => {
             let mut new_arrini = vec![];
             for (lhs, rhs) in arrini {
-                let lhs2 = walk_nodecay(lhs);
-                lhs2.checklval();
-                let mut rhs2 = walk(rhs);
+                let lhs2 = walk_nodecay(lhs, ctx);
+                check_lval(&lhs2, ctx);
+                let mut rhs2 = walk(rhs, ctx);
                 let lty = lhs2.nodesctype(None);
                 if lty.ty == Ty::BOOL {
                     rhs2 = Node::new_cast(BOOL_TY.clone(), rhs2);
@@ -297,7 +638,20 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
             }
             return Node::new_arrini(new_arrini);
         }
-        Break | Continue => {
+        Break => {
+            if ctx.loop_depth + ctx.switch_depth == 0 {
+                report(
+                    node,
+                    "'break' statement not in loop or switch statement",
+                    ctx,
+                );
+            }
+            node.clone()
+        }
+        Continue => {
+            if ctx.loop_depth == 0 {
+                report(node, "'continue' statement not in loop statement", ctx);
+            }
             return node.clone();
         }
         NULL => {
@@ -309,7 +663,402 @@ pub fn do_walk(node: &Node, decay: bool) -> Node {
     }
 }
 
-pub fn sema(program: &mut Program) {
+// Fills in the type of every expression node bottom-up. The parser
+// builds many nodes with NULL_TY and walk() only fixes up some of them,
+// so this pass makes the final types independent of how a node was built.
+pub fn annotate_types(node: &mut Node) {
+    match &mut node.op {
+        BinaryTree(ctype, op, lhs, rhs) => {
+            annotate_types(lhs);
+            annotate_types(rhs);
+            let lty = lhs.nodesctype(None);
+            let rty = rhs.nodesctype(None);
+            *ctype = match op {
+                TokenAdd if lty.ty == Ty::PTR => lty,
+                TokenAdd if rty.ty == Ty::PTR => rty,
+                TokenSub if lty.ty == Ty::PTR && rty.ty != Ty::PTR => lty,
+                _ => INT_TY.clone(),
+            };
+        }
+        Assign(ctype, lhs, rhs) => {
+            annotate_types(lhs);
+            annotate_types(rhs);
+            *ctype = lhs.nodesctype(None);
+        }
+        Deref(ctype, lhs) => {
+            annotate_types(lhs);
+            let lty = lhs.nodesctype(None);
+            if let Some(ptr_to) = lty.ptr_to {
+                *ctype = *ptr_to;
+            }
+        }
+        // an array decays into a pointer to its first element, so the
+        // type set by walk() is kept.
+        Addr(ctype, lhs) => {
+            annotate_types(lhs);
+            if ctype.ty == Ty::NULL {
+                *ctype = lhs.nodesctype(None).ptr_to();
+            }
+        }
+        Ternary(ctype, cond, then, els) => {
+            annotate_types(cond);
+            annotate_types(then);
+            annotate_types(els);
+            let tty = match then.op {
+                NULL => cond.nodesctype(None),
+                _ => then.nodesctype(None),
+            };
+            let ety = els.nodesctype(None);
+            *ctype = if tty.ty != Ty::PTR && ety.ty == Ty::PTR {
+                ety
+            } else {
+                tty
+            };
+        }
+        TupleExpr(ctype, lhs, rhs) => {
+            annotate_types(lhs);
+            annotate_types(rhs);
+            *ctype = rhs.nodesctype(None);
+        }
+        IncDec(ctype, _, lhs) => {
+            annotate_types(lhs);
+            *ctype = lhs.nodesctype(None);
+        }
+        StmtExpr(ctype, body) => {
+            annotate_types(body);
+            *ctype = VOID_TY.clone();
+            if let CompStmt(stmts) = &body.op {
+                if let Some(Node { op: Expr(expr), .. }) = stmts.last() {
+                    *ctype = expr.nodesctype(None);
+                }
+            }
+        }
+        Dot(_, expr, _) | Cast(_, expr) | Not(expr) | Ret(expr) | Expr(expr) => {
+            annotate_types(expr);
+        }
+        Call(_, _, args) => {
+            for arg in args {
+                annotate_types(arg);
+            }
+        }
+        CallPtr(_, fptr, args) => {
+            annotate_types(fptr);
+            for arg in args {
+                annotate_types(arg);
+            }
+        }
+        Equal(lhs, rhs) | Ne(lhs, rhs) | DoWhile(lhs, rhs) => {
+            annotate_types(lhs);
+            annotate_types(rhs);
+        }
+        CompStmt(stmts) => {
+            for stmt in stmts {
+                annotate_types(stmt);
+            }
+        }
+        IfThen(cond, then, els) => {
+            annotate_types(cond);
+            annotate_types(then);
+            if let Some(els) = els {
+                annotate_types(els);
+            }
+        }
+        For(init, cond, inc, body) => {
+            annotate_types(init);
+            annotate_types(cond);
+            annotate_types(inc);
+            annotate_types(body);
+        }
+        Switch(cond, body, ..) => {
+            annotate_types(cond);
+            annotate_types(body);
+        }
+        Case(_, body) | Default(body) => {
+            annotate_types(body);
+        }
+        ArrIni(arrini) => {
+            for (lhs, rhs) in arrini {
+                annotate_types(lhs);
+                annotate_types(rhs);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The direct subexpressions and substatements of a node.
+fn children_mut(node: &mut Node) -> Vec<&mut Node> {
+    match &mut node.op {
+        BinaryTree(_, _, lhs, rhs)
+        | Assign(_, lhs, rhs)
+        | TupleExpr(_, lhs, rhs)
+        | Equal(lhs, rhs)
+        | Ne(lhs, rhs)
+        | DoWhile(lhs, rhs) => {
+            vec![lhs, rhs]
+        }
+        Deref(_, lhs)
+        | Addr(_, lhs)
+        | IncDec(_, _, lhs)
+        | StmtExpr(_, lhs)
+        | Dot(_, lhs, _)
+        | Cast(_, lhs)
+        | Not(lhs)
+        | Ret(lhs)
+        | Expr(lhs)
+        | Case(_, lhs)
+        | Default(lhs) => {
+            vec![lhs]
+        }
+        Ternary(_, cond, then, els) => {
+            vec![cond, then, els]
+        }
+        IfThen(cond, then, els) => {
+            let mut v: Vec<&mut Node> = vec![cond, then];
+            if let Some(els) = els {
+                v.push(els);
+            }
+            v
+        }
+        For(init, cond, inc, body) => {
+            vec![init, cond, inc, body]
+        }
+        Switch(cond, body, ..) => {
+            vec![cond, body]
+        }
+        Call(_, _, stmts) | CompStmt(stmts) => stmts.iter_mut().collect(),
+        CallPtr(_, fptr, args) => {
+            let mut children = vec![&mut **fptr];
+            children.extend(args.iter_mut());
+            children
+        }
+        ArrIni(arrini) => arrini
+            .iter_mut()
+            .flat_map(|(lhs, rhs)| vec![lhs, rhs])
+            .collect(),
+        _ => {
+            vec![]
+        }
+    }
+}
+
+// Whether evaluating the node may do more than compute a value.
+pub fn has_side_effects(node: &Node) -> bool {
+    match &node.op {
+        Num(_) => false,
+        VarRef(var) => var.ctype.is_volatile,
+        Deref(ctype, expr) => ctype.is_volatile || has_side_effects(expr),
+        Addr(_, expr) | Not(expr) | Cast(_, expr) | Dot(_, expr, _) => has_side_effects(expr),
+        BinaryTree(_, _, lhs, rhs) | Equal(lhs, rhs) | Ne(lhs, rhs) => {
+            has_side_effects(lhs) || has_side_effects(rhs)
+        }
+        Ternary(_, cond, then, els) => {
+            has_side_effects(cond) || has_side_effects(then) || has_side_effects(els)
+        }
+        _ => true,
+    }
+}
+
+// Whether the two nodes compute the same value, provided neither has side
+// effects.
+fn same_value(a: &Node, b: &Node) -> bool {
+    match (&a.op, &b.op) {
+        (Num(x), Num(y)) => x == y,
+        (VarRef(x), VarRef(y)) => {
+            x.is_local == y.is_local && x.offset == y.offset && x.labelname == y.labelname
+        }
+        (Deref(_, x), Deref(_, y)) | (Addr(_, x), Addr(_, y)) | (Not(x), Not(y)) => {
+            same_value(x, y)
+        }
+        (Cast(xty, x), Cast(yty, y)) => xty == yty && same_value(x, y),
+        (Dot(_, x, xm), Dot(_, y, ym)) => xm == ym && same_value(x, y),
+        (BinaryTree(_, xop, xl, xr), BinaryTree(_, yop, yl, yr)) => {
+            xop == yop && same_value(xl, yl) && same_value(xr, yr)
+        }
+        _ => false,
+    }
+}
+
+// Folds binary nodes whose operands are constants, and simplifies the
+// identities `x + 0`, `x - 0`, `x * 1`, `x / 1` to `x` and `x * 0` to 0,
+// and `x - x`, `x ^ x` to 0 and `x & x`, `x | x` to `x`. These are kept
+// when evaluating `x` has side effects. This runs after sema so that e.g.
+// `x + 0 = 1` is still rejected.
+fn fold(node: &mut Node) {
+    for child in children_mut(node) {
+        fold(child);
+    }
+    let folded = match &node.op {
+        BinaryTree(..) if eval(node).is_some() => Node::new_num(eval(node).unwrap()),
+        BinaryTree(_, op, lhs, rhs) => match (op, eval(lhs), eval(rhs)) {
+            (TokenAdd, _, Some(0)) | (TokenSub, _, Some(0)) => *lhs.clone(),
+            (TokenAdd, Some(0), _) => *rhs.clone(),
+            (TokenStar, _, Some(1)) | (TokenDiv, _, Some(1)) => *lhs.clone(),
+            (TokenStar, Some(1), _) => *rhs.clone(),
+            (TokenStar, _, Some(0)) if !has_side_effects(lhs) => Node::new_num(0),
+            (TokenStar, Some(0), _) if !has_side_effects(rhs) => Node::new_num(0),
+            (TokenSub | TokenXor, _, _) if !has_side_effects(lhs) && same_value(lhs, rhs) => {
+                Node::new_num(0)
+            }
+            (TokenAmpersand | TokenOr, _, _) if !has_side_effects(lhs) && same_value(lhs, rhs) => {
+                *lhs.clone()
+            }
+            _ => {
+                return;
+            }
+        },
+        _ => {
+            return;
+        }
+    };
+    *node = folded.with_token_of(node);
+}
+
+// Folds the bodies of every function, from -O1.
+pub fn fold_constants(program: &mut Program) {
+    for node in &mut program.nodes {
+        if let Func(_, _, _, body, _) = &mut node.op {
+            fold(body);
+        }
+    }
+}
+
+fn is_scalar(ctype: &Type) -> bool {
+    is_integer(ctype) || ctype.ty == Ty::PTR
+}
+
+struct UninitState {
+    // locals assigned on some path seen so far.
+    assigned: HashSet<i32>,
+    // locals already reported.
+    warned: HashSet<i32>,
+}
+
+// Best-effort definite assignment check for -Wuninitialized. `init` holds
+// the stack offsets of the scalar locals assigned on every path reaching
+// `node`. A local whose address is taken is assumed to be assigned through
+// the pointer, and assignments inside loop bodies and switch statements
+// are not trusted after them.
+fn check_uninit(node: &Node, init: &mut HashSet<i32>, uninit: &mut UninitState) {
+    match &node.op {
+        VarRef(var)
+            if var.is_local
+                && var.offset != 0
+                && is_scalar(&var.ctype)
+                && !init.contains(&var.offset)
+                && uninit.warned.insert(var.offset) =>
+        {
+            let name = match &node.token {
+                Some(token) => token.text(),
+                None => String::from("?"),
+            };
+            if uninit.assigned.contains(&var.offset) {
+                node.warn(&format!("variable '{}' may be used uninitialized", name));
+            } else {
+                node.warn(&format!("variable '{}' is used uninitialized", name));
+            }
+        }
+        Assign(_, lhs, rhs) => {
+            check_uninit(rhs, init, uninit);
+            match &lhs.op {
+                VarRef(var) => {
+                    init.insert(var.offset);
+                    uninit.assigned.insert(var.offset);
+                }
+                _ => {
+                    check_uninit(lhs, init, uninit);
+                }
+            }
+        }
+        Addr(_, lhs) => match &lhs.op {
+            VarRef(var) => {
+                init.insert(var.offset);
+                uninit.assigned.insert(var.offset);
+            }
+            _ => {
+                check_uninit(lhs, init, uninit);
+            }
+        },
+        IncDec(_, _, lhs) => {
+            check_uninit(lhs, init, uninit);
+        }
+        BinaryTree(_, TokenLogAnd, lhs, rhs) | BinaryTree(_, TokenLogOr, lhs, rhs) => {
+            check_uninit(lhs, init, uninit);
+            check_uninit(rhs, &mut init.clone(), uninit);
+        }
+        BinaryTree(_, _, lhs, rhs) | TupleExpr(_, lhs, rhs) | Equal(lhs, rhs) | Ne(lhs, rhs) => {
+            check_uninit(lhs, init, uninit);
+            check_uninit(rhs, init, uninit);
+        }
+        Ternary(_, cond, then, els) => {
+            check_uninit(cond, init, uninit);
+            let mut els_init = init.clone();
+            check_uninit(then, init, uninit);
+            check_uninit(els, &mut els_init, uninit);
+            init.retain(|offset| els_init.contains(offset));
+        }
+        IfThen(cond, then, els) => {
+            check_uninit(cond, init, uninit);
+            let mut els_init = init.clone();
+            check_uninit(then, init, uninit);
+            if let Some(els) = els {
+                check_uninit(els, &mut els_init, uninit);
+            }
+            init.retain(|offset| els_init.contains(offset));
+        }
+        For(init_stmt, cond, inc, body) => {
+            check_uninit(init_stmt, init, uninit);
+            check_uninit(cond, init, uninit);
+            let mut body_init = init.clone();
+            check_uninit(body, &mut body_init, uninit);
+            check_uninit(inc, &mut body_init, uninit);
+        }
+        DoWhile(body, cond) => {
+            check_uninit(body, init, uninit);
+            check_uninit(cond, init, uninit);
+        }
+        Switch(cond, body, ..) => {
+            check_uninit(cond, init, uninit);
+            check_uninit(body, &mut init.clone(), uninit);
+        }
+        Case(_, body) | Default(body) => {
+            check_uninit(body, init, uninit);
+        }
+        Deref(_, expr)
+        | Dot(_, expr, _)
+        | Cast(_, expr)
+        | Not(expr)
+        | Ret(expr)
+        | Expr(expr)
+        | StmtExpr(_, expr) => {
+            check_uninit(expr, init, uninit);
+        }
+        Call(_, _, args) => {
+            for arg in args {
+                check_uninit(arg, init, uninit);
+            }
+        }
+        CallPtr(_, fptr, args) => {
+            check_uninit(fptr, init, uninit);
+            for arg in args {
+                check_uninit(arg, init, uninit);
+            }
+        }
+        CompStmt(stmts) => {
+            for stmt in stmts {
+                check_uninit(stmt, init, uninit);
+            }
+        }
+        ArrIni(arrini) => {
+            for (_, rhs) in arrini {
+                check_uninit(rhs, init, uninit);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn sema(program: &mut Program, ctx: &mut Context) -> Result<(), Vec<CompileError>> {
     let mut nodes = vec![];
     let program_nodes = std::mem::replace(&mut program.nodes, vec![]);
 
@@ -317,7 +1066,16 @@ pub fn sema(program: &mut Program) {
         match topnode.op {
             Func(ctype, ident, args, body, stacksize) => {
                 // eval body
-                let body = walk(&body);
+                let mut body = walk(&body, ctx);
+                annotate_types(&mut body);
+                if *WARN_UNINITIALIZED.lock().unwrap() {
+                    let mut init = args.iter().map(|arg| arg.offset).collect();
+                    let mut uninit = UninitState {
+                        assigned: HashSet::new(),
+                        warned: HashSet::new(),
+                    };
+                    check_uninit(&body, &mut init, &mut uninit);
+                }
                 let node = Node::new_func(ctype.clone(), ident.clone(), args, body, stacksize);
                 nodes.push(node);
             }
@@ -333,4 +1091,9 @@ pub fn sema(program: &mut Program) {
         }
     }
     program.nodes = nodes;
+    let errors = std::mem::take(&mut ctx.errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(())
 }
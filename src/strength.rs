@@ -0,0 +1,128 @@
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::collections::{HashMap, HashSet};
+
+// Strength reduction.
+//
+// Multiplying by a power of two, which every pointer scaling and array
+// index does, becomes a left shift. Dividing by 2^k becomes an
+// arithmetic shift after adding 2^k-1 to negative dividends, so that
+// the quotient still rounds toward zero:
+//
+//   t = x >> 63; t = t >>> (64-k); t = x + t; q = t >> k
+//
+// and x % 2^k becomes x & (2^k-1) when x is known to be non-negative.
+// Constants are only recognized when loaded by `Imm` earlier in the
+// same block.
+
+fn log2(val: i32) -> Option<i32> {
+    if val > 1 && val & (val - 1) == 0 {
+        return Some(val.trailing_zeros() as i32);
+    }
+    None
+}
+
+fn new_imm(val: i32, line: usize, irs: &mut Vec<Ir>, fun: &Function) -> Reg {
+    let r = fun.new_reg();
+    irs.push(
+        Ir::new(
+            IrImm,
+            r.clone(),
+            Reg::dummy(),
+            Reg::dummy(),
+            Reg::dummy(),
+            None,
+            None,
+            val,
+            -1,
+        )
+        .with_line(line),
+    );
+    r
+}
+
+fn new_binop(op: IrOp, r0: Reg, r1: Reg, r2: Reg, line: usize, irs: &mut Vec<Ir>) {
+    irs.push(Ir::new(op, r0, r1, r2, Reg::dummy(), None, None, -1, -1).with_line(line));
+}
+
+fn new_shift(op: IrOp, r0: Reg, r1: Reg, count: i32, line: usize, irs: &mut Vec<Ir>) {
+    irs.push(Ir::new_shift(op, r0, r1, count).with_line(line));
+}
+
+fn reduce_bb(bb: &mut BB, fun: &Function) -> usize {
+    let mut reduced = 0;
+    let mut consts: HashMap<i32, i32> = HashMap::new();
+    let mut nonneg: HashSet<i32> = HashSet::new();
+    let irs = std::mem::take(&mut bb.irs);
+    let mut n_irs = vec![];
+    for ir in irs {
+        let line = ir.line;
+        let lhs = consts.get(&ir.r1.vn).copied();
+        let rhs = consts.get(&ir.r2.vn).and_then(|val| log2(*val));
+        let (r0, r1, r2) = (ir.r0.clone(), ir.r1.clone(), ir.r2.clone());
+        let reduced_ir = match (&ir.op, lhs.and_then(log2), rhs) {
+            (IrMul(size), _, Some(k)) => {
+                new_shift(IrShl(*size), r0.clone(), r1, k, line, &mut n_irs);
+                true
+            }
+            (IrMul(size), Some(k), _) => {
+                new_shift(IrShl(*size), r0.clone(), r2, k, line, &mut n_irs);
+                true
+            }
+            (IrDiv(_), _, Some(k)) => {
+                let (t1, t2, t3) = (fun.new_reg(), fun.new_reg(), fun.new_reg());
+                new_shift(IrSar, t1.clone(), r1.clone(), 63, line, &mut n_irs);
+                new_shift(IrShr, t2.clone(), t1, 64 - k, line, &mut n_irs);
+                new_binop(IrAdd(8), t3.clone(), r1, t2, line, &mut n_irs);
+                new_shift(IrSar, r0.clone(), t3, k, line, &mut n_irs);
+                true
+            }
+            (IrMod(_), _, Some(k)) if nonneg.contains(&r1.vn) => {
+                let rmask = new_imm((1 << k) - 1, line, &mut n_irs, fun);
+                new_binop(IrAnd, r0.clone(), r1, rmask, line, &mut n_irs);
+                true
+            }
+            _ => false,
+        };
+
+        // what is known about r0 from here on
+        if r0.active() {
+            consts.remove(&r0.vn);
+            nonneg.remove(&r0.vn);
+            let known_nonneg = match &ir.op {
+                IrImm => ir.imm >= 0,
+                IrMovzx(_) | IrLt | IrLe | IrEqual | IrNe => true,
+                IrAnd => nonneg.contains(&ir.r1.vn) || nonneg.contains(&ir.r2.vn),
+                IrShr if !ir.r2.active() => ir.imm > 0,
+                IrShr => consts.get(&ir.r2.vn).is_some_and(|k| *k > 0),
+                _ => false,
+            };
+            if ir.op == IrImm {
+                consts.insert(r0.vn, ir.imm);
+            }
+            if known_nonneg {
+                nonneg.insert(r0.vn);
+            }
+        }
+
+        if reduced_ir {
+            reduced += 1;
+        } else {
+            n_irs.push(ir);
+        }
+    }
+    bb.irs = n_irs;
+    reduced
+}
+
+pub fn strength_reduce(program: &mut Program) -> usize {
+    let mut reduced = 0;
+    for fun in &program.funs {
+        fun.resume_regno();
+        for bb in &fun.bbs {
+            reduced += reduce_bb(&mut bb.borrow_mut(), fun);
+        }
+    }
+    reduced
+}
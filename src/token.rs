@@ -1,5 +1,6 @@
-use super::lib::*;
+use super::intern::Symbol;
 use super::preprocess::*;
+use super::*;
 use std::collections::HashMap;
 use std::sync::Mutex;
 use TokenType::*;
@@ -26,9 +27,9 @@ lazy_static! {
     pub static ref ESCAPED: Mutex<HashMap<char, char>> = Mutex::new(hash![
         // ('a', "\\a"), ('b', "\\b"), ('f', "\\f"),
         ('n', '\n'), ('r', '\r'), // ('v', "\\v"),
-        ('t', '\t') // ('e', '\033'), ('E', '\033')
+        ('t', '\t'), // ('e', '\033'), ('E', '\033')
+        ('\\', '\\'), ('\'', '\''), ('"', '"'), ('?', '?')
     ]);
-    pub static ref LINE: Mutex<usize> = Mutex::new(1);
 }
 
 pub static SIGNALS: &[Signal] = &[
@@ -156,7 +157,12 @@ pub enum TokenType {
     TokenBool,
     TokenSwitch,
     TokenCase,
+    TokenDefault,
     TokenEnum,
+    TokenVolatile,
+    TokenRestrict,
+    TokenRegister,
+    TokenAuto,
     TokenNoSignal,
     TokenEof,
 }
@@ -186,13 +192,18 @@ impl From<String> for TokenType {
             "_Bool" => TokenBool,
             "switch" => TokenSwitch,
             "case" => TokenCase,
+            "default" => TokenDefault,
             "enum" => TokenEnum,
+            "volatile" => TokenVolatile,
+            "restrict" => TokenRestrict,
+            "register" => TokenRegister,
+            "auto" => TokenAuto,
             _ => TokenIdent,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub ty: TokenType,
     pub val: i32,
@@ -200,6 +211,8 @@ pub struct Token {
     pub pos: usize,
     pub end: usize,
     pub line: usize,
+    pub noexpand: bool, // identifier that must not be macro-expanded again
+    pub sym: Symbol,    // interned name of an identifier, set by TokenSet
 }
 
 impl Token {
@@ -218,6 +231,8 @@ impl Token {
             pos,
             end,
             line,
+            noexpand: false,
+            sym: 0,
         }
     }
     pub fn getstring(&self) -> String {
@@ -230,28 +245,75 @@ impl Token {
             }
         }
     }
+    // Returns the source text of the token.
+    pub fn text(&self) -> String {
+        if let TokenEof = self.ty {
+            return String::from("end of input");
+        }
+        return String::from(&PROGRAMS.lock().unwrap()[self.program_id][self.pos..self.end]);
+    }
+    pub fn error(&self, msg: String) -> CompileError {
+        CompileError::new(self.program_id, self.pos, self.end, msg)
+    }
+    // Prints a warning pointing at the token. Warnings don't stop compilation.
+    pub fn warn(&self, msg: &str) {
+        let source = PROGRAMS.lock().unwrap()[self.program_id].clone();
+        eprint!(
+            "{}",
+            diagnostic("warning", self.program_id, &source, self.pos, self.end, msg)
+        );
+    }
 }
 
-pub struct TokenSet {
-    pub tokens: Vec<Token>,
-    pub pos: usize,
+// Returns how a token of the given type is spelled, for diagnostics.
+pub fn token_name(ty: &TokenType) -> String {
+    for signal in SIGNALS {
+        if signal.ty == *ty {
+            return String::from(signal.name);
+        }
+    }
+    match ty {
+        TokenIdent => String::from("identifier"),
+        TokenNum => String::from("number"),
+        TokenWhile => String::from("while"),
+        _ => format!("{:?}", ty),
+    }
 }
 
-impl TokenSet {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+pub struct TokenSet<'a> {
+    pub tokens: Vec<Token>,
+    pub pos: usize,
+    pub ctx: &'a mut Context,
+}
+
+impl<'a> TokenSet<'a> {
+    pub fn new(mut tokens: Vec<Token>, ctx: &'a mut Context) -> Self {
+        // identifiers are interned up front, so the parser never has to
+        // go back to the source text for their names
+        let programs = PROGRAMS.lock().unwrap();
+        for token in &mut tokens {
+            if token.ty == TokenIdent {
+                token.sym = ctx
+                    .names
+                    .intern(&programs[token.program_id][token.pos..token.end]);
+            }
+        }
+        drop(programs);
+        Self {
+            tokens,
+            pos: 0,
+            ctx,
+        }
     }
-    pub fn assert_ty(&mut self, ty: TokenType) {
-        let pos = self.pos;
-        if !self.consume_ty(ty) {
-            // error(&format!("assertion failed at: {}", &self.input[..self.val as usize]));
-            // for debug.
-            panic!(
-                "assertion failed at: {}..",
-                &PROGRAMS.lock().unwrap()[self.tokens[pos].program_id]
-                    [pos..pos + self.tokens[pos].val as usize]
-            );
+    pub fn assert_ty(&mut self, ty: TokenType) -> Result<(), CompileError> {
+        if !self.consume_ty(ty.clone()) {
+            return Err(self.error(format!(
+                "expected '{}' but got '{}'",
+                token_name(&ty),
+                self.tokens[self.pos].text()
+            )));
         }
+        Ok(())
     }
     pub fn consume_ty(&mut self, ty: TokenType) -> bool {
         let token = &self.tokens[self.pos];
@@ -272,7 +334,8 @@ impl TokenSet {
     pub fn is_typename(&mut self) -> bool {
         let token = &self.tokens[self.pos];
         match token.ty {
-            TokenInt | TokenChar | TokenVoid | TokenStruct | TokenTypeof => {
+            TokenInt | TokenChar | TokenVoid | TokenStruct | TokenTypeof | TokenVolatile
+            | TokenEnum | TokenRegister | TokenAuto => {
                 self.pos += 1;
                 return true;
             }
@@ -281,18 +344,29 @@ impl TokenSet {
             }
         }
     }
-    pub fn ident(&mut self) -> String {
-        let token = self.tokens[self.pos].clone();
-        let name = String::from(&PROGRAMS.lock().unwrap()[token.program_id][token.pos..token.end]);
-        if !self.consume_ty(TokenIdent) {
-            // error(&format!("should be identifier at {}", &tokenset[*pos].input[*pos..]));
-            // for debug.
-            panic!(
-                "should be identifier at {}",
-                &PROGRAMS.lock().unwrap()[token.program_id][token.pos..]
-            );
+    // Skips type qualifiers and returns whether `volatile` was among them.
+    // `restrict` has no effect on code generation.
+    pub fn consume_qualifiers(&mut self) -> bool {
+        let mut is_volatile = false;
+        loop {
+            if self.consume_ty(TokenVolatile) {
+                is_volatile = true;
+            } else if !self.consume_ty(TokenRestrict) {
+                return is_volatile;
+            }
+        }
+    }
+    pub fn ident(&mut self) -> Result<Symbol, CompileError> {
+        let token = &self.tokens[self.pos];
+        if token.ty != TokenIdent {
+            return Err(self.error(format!("expected identifier but got '{}'", token.text())));
         }
-        return name;
+        self.pos += 1;
+        Ok(token.sym)
+    }
+    // The name of an identifier read by ident().
+    pub fn name(&self, sym: Symbol) -> String {
+        self.ctx.names.name(sym).to_string()
     }
     pub fn getstring(&self) -> String {
         let token = &self.tokens[self.pos];
@@ -308,6 +382,10 @@ impl TokenSet {
     pub fn getval(&self) -> i32 {
         return self.tokens[self.pos].val;
     }
+    // Returns an error pointing at the current token.
+    pub fn error(&self, msg: String) -> CompileError {
+        self.tokens[self.pos].error(msg)
+    }
 }
 
 pub struct Signal {
@@ -326,25 +404,16 @@ pub fn read_file(filename: &str) -> Result<String, Box<dyn std::error::Error>> {
     return Ok(content);
 }
 
-fn read_string(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) -> Token {
+fn read_string(p: &mut core::str::Chars, program_id: usize, line: usize, pos: &mut usize) -> Token {
     let start = *pos;
     let mut sb = String::new();
 
-    loop {
-        let c = c_char(p, pos) as char;
-        if c == '"' {
-            break;
-        }
-        sb.push(c);
+    // an escaped quote doesn't end the string
+    while p.clone().next() != Some('"') {
+        sb.push(c_char(p, pos) as char);
     }
-    return Token::new(
-        TokenString(sb),
-        0,
-        program_id,
-        start,
-        *pos,
-        *LINE.lock().unwrap(),
-    );
+    next_char(p, pos);
+    Token::new(TokenString(sb), 0, program_id, start, *pos, line)
 }
 
 fn next_char(p: &mut core::str::Chars, pos: &mut usize) -> char {
@@ -376,20 +445,21 @@ fn c_char(p: &mut core::str::Chars, pos: &mut usize) -> u8 {
         return val;
     }
     if isoctal(c) {
-        // octal-escaped-sequence in a char literal
-        let mut val = c as u8 - '0' as u8;
+        // octal-escaped-sequence in a char literal. Up to \777 is read,
+        // and only the low byte is kept.
+        let mut val = c.to_digit(8).unwrap();
         let mut pp = p.clone();
         for _ in 0..2 {
             c = next_char(&mut pp, pos);
             if isoctal(c) {
-                val = 8 * val + c as u8 - '0' as u8;
+                val = 8 * val + c.to_digit(8).unwrap();
                 p.next();
             } else {
                 *pos -= 1;
-                return val;
+                return val as u8;
             }
         }
-        return val;
+        return val as u8;
     }
     panic!("invalid char.");
 }
@@ -419,19 +489,12 @@ fn isxdigit(c: char) -> bool {
     }
 }
 
-fn read_char(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) -> Token {
+fn read_char(p: &mut core::str::Chars, program_id: usize, line: usize, pos: &mut usize) -> Token {
     let start = *pos;
     let val = c_char(p, pos) as i32;
     assert!(p.next().unwrap() == '\'');
     *pos += 1;
-    return Token::new(
-        TokenNum,
-        val,
-        program_id,
-        start,
-        *pos,
-        *LINE.lock().unwrap(),
-    );
+    Token::new(TokenNum, val, program_id, start, *pos, line)
 }
 
 fn line_comment(p: &mut core::str::Chars, pos: &mut usize) {
@@ -451,7 +514,11 @@ fn line_comment(p: &mut core::str::Chars, pos: &mut usize) {
     return;
 }
 
-fn block_comment(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) {
+fn block_comment(
+    p: &mut core::str::Chars,
+    program_id: usize,
+    pos: &mut usize,
+) -> Result<(), CompileError> {
     let start = *pos;
     *pos += 2;
     let mut pp = p.clone();
@@ -459,27 +526,25 @@ fn block_comment(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) {
     loop {
         if let Some(c) = pp.next() {
             *pos += 1;
-            if c == '*' && &PROGRAMS.lock().unwrap()[program_id][*pos..*pos + 1] == "/" {
+            if c == '*' && PROGRAMS.lock().unwrap()[program_id].get(*pos..*pos + 1) == Some("/") {
                 *pos += 1;
                 break;
             }
         } else {
-            error(
-                get_path(program_id),
-                *LINE.lock().unwrap(),
-                "premature end of input.",
-            );
+            let msg = String::from("unterminated comment");
+            return Err(CompileError::new(program_id, start, start + 2, msg));
         }
     }
     for _ in 0..(*pos - start) - 1 {
         p.next();
     }
-    return;
+    Ok(())
 }
 
 fn signal(
     p: &mut core::str::Chars,
     program_id: usize,
+    line: usize,
     pos: &mut usize,
     input: &str,
 ) -> Option<Token> {
@@ -492,7 +557,7 @@ fn signal(
                 program_id,
                 *pos,
                 *pos + len,
-                *LINE.lock().unwrap(),
+                line,
             );
             *pos += len;
             for _ in 0..len - 1 {
@@ -504,7 +569,13 @@ fn signal(
     return None;
 }
 
-fn ident(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, c: char) -> Token {
+fn ident(
+    p: &mut core::str::Chars,
+    program_id: usize,
+    line: usize,
+    pos: &mut usize,
+    c: char,
+) -> Token {
     let mut ident = String::new();
     ident.push(c);
     let mut len = 1;
@@ -522,40 +593,39 @@ fn ident(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, c: char)
         }
     }
     *pos += 1;
-    let token = Token::new(
-        TokenType::from(ident),
-        len,
-        program_id,
-        possub,
-        *pos,
-        *LINE.lock().unwrap(),
-    );
+    let token = Token::new(TokenType::from(ident), len, program_id, possub, *pos, line);
     return token;
 }
 
 fn number(
     p: &mut core::str::Chars,
     program_id: usize,
+    line: usize,
     pos: &mut usize,
     input: &str,
     c: char,
-) -> Token {
+) -> Result<Token, CompileError> {
     if c == '0' && (&input[*pos + 1..*pos + 2] == "X" || &input[*pos + 1..*pos + 2] == "x") {
         *pos += 2;
         p.next();
-        return hexadecimal(p, program_id, pos, input);
+        return hexadecimal(p, program_id, line, pos);
     }
 
     if c == '0' {
         *pos += 1;
-        return octal(p, program_id, pos);
+        return Ok(octal(p, program_id, line, pos));
     }
 
     *pos += 1;
-    return decimal(p, program_id, pos, c);
+    Ok(decimal(p, program_id, line, pos, c))
 }
 
-fn hexadecimal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, input: &str) -> Token {
+fn hexadecimal(
+    p: &mut core::str::Chars,
+    program_id: usize,
+    line: usize,
+    pos: &mut usize,
+) -> Result<Token, CompileError> {
     let mut pp = p.clone();
     let mut ishex = false;
     let mut num = 0;
@@ -570,26 +640,29 @@ fn hexadecimal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, inp
             if ishex {
                 break;
             } else {
-                error(
-                    get_path(program_id),
-                    *LINE.lock().unwrap(),
-                    &format!("bad hexadecimal number at {}..", &input[*pos..*pos + 5]),
-                );
+                let msg = String::from("invalid hexadecimal number");
+                return Err(CompileError::new(program_id, possub - 2, *pos, msg));
             }
         }
     }
 
-    return Token::new(
+    Ok(Token::new(
         TokenNum,
         num,
         program_id,
         possub - 2,
         *pos,
-        *LINE.lock().unwrap(),
-    );
+        line,
+    ))
 }
 
-fn decimal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, c: char) -> Token {
+fn decimal(
+    p: &mut core::str::Chars,
+    program_id: usize,
+    line: usize,
+    pos: &mut usize,
+    c: char,
+) -> Token {
     let mut pp = p.clone();
     let possub = *pos;
     let mut num = c as i32 - '0' as i32;
@@ -604,17 +677,10 @@ fn decimal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize, c: char
         break;
     }
 
-    return Token::new(
-        TokenNum,
-        num,
-        program_id,
-        possub - 1,
-        *pos,
-        *LINE.lock().unwrap(),
-    );
+    Token::new(TokenNum, num, program_id, possub - 1, *pos, line)
 }
 
-fn octal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) -> Token {
+fn octal(p: &mut core::str::Chars, program_id: usize, line: usize, pos: &mut usize) -> Token {
     let mut pp = p.clone();
     let possub = *pos;
     let mut num = 0;
@@ -629,14 +695,7 @@ fn octal(p: &mut core::str::Chars, program_id: usize, pos: &mut usize) -> Token
         break;
     }
 
-    return Token::new(
-        TokenNum,
-        num,
-        program_id,
-        possub - 1,
-        *pos,
-        *LINE.lock().unwrap(),
-    );
+    Token::new(TokenNum, num, program_id, possub - 1, *pos, line)
 }
 
 pub fn remove_backslash_or_crlf_newline(input: &mut String) {
@@ -711,25 +770,19 @@ pub fn stringize(tokens: &Vec<Token>) -> Token {
     return Token::new(TokenString(sb), 0, program_id, start, end, line);
 }
 
-pub fn scan(program_id: usize, add_eof: bool) -> Vec<Token> {
+pub fn scan(program_id: usize, add_eof: bool) -> Result<Vec<Token>, CompileError> {
     let mut tokens: Vec<Token> = vec![];
     let mut pos = 0;
+    let mut line = 1;
     let input = PROGRAMS.lock().unwrap()[program_id].clone();
     let mut p = input.chars();
 
     while let Some(c) = p.next() {
         // \n
         if c == '\n' {
-            tokens.push(Token::new(
-                TokenNewLine,
-                0,
-                program_id,
-                pos,
-                pos + 1,
-                *LINE.lock().unwrap(),
-            ));
+            tokens.push(Token::new(TokenNewLine, 0, program_id, pos, pos + 1, line));
             pos += 1;
-            *LINE.lock().unwrap() += 1;
+            line += 1;
             continue;
         }
 
@@ -747,21 +800,21 @@ pub fn scan(program_id: usize, add_eof: bool) -> Vec<Token> {
 
         // Block Comment
         if c == '/' && &input[pos + 1..pos + 2] == "*" {
-            block_comment(&mut p, program_id, &mut pos);
+            block_comment(&mut p, program_id, &mut pos)?;
             continue;
         }
 
         // char literal
         if c == '\'' {
             pos += 1;
-            tokens.push(read_char(&mut p, program_id, &mut pos));
+            tokens.push(read_char(&mut p, program_id, line, &mut pos));
             continue;
         }
 
         // string literal
         if c == '"' {
             pos += 1;
-            let mut string_token = read_string(&mut p, program_id, &mut pos);
+            let mut string_token = read_string(&mut p, program_id, line, &mut pos);
             if !tokens.is_empty() {
                 if let (TokenString(s1), TokenString(s2)) =
                     (&tokens.last().unwrap().ty, &string_token.ty)
@@ -778,43 +831,39 @@ pub fn scan(program_id: usize, add_eof: bool) -> Vec<Token> {
         }
 
         // signal
-        if let Some(token) = signal(&mut p, program_id, &mut pos, &input) {
+        if let Some(token) = signal(&mut p, program_id, line, &mut pos, &input) {
             tokens.push(token);
             continue;
         }
 
         // ident
         if c.is_alphabetic() || c == '_' {
-            tokens.push(ident(&mut p, program_id, &mut pos, c));
+            tokens.push(ident(&mut p, program_id, line, &mut pos, c));
             continue;
         }
 
         // number
         if c.is_digit(10) {
-            tokens.push(number(&mut p, program_id, &mut pos, &input, c));
+            tokens.push(number(&mut p, program_id, line, &mut pos, &input, c)?);
             continue;
         }
 
-        error(
-            get_path(program_id),
-            *LINE.lock().unwrap(),
-            &format!("cannot scan at {}", &input[pos..]),
-        );
+        let msg = format!("stray '{}' in program", c);
+        return Err(CompileError::new(program_id, pos, pos + 1, msg));
     }
 
     // guard
     if add_eof {
-        let token = Token::new(TokenEof, 0, program_id, pos, pos, *LINE.lock().unwrap());
+        let token = Token::new(TokenEof, 0, program_id, pos, pos, line);
         tokens.push(token);
     }
 
-    return tokens;
+    Ok(tokens)
 }
 
-pub fn tokenize(program_id: usize, add_eof: bool) -> Vec<Token> {
-    *LINE.lock().unwrap() = 1;
-    let tokens = scan(program_id, add_eof);
-    let tokens = preprocess(tokens);
+pub fn tokenize(program_id: usize, add_eof: bool) -> Result<Vec<Token>, CompileError> {
+    let tokens = scan(program_id, add_eof)?;
+    let tokens = preprocess(tokens)?;
     let tokens = strip_newline_tokens(tokens);
-    return tokens;
+    Ok(tokens)
 }
@@ -0,0 +1,164 @@
+use super::gen_ir::{IrOp::*, *};
+use super::mir::*;
+
+use std::collections::{HashMap, HashSet};
+
+// IR verifier. This pass checks the invariants the later passes rely on:
+//
+// - Every virtual register is defined before it is used. A register
+//   defined in another block is only required to be defined somewhere
+//   in the function.
+//
+// - Every block a jump or a branch refers to is emitted exactly once,
+//   and a jump into a block with a parameter passes a value to it.
+//
+// - The successor and predecessor lists of the blocks match the jumps
+//   and fall-throughs of their instructions.
+//
+// - Loads and stores move 1, 2, 4 or 8 bytes.
+//
+// After register allocation, every register is also required to have
+// been assigned a real register.
+
+fn check_size(op: &IrOp) -> Option<String> {
+    let (size, sizes): (i32, &[i32]) = match op {
+        IrLoad(size) | IrStore(size) | IrStoreArg(size) => (*size, &[1, 2, 4, 8]),
+        IrMovsx(size) | IrMovzx(size) => (*size, &[1, 2, 4]),
+        _ => {
+            return None;
+        }
+    };
+    if sizes.contains(&size) {
+        return None;
+    }
+    Some(format!("invalid size {}", size))
+}
+
+fn check_cfg(fun: &Function, labels: &HashMap<BBId, i32>, errors: &mut Vec<String>) {
+    let sorted = |ids: &[BBId]| {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    };
+    let mut edges = HashSet::new();
+    for (i, bb) in fun.bbs.iter().enumerate() {
+        let bb = bb.borrow();
+        let expected = sorted(&fun.successors(i));
+        if sorted(&bb.succ) != expected {
+            errors.push(format!(
+                "{}: .L{} has successors {:?} but jumps to {:?}",
+                fun.name,
+                bb.label,
+                sorted(&bb.succ),
+                expected
+            ));
+        }
+        for to in &bb.succ {
+            edges.insert((bb.label, *to));
+        }
+    }
+    for bb in &fun.bbs {
+        let bb = bb.borrow();
+        for from in &bb.pred {
+            if !labels.contains_key(from) || !edges.contains(&(*from, bb.label)) {
+                errors.push(format!(
+                    "{}: .L{} has predecessor .L{} which does not lead to it",
+                    fun.name, bb.label, from
+                ));
+            }
+        }
+        for (from, to) in &edges {
+            if *to == bb.label && !bb.pred.contains(from) {
+                errors.push(format!(
+                    "{}: .L{} is missing predecessor .L{}",
+                    fun.name, bb.label, from
+                ));
+            }
+        }
+    }
+}
+
+pub fn verify(fun: &Function, allocated: bool) -> Result<(), Vec<String>> {
+    let mut errors = vec![];
+    let mut report = |ir: &Ir, msg: String| {
+        errors.push(format!("{}: {}: {}", fun.name, ir.tostr(), msg));
+    };
+
+    // labels and definitions of the whole function
+    let mut labels = HashMap::new();
+    let mut defs = HashSet::new();
+    for bb in &fun.bbs {
+        let bb = bb.borrow();
+        *labels.entry(bb.label).or_insert(0) += 1;
+        if bb.param.active() {
+            defs.insert(bb.param.vn);
+        }
+        for ir in &bb.irs {
+            if ir.r0.active() {
+                defs.insert(ir.r0.vn);
+            }
+        }
+    }
+
+    for bb in &fun.bbs {
+        let bb = bb.borrow();
+        let local_defs: HashSet<i32> = bb
+            .irs
+            .iter()
+            .filter(|ir| ir.r0.active())
+            .map(|ir| ir.r0.vn)
+            .collect();
+        let mut defined = HashSet::new();
+        if bb.param.active() {
+            defined.insert(bb.param.vn);
+        }
+        for ir in &bb.irs {
+            for r in ir.uses() {
+                if !defs.contains(&r.vn) {
+                    report(ir, format!("r{} is never defined", r.vn));
+                } else if local_defs.contains(&r.vn) && !defined.contains(&r.vn) {
+                    report(ir, format!("r{} is used before its definition", r.vn));
+                }
+                if allocated && r.rn < 0 {
+                    report(ir, format!("r{} has no real register", r.vn));
+                }
+            }
+            if ir.r0.active() {
+                defined.insert(ir.r0.vn);
+                if allocated && ir.r0.rn < 0 {
+                    report(ir, format!("r{} has no real register", ir.r0.vn));
+                }
+            }
+            for target in ir.bb1.iter().chain(ir.bb2.iter()) {
+                let target = target.borrow();
+                if labels.get(&target.label) != Some(&1) {
+                    report(
+                        ir,
+                        format!(
+                            ".L{} is emitted {} times",
+                            target.label,
+                            labels.get(&target.label).unwrap_or(&0)
+                        ),
+                    );
+                }
+                if target.param.active() && (ir.op != IrJmp || !ir.bbarg.active()) {
+                    report(
+                        ir,
+                        format!(".L{} takes a value but none is passed", target.label),
+                    );
+                }
+            }
+            if let Some(msg) = check_size(&ir.op) {
+                report(ir, msg);
+            }
+        }
+    }
+
+    check_cfg(fun, &labels, &mut errors);
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
@@ -1,3 +1,5 @@
 // This file is compiled by gcc.
 
 int global_arr[1] = {5};
+
+int is_aligned(void *p, int n) { return (long)p % n == 0; }
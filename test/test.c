@@ -13,6 +13,10 @@ extern void *stderr;
 	}                                                          \
 } while (0)
 
+#define SQ(x) ((x)*(x))
+#define PAREN (1+2)
+#define TWICE(x) (x + x)
+
 int printf();
 int fprintf();
 int exit();
@@ -27,11 +31,48 @@ int add3(int a[][2]) { return a[0][0] + a[1][0]; }
 int add4(int a[2][2]) { return a[0][0] + a[1][0]; }
 int *pointer(){ int a = 10; int *p = &a; return p; }
 void nop() {}
+int sq_sum() { return SQ(1+2); }
+int paren_mul() { return PAREN*3; }
+int ret_count;
+void early_ret(int x) { if (x) return; ret_count = 5; }
+int tentative;
+int tentative = 3;
+int tentative;
+int proto_add(int, int *);
+int proto_add(int a, int *b) { return a + *b; }
+int no_params(void) { return 7; }
+int is_aligned();
+int is_even(int n) { if (n == 0) return 1; return is_odd(n - 1); }
+int is_odd(int n) { if (n == 0) return 0; return is_even(n - 1); }
+int fwd_deref() { return *fwd_ptr(); }
+int fwd_val = 9;
+int *fwd_ptr() { return &fwd_val; }
+int eighth(int a, int b, int c, int d, int e, int f, int g, int h) { return h; }
+int ninth_mix(int a, int b, int c, int d, int e, int f, int g, char h, int i) { return a + g * 10 + h * 100 + i * 1000; }
+int keep_id(int x) { return x; }
+int keep_live(int n) { int s = 0; for (int i = 0; i < n; i++) s += i * 2 + (i * 3 + (i * 4 + keep_id(i))); return s; }
+int frame_align() { char c1; char *p1; char c2; int *p2; char c3; char **p3; return is_aligned(&p1, 8) && is_aligned(&p2, 8) && is_aligned(&p3, 8); }
+int elvis_calls;
+int elvis_next() { elvis_calls++; return elvis_calls * 3; }
+int sc_calls;
+int sc_bump() { sc_calls++; return 1; }
+int sc_zero() { sc_calls++; return 0; }
+int cast_calls;
+int cast_bump() { cast_calls++; return 3; }
+int fp_g[3];
+int *fp_at(int i) { return &fp_g[i]; }
+
+int self_ref = 4;
+#define self_ref self_ref + 1
+int mut_a = 2;
+#define mut_a mut_b * 3
+#define mut_b mut_a + 1
 
 int var1;
 int var2[5];
 extern int global_arr[1];
 int *aaa;
+volatile int *restrict vp;
 typedef int myint;
 
 enum {
@@ -42,6 +83,15 @@ enum {
 int GA = 12;
 char *GB[] = {"abc", "def", "ghi"};
 int GC[2] = {23, 3};
+int gtern1 = 1 ? 10 : 20;
+int gtern2 = 0 ? 10 : 2 * 10;
+int genum1 = EEE;
+int genum2 = BBB * 2 + EEE;
+int gm1 = 3, *gm2, gm3[2] = {4, 5};
+char gc1 = 1, gc2 = 2;
+char gc3[3] = {-1, 2, 3};
+char gesc[] = "\033[0m";
+char *gescp = "\0331\xff";
 
 // Single-line comment test
 
@@ -74,6 +124,88 @@ int main() {
 	EXPECT(3, one()+two());
 	EXPECT(6, mul(2, 3));
 	EXPECT(21, add(1,2,3,4,5,6));
+	EXPECT(0, ({ ret_count = 0; early_ret(1); ret_count; }));
+	EXPECT(5, ({ early_ret(0); ret_count; }));
+	EXPECT(3, tentative);
+	EXPECT(5, ({ int b = 2; proto_add(3, &b); }));
+	EXPECT(7, no_params());
+	EXPECT(1, frame_align());
+	EXPECT(1, is_even(10));
+	EXPECT(1, is_odd(7));
+	EXPECT(9, fwd_deref());
+	EXPECT(60, keep_live(4));
+	EXPECT(8, eighth(1, 2, 3, 4, 5, 6, 7, 8));
+	EXPECT(9371, ninth_mix(1, 0, 0, 0, 0, 0, 7, 3, 9));
+	EXPECT(5, ({ int x = 5; x + 0 - 0; }));
+	EXPECT(6, ({ int x = 6; 1 * x * 1 / 1; }));
+	EXPECT(1, ({ int i = 0; i++ * 0; i; }));
+	EXPECT(0, ({ int x = 6; x * 0; }));
+	EXPECT(0, ({ int x = 6; (x ^ x) + (x - x); }));
+	EXPECT(6, ({ int x = 6; (x & x) | (x | x); }));
+	EXPECT(2, ({ int i = 0; (i++) - (i++); i; }));
+	EXPECT(4, ({ int a[2]; a[0] = 1; a[1] = 3; int *p = a; (*p ^ p[1]) + (p[1] & *p) + 1; }));
+	EXPECT(0, 0 && sc_bump());
+	EXPECT(1, 1 || sc_bump());
+	EXPECT(0, ({ int z = 0; z && sc_bump(); }));
+	EXPECT(1, ({ int o = 1; o || sc_bump(); }));
+	EXPECT(0, sc_calls);
+	EXPECT(1, ({ int o = 1; o && sc_bump(); }));
+	EXPECT(0, ({ int z = 0; z || sc_zero(); }));
+	EXPECT(2, sc_calls);
+	EXPECT(5, ({ int z = 0; (z || sc_zero()) + 5; }));
+	EXPECT(9, ({ int x = ({ int y = 2; ({ y + 1; }); }); ({ int z = x * 2; ({ ({ z; }) + x; }); }); }));
+	EXPECT(6, ({ int s = 0; for (int i = 0; i < 3; i++) s += ({ int t = i; ({ t * 2; }); }); s; }));
+	EXPECT(3, ({ ({}); 3; }));
+	EXPECT(1, ({ int a = 3; int b = 3; char c = (a == b); c; }));
+	EXPECT(0, ({ int a = 3; int b = 4; char c = 7; c = a == b; c; }));
+	EXPECT(1, ({ int a = 3; char c = 256 + (a == a); c; }));
+	EXPECT(17, ({ int a = 3; char c[2]; c[1] = 7; c[0] = a != 4; c[0] * 10 + c[1]; }));
+	EXPECT(2, ({ int a = 3; char c = (a < 4) + (a <= 3) + (a == 4); c; }));
+	EXPECT(1, ({ char c = -1; c == -1; }));
+	EXPECT(1, ({ char c = 200; c < 0; }));
+	EXPECT(-56, ({ char c = 200; c; }));
+	EXPECT(1, ({ char c = -3; char d = 2; c < d; }));
+	EXPECT(2, ({ char c = -1; switch (c) { case -1: c = 2; } c; }));
+	EXPECT(1, ({ int a = -1; a == -1; }));
+	EXPECT(1, ({ int a = -2; int b = -1; a < b; }));
+	EXPECT(1, ({ _Bool b = 5; b; }));
+	EXPECT(1, ({ _Bool b = 1; b == 1; }));
+	EXPECT(0, ({ _Bool b = 0; b; }));
+	EXPECT(44, (char)300);
+	EXPECT(-1, (char)255);
+	EXPECT(127, (char)-129);
+	EXPECT(65, ({ int x = 321; (char)x; }));
+	EXPECT(-1, ({ int x = 255; (char)x; }));
+	EXPECT(-1, ({ char c = -1; (int)c; }));
+	EXPECT(1, ({ char c = 2; (_Bool)c; }));
+	EXPECT(1, ({ int x = 256; (_Bool)x; }));
+	EXPECT(0, ({ int x = 0; (_Bool)x; }));
+	EXPECT(1, ({ int x; (_Bool)&x; }));
+	EXPECT(1, ({ _Bool b = 1; (char)b; }));
+	EXPECT(1, ({ _Bool b = 1; (int)b; }));
+	EXPECT(321, ({ int x = 321; *(int *)(char *)&x; }));
+	EXPECT(65, ({ int x = 321; *(char *)&x; }));
+	EXPECT(8, (int)(int *)8);
+	EXPECT(1, ({ (void)cast_bump(); cast_calls; }));
+	EXPECT(5, ({ int i = 0; (void)(i = 5); i; }));
+	EXPECT(30, ({ int s = 0; for (int i = 0; i < 3; i++) { int i = 10; s += i; } s; }));
+	EXPECT(3, ({ int n = 0; for (int i = 0; i < 3; i++) { int i = 0; i++; n += i; } n; }));
+	EXPECT(12, ({ int s = 0; for (int i = 1; i * i < 20; i = i + 1) s += i; s - 3 + 5; }));
+	EXPECT(7, ({ int i = 7; for (int i = 0; i < 2; i++) ; i; }));
+	EXPECT(2, ({ enum { A, B, C } e = (enum { X, Y, Z })2; (int)e; }));
+	EXPECT(1, ({ int x = 0x7fffffff; (x + 1) < 0; }));
+	EXPECT(1, ({ int x = -0x7fffffff - 1; (x - 1) > 0; }));
+	EXPECT(0, ({ int x = 65536; x * x; }));
+	EXPECT(-2, ({ int x = 0x7fffffff; x << 1; }));
+	EXPECT(-3, ({ int x = -7; x / 2; }));
+	EXPECT(-4, -8 >> 1);
+	EXPECT(-4, ({ int x = -8; x >> 1; }));
+	EXPECT(-1, ({ int x = -8; int y = 5; x >> y; }));
+	EXPECT(-64, ({ int x = -8; x << 3; }));
+	EXPECT(-1, ({ int x = -7; x % 2; }));
+	EXPECT(3, ({ int x = 2; (void)(1 + 2); (void)x; (void)(x = 3); x; }));
+	EXPECT(5, ({ int x = 1; x ? (void)(x = 5) : (void)(x = 7); x; }));
+	EXPECT(4, ({ int n = 1; ({ n = 4; if (n) n; }); n; }));
 
 	EXPECT(0, 0 || 0);
 	EXPECT(1, 1 || 0);
@@ -103,6 +235,8 @@ int main() {
 	EXPECT(45, ({ int x=0; int y=0; do { y=y+x; x=x+1; } while (x < 10); y; }));
 	EXPECT(1, ({ int x=0; do {x++; break;} while (1); x; }));
 	EXPECT(1, ({ int x=0; do {x++; continue;} while (0); x; }));
+	EXPECT(30, ({ int i=0; int s=0; do { i++; if (i%2) continue; s+=i; } while (i<10); s; }));
+	EXPECT(13, ({ int j=0; int t=0; while (j<5) { j++; switch (j) { case 2: continue; } t+=j; } t; }));
 
 	EXPECT(60, ({ int sum=0; int i; for (i=10; i<15; i=i+1) sum = sum + i; sum;}));
 	EXPECT(89, ({ int i=1; int j=1; for (int k=0; k<10; k=k+1) { int m=i+j; i=j; j=m; } i;}));
@@ -128,6 +262,11 @@ int main() {
 	EXPECT(6, ({ int x=0; switch(3) { case 2: x=5; break; case 3: x=6; break; case 4: x=7; break; } x; }));
 	EXPECT(7, ({ int x=0; switch(3) { case 2: x=5; case 3: x=6; case 4: x=7; } x; }));
 	EXPECT(0, ({ int x=0; switch(3) case 1: x=5; x; }));
+	EXPECT(9, ({ int x=0; switch(3) { case 2: x=5; break; default: x=9; break; case 4: x=7; } x; }));
+	EXPECT(7, ({ int x=0; switch(4) { case 2: x=5; break; default: x=9; break; case 4: x=7; } x; }));
+	EXPECT(6, ({ int x=0; switch(1) { case 1: x=1; default: x+=5; } x; }));
+	EXPECT(5, ({ int x=0; switch(8) { default: x+=2; case 1: x+=3; } x; }));
+	EXPECT(3, ({ int x=0; switch(1) { case 1: switch(5) { case 2: x=2; } case 3: x+=3; } x; }));
 
 	EXPECT(3, ({ int ary[2]; *ary=1; *(ary+1)=2; *ary + *(ary+1);}));
 	EXPECT(3, ({ int *ary; int ary2[5]; ary = ary2; *(ary+1)=1; *(ary+4)=2; *(ary+1) + *(ary+4);}));
@@ -135,6 +274,11 @@ int main() {
 	EXPECT(12, ({ int x; int *p = pointer(); x = *p; *p = 2; x + *p;}));
 
 	EXPECT(40, ({ int ary[2][5]; sizeof(ary);}));
+	EXPECT(24, ({ int a[2][3]; sizeof a; }));
+	EXPECT(12, ({ int a[2][3]; sizeof a[1]; }));
+	EXPECT(3, ({ int a[2][3]; &a[1][0] - &a[0][0]; }));
+	EXPECT(36, ({ enum { N = 3 }; int a[N][PAREN]; sizeof a; }));
+	EXPECT(48, ({ int a[2 * 2][sizeof(char) ? 3 : 1]; sizeof a; }));
 	EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[0][1]=5; add2(ary);}));
 	EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[1][0]=5; add3(ary);}));
 	EXPECT(8, ({ int ary[2][2]; ary[0][0]=3; ary[1][0]=5; add4(ary);}));
@@ -151,11 +295,27 @@ int main() {
 	EXPECT('i', ({ char *ary[3] = {"abc", "def", "ghi"}; ary[2][2]; }));
 	EXPECT('d'+'i', ({ char *ary[] = {"abc", "def", "ghi"}; ary[1][0] + ary[2][2]; }));
 	EXPECT(2, ({ int ary[] = {1, 2, 3}; ary[1];}));
+	EXPECT(7, ({ char x = 7; char ary[2] = {1, 2}; x; }));
+	EXPECT(8, ({ char *p = "abcdefgh"; p + 8 - p; }));
 
 	EXPECT(1, ({ char x; sizeof x; }));
 	EXPECT(4, ({ int x; sizeof(x); }));
 	EXPECT(8, ({ int *x; sizeof x; }));
 	EXPECT(16, ({ int x[4]; sizeof x; }));
+	EXPECT(8, ({ void *x; sizeof x; }));
+	EXPECT(4, sizeof(int));
+	EXPECT(8, sizeof(char *));
+	EXPECT(12, sizeof(int[3]));
+	EXPECT(4, _Alignof(int));
+	EXPECT(8, ({ int x; sizeof(&x); }));
+	EXPECT(8, ({ int x[3]; sizeof(&x); }));
+	EXPECT(12, ({ int x[3]; sizeof(*&x); }));
+	EXPECT(5, ({ int x[2][3]; x[0][1] = 5; (&x)[0][0][1]; }));
+	EXPECT(7, ({ int x[3]; x[1] = 7; (*&x)[1]; }));
+	EXPECT(3, ({ int x = 3; void *p = &x; int *q = p; *q; }));
+	EXPECT('d', ({ char *buf = "abcdef"; void *p = buf; char *q = p + 3; *q; }));
+	EXPECT('c', ({ char *buf = "abcdef"; void *p = buf; p++; p = p + 1; char *q = p; *q; }));
+	EXPECT(4, ({ char *buf = "abcdef"; void *p = buf; void *q = buf + 4; q - p; }));
 	EXPECT(10, ({ int x = 10; aaa = &x; *aaa; }));
 	EXPECT(4, sizeof("abc"));
 	EXPECT(7, sizeof("abc" "def"));
@@ -166,9 +326,14 @@ int main() {
 	EXPECT(8, ({ int *x; _Alignof x; }));
 	EXPECT(4, ({ int x[4]; _Alignof x; }));
 	EXPECT(8, ({ int *x[4]; _Alignof x; }));
+	EXPECT(40, ({ int x[10]; sizeof x; }));
+	EXPECT(8, ({ char x[2][4]; sizeof x; }));
+	EXPECT(4, ({ char x[2][4]; sizeof x[1]; }));
+	EXPECT(1, ({ char x[2][4]; _Alignof x[1]; }));
+	EXPECT(4, ({ struct { char a; int b; } x[3]; _Alignof x; }));
 
 	EXPECT(5, ({ char x = 5; x; }));
-	EXPECT(42, ({ int x = 0; char *p = &x; p[0] = 42; x; }));
+	EXPECT(42, ({ int x = 0; void *v = &x; char *p = v; p[0] = 42; x; }));
 
 	EXPECT(0, '\0');
 	EXPECT(0, '\00');
@@ -181,7 +346,7 @@ int main() {
 	EXPECT('0', "\10000"[1]);
 	EXPECT('0', "\10000"[2]);
 	EXPECT(0, "\10000"[3]);
-	EXPECT(255, "\xffxyz"[0]);
+	EXPECT(-1, "\xffxyz"[0]);
 	EXPECT('x', "\xffxyz"[1]);
 
 	EXPECT('a', ({ char *p = "abc"; p[0]; }));
@@ -201,13 +366,29 @@ int main() {
 	EXPECT(1, ({; 1; }));
 
 	EXPECT(4, ({ struct { int a; } x; sizeof(x); }));
+	EXPECT(24, ({ int a, *b, c[3]; sizeof(a) + sizeof(b) + sizeof(c); }));
+	EXPECT(12, ({ int a, *b, c[3]; b = &a; *b = 5; c[2] = 7; a + c[2]; }));
+	EXPECT(3, ({ int a = 1, b = a + 1; a + b; }));
+	EXPECT(6, ({ int a = 1, b = 2, c = 3; a + b + c; }));
+	EXPECT(8, ({ struct { int a, b; } x; sizeof(x); }));
+	EXPECT(9, ({ typedef int T, *P; T t = 9; P p = &t; *p; }));
+	EXPECT(3, ({ int s = 0; for (int i = 0, j = 3; i < j; i++) s++; s; }));
 	EXPECT(8, ({ struct { char a; int b; } x; sizeof(x); }));
 	EXPECT(12, ({ struct { char a; char b; int c; char d; } x; sizeof(x); }));
 	EXPECT(3, ({ struct { int a; } x; x.a=3; x.a; }));
 	EXPECT(8, ({ struct { char a; int b; } x; x.a=3; x.b=5; x.a+x.b; }));
 	EXPECT(8, ({ struct tag { char a; int b; } x; struct tag *p = &x; x.a=3; x.b=5; p->a+p->b; }));
+	EXPECT(7, ({ struct { int a; int b; } x; void *v = &x; int *p = v; p[1] = 7; x.a = 1; x.b; }));
+	EXPECT(1, ({ struct { int a; int b; } x; &x.b - &x.a; }));
+	EXPECT(9, ({ struct { char c; int d; int e; } x; x.e = 9; void *v = &x; int *p = v; p[2]; }));
+	EXPECT(7, ({ struct P { int x; int y; } a, b; b.x = 3; b.y = 4; a = b; a.x + a.y; }));
+	EXPECT(14, ({ struct S { char c; int d[3]; char e; } a, b; b.c = 2; b.d[2] = 5; b.e = 7; struct S *p = &a; *p = b; a.c + a.d[2] + a.e; }));
+	EXPECT(6, ({ struct { int x; int y; } a, b, c; c.x = 1; c.y = 2; a = b = c; a.x + b.y + c.x + c.y; }));
 
 	EXPECT(48, ({ struct { struct { int b; int c[5]; } a[2]; } x; sizeof(x); }));
+	EXPECT(4, sizeof(struct { int n; char d[]; }));
+	EXPECT(6, ({ char buf[8]; void *v = buf; struct { int n; char d[]; } *p = v; char *q = &p->d[2]; q - buf; }));
+	EXPECT(9, ({ char buf[8]; void *v = buf; struct { int n; char d[]; } *p = v; p->d[2] = 9; buf[6]; }));
 
 	EXPECT(8, ({
 		struct {
@@ -250,14 +431,43 @@ int main() {
 		a;
 	}));
 
+	EXPECT(16, ({ enum { N = 4 }; int a[N]; sizeof a; }));
+	EXPECT(12, ({ enum { M = 1 + 2, NEG = -1 }; int a[M]; sizeof a + NEG + 1; }));
+	EXPECT(48, ({ int a[EEE - BBB + 1][6]; sizeof a; }));
+	EXPECT(7, ({ int x = 0; enum { K = 3 }; switch (3) { case K: x = 7; } x; }));
+	EXPECT(6, ({ enum color { RED, GREEN = 5, BLUE }; enum color c = BLUE; c; }));
+	EXPECT(4, ({ enum fruit { APPLE }; sizeof(enum fruit); }));
+	EXPECT(3, ({ enum E { K = 3, L }; enum E e = K; e; }));
+	EXPECT(5, ({ enum E { K = 3, L }; enum E e = K; int i = e; e = L; e == L && i == K ? i + 2 : 0; }));
+	EXPECT(6, ({ enum E { K = 3, L }; enum E e = K; e++; e += 1; e < L + 2 ? e + 1 : 0; }));
+	EXPECT(8, ({ enum E { K = 3, L }; struct { enum E e; char c; } s; s.e = L; enum E *p = &s.e; *p + sizeof(s) - 4; }));
+	EXPECT(2, ({ enum { P, Q, R } e = R; int x = 0; switch (e) { case P: case R: x = 2; break; case Q: x = 1; } x; }));
+
+	EXPECT(5, ({ int x = 5; volatile int *restrict p = &x; *p; }));
+	EXPECT(3, ({ volatile int v = 3; int volatile *q = &v; *q; }));
+	EXPECT(3, ({ register int i = 3; i; }));
+	EXPECT(10, ({ auto int s = 0; for (register int i = 0; i < 5; i++) s += i; s; }));
+
 	EXPECT(0, !1);
 	EXPECT(1, !0);
 
 	EXPECT(5, 0 ? 3 : 5);
 	EXPECT(3, 1 ? 3 : 5);
+	EXPECT(4, 4 ?: 5);
+	EXPECT(5, 0 ?: 5);
+	EXPECT(3, ({ elvis_calls = 0; int x = elvis_next() ?: 5; x; }));
+	EXPECT(1, elvis_calls);
 
 	EXPECT(3, (1, 2, 3));
 
+	EXPECT(9, sq_sum());
+	EXPECT(9, paren_mul());
+	EXPECT(9, SQ(PAREN));
+	EXPECT(16, SQ(SQ(2)));
+	EXPECT(4, TWICE(TWICE(1)));
+	EXPECT(5, self_ref);
+	EXPECT(5, mut_a);
+
 	EXPECT(11, 9 | 2);
 	EXPECT(11, 9 | 3);
 
@@ -308,6 +518,15 @@ int main() {
 	EXPECT(5, ({ int x; typeof(x) y = 5; y; }));
 	EXPECT(1, ({ char x; typeof(x) y = 257; y; }));
 	EXPECT(2, ({ char x; typeof(x) y[2]; y[0]=257; y[1]=1; y[0]+y[1]; }));
+	EXPECT(12, ({ int a[3]; typeof(a) b; sizeof b; }));
+	EXPECT(12, ({ int a[3]; sizeof(typeof(a)); }));
+	EXPECT(8, ({ typeof(int *) p; sizeof p; }));
+	EXPECT(1, ({ sizeof(typeof(char)); }));
+	EXPECT(4, sizeof({int x; x;}));
+	EXPECT(1, sizeof({ int i = 3; char c = i; c; }));
+	EXPECT(8, ({ int a[2]; sizeof({ a; }); }));
+	EXPECT(44, ({ int x = 300; (typeof(char))x; }));
+	EXPECT(3, ({ int x = 3; typeof(int *) p = &x; *p; }));
 
 	EXPECT(0, ({ _Bool x = 0; x; }));
 	EXPECT(1, ({ _Bool x = 1; x; }));
@@ -325,5 +544,47 @@ int main() {
 	
 	EXPECT('d'+12, ({ GA+GB[1][0]; }));
 	EXPECT(23, ({ GC[0]; }));
+	EXPECT(10, gtern1);
+	EXPECT(20, gtern2);
+	EXPECT(11, genum1);
+	EXPECT(31, genum2);
+	EXPECT(10, ({ struct { int a; int b; } s; s.a = 2; s.b = 3; s.a * s.a + s.b + s.b; }));
+	EXPECT(7, ({ int a[3]; int i = 1; a[i] = 5; a[i] = a[i] + 2; a[i]; }));
+	EXPECT(9, ({ int x = 4; int y = x + 1; x = 8; y = x + 1; y; }));
+	EXPECT(6, ({ int y = 5; int *p = &y; y = 6; *p; }));
+	EXPECT(3, ({ int y = 1; y = 2; y = 3; y; }));
+	EXPECT(7, ({ int y = 0; if (1) y = 7; else y = 8; y; }));
+	EXPECT(8, ({ int y = 0; if (0) y = 7; else y = 8; y; }));
+	EXPECT(12, ({ gm2 = &gm1; *gm2 + gm3[0] + gm3[1]; }));
+	EXPECT(2, gc2);
+	EXPECT(3, gc1 + gc2);
+	EXPECT(4, gc3[0] + gc3[1] + gc3[2]);
+	EXPECT(5, sizeof(gesc));
+	EXPECT(27, gesc[0]);
+	EXPECT(109, gesc[3]);
+	EXPECT(0, gesc[4]);
+	EXPECT(27, gescp[0]);
+	EXPECT(49, gescp[1]);
+	EXPECT(-1, gescp[2]);
+	EXPECT(2, sizeof("\xe9"));
+	EXPECT(-23, ({ char *s = "\xe9"; s[0]; }));
+	EXPECT(34, ({ char *s = "a\"b"; s[1]; }));
+	EXPECT(4, sizeof("a\"b"));
+	EXPECT(92, ({ char *s = "\\"; s[0]; }));
+	EXPECT(39, '\'');
+	EXPECT(-1, ({ int x = -7; x / 4; }));
+	EXPECT(1, ({ int x = 7; x / 4; }));
+	EXPECT(-2, ({ int x = -8; x / 4; }));
+	EXPECT(-112, ({ int x = -7; x * 16; }));
+	EXPECT(-3, ({ int x = -7; x % 4; }));
+	EXPECT(3, ({ int x = -9; (x & 15) % 4; }));
+	EXPECT(1, ({ int x = -9; (x < 0) % 2; }));
+	EXPECT(6, ({ int i = 0; for (;;) { if (i > 5) break; else i++; } i; }));
+	EXPECT(3, ({ int i = 0; int j = 0; while (i < 3) { if (i) j++; else {} i++; } j + 1; }));
+	EXPECT(7, ({ int *(*f)(int) = fp_at; *f(1) = 7; fp_g[1]; }));
+	EXPECT(6, ({ int (*f)(int, int) = plus; f(2, 4); }));
+	EXPECT(12, ({ int (*f)(int x, int y); f = &mul; (*f)(3, 4); }));
+	EXPECT(8, ({ int (*fs[2])(int, int); fs[0] = plus; fs[1] = mul; fs[0](1, 2) + fs[1](1, 5); }));
+	EXPECT(1, ({ int *(*f)(void); int (*g)(int) = one; sizeof(f) == 8 && g(9) == 1; }));
 	return 0;
 }
\ No newline at end of file
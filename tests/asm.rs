@@ -0,0 +1,385 @@
+// Checks the assembly printed for a few snippets.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+fn compile(name: &str, src: &str) -> String {
+    compile_with(name, &[], src)
+}
+
+fn compile_with(name: &str, flags: &[&str], src: &str) -> String {
+    common::assembly("asm", name, flags, src)
+}
+
+// The lines following `.bss` up to the next section directive.
+fn bss_symbols(asm: &str) -> Vec<String> {
+    let mut symbols = vec![];
+    let mut in_bss = false;
+    for line in asm.lines() {
+        if line.starts_with('.') && !line.starts_with(".L") {
+            in_bss = line == ".bss";
+        } else if in_bss && line.ends_with(':') {
+            symbols.push(line.trim_end_matches(':').to_string());
+        }
+    }
+    symbols
+}
+
+#[test]
+fn globals_on_one_line() {
+    let asm = compile("globals", "int x, *y; int main() { return 0; }");
+    let bss = bss_symbols(&asm);
+    assert!(bss.contains(&String::from("x")), "{}", asm);
+    assert!(bss.contains(&String::from("y")), "{}", asm);
+}
+
+// The data directive emitted right after the label of `symbol`.
+fn directive_of<'a>(asm: &'a str, symbol: &str) -> Option<&'a str> {
+    let mut lines = asm.lines();
+    lines.find(|line| *line == format!("{}:", symbol))?;
+    lines.next().map(|line| line.trim())
+}
+
+#[test]
+fn char_global_init_is_a_byte() {
+    let asm = compile("char_init", "char c = 1; int main() { return c; }");
+    assert_eq!(directive_of(&asm, "c"), Some(".byte 1"), "{}", asm);
+}
+
+#[test]
+fn pointer_global_init_is_a_quad() {
+    let asm = compile("ptr_init", "int *p = 0; int main() { return 0; }");
+    assert_eq!(directive_of(&asm, "p"), Some(".quad 0"), "{}", asm);
+}
+
+// The directive emitted right before the label of `symbol`.
+fn directive_before<'a>(asm: &'a str, symbol: &str) -> Option<&'a str> {
+    let lines: Vec<&str> = asm.lines().collect();
+    let i = lines
+        .iter()
+        .position(|line| *line == format!("{}:", symbol))?;
+    lines.get(i.checked_sub(1)?).map(|line| line.trim())
+}
+
+#[test]
+fn globals_are_aligned() {
+    let asm = compile(
+        "align",
+        "char c; int *p; struct { char a; int b; } s; int n = 1; int main() { return 0; }",
+    );
+    assert_eq!(directive_before(&asm, "p"), Some(".align 8"), "{}", asm);
+    assert_eq!(directive_before(&asm, "s"), Some(".align 4"), "{}", asm);
+    assert_eq!(directive_before(&asm, "n"), Some(".align 4"), "{}", asm);
+    assert_eq!(directive_before(&asm, "c"), Some(".align 1"), "{}", asm);
+}
+
+#[test]
+fn symbols_have_type_and_size() {
+    let asm = compile("symbols", "int a[3]; char *p = 0; int main() { return 0; }");
+    for line in [
+        ".type a, @object",
+        ".size a, 12",
+        ".type p, @object",
+        ".size p, 8",
+        ".type main, @function",
+        ".size main, .-main",
+    ] {
+        assert!(asm.contains(&format!("\t{}\n", line)), "{}\n{}", line, asm);
+    }
+    assert_eq!(asm.matches(".note.GNU-stack").count(), 1, "{}", asm);
+}
+
+#[test]
+fn int_arithmetic_is_32_bit() {
+    let asm = compile(
+        "int32",
+        "int f(int a, int b) { return (a + b) * (a - b) / b; } int *g(int *p, int i) { return p + i; }",
+    );
+    let is_32 = |reg: &str| reg.starts_with('e') || reg.ends_with('d');
+    let f = &asm[asm.find("\nf:").unwrap()..asm.find("\ng:").unwrap()];
+    for op in ["add", "sub", "imul", "idiv"] {
+        let operands = f
+            .lines()
+            .filter_map(|l| l.strip_prefix(&format!("\t{} ", op)))
+            .find(|operands| !operands.starts_with("rsp"))
+            .unwrap();
+        assert!(operands.split(", ").all(is_32), "{}\n{}", op, asm);
+    }
+    assert!(f.contains("\tcdq\n"), "{}", asm);
+    // pointer arithmetic stays 64-bit
+    let g = &asm[asm.find("\ng:").unwrap()..];
+    let add = g.lines().find_map(|l| l.strip_prefix("\tadd ")).unwrap();
+    assert!(!add.split(", ").any(is_32), "{}", asm);
+}
+
+#[test]
+fn constant_shift_count_is_immediate() {
+    let asm = compile(
+        "shift_imm",
+        "int f(int x) { return (x << 3) + (x >> 2); } int g(int x, int n) { return x >> n; }",
+    );
+    let f = &asm[asm.find("\nf:").unwrap()..asm.find("\ng:").unwrap()];
+    assert!(f.contains(", 3\n") && f.contains("\tsar "), "{}", asm);
+    assert!(!f.contains("cl"), "{}", asm);
+    let g = &asm[asm.find("\ng:").unwrap()..];
+    assert!(g.contains("\tsar ") && g.contains(", cl\n"), "{}", asm);
+    assert!(!asm.contains("\tshr "), "{}", asm);
+}
+
+#[test]
+fn array_index_is_shifted() {
+    let asm = compile_with(
+        "index",
+        &["-O1"],
+        "int main() { int a[4]; int i = 2; a[i] = 3; return a[i]; }",
+    );
+    assert!(asm.contains("shl "), "{}", asm);
+    assert!(!asm.contains("imul"), "{}", asm);
+}
+
+#[test]
+fn division_by_power_of_two_is_shifted() {
+    let asm = compile_with("div", &["-O1"], "int main() { int x = -7; return x / 4; }");
+    assert!(asm.contains("sar "), "{}", asm);
+    assert!(!asm.contains("idiv"), "{}", asm);
+}
+
+#[test]
+fn values_live_across_call_in_callee_saved_registers() {
+    let asm = compile(
+        "call_clobber",
+        "int g(int x) { return x; } int f(int a) { return a * 3 + (a * 5 + (a * 7 + g(a))); }",
+    );
+    assert!(!asm.contains("push r10"), "{}", asm);
+    let f = &asm[asm.find("\nf:").unwrap()..];
+    let call = f.find("call g").unwrap();
+    // the three products are computed before the call, in 32 bits, and
+    // kept in callee-saved registers
+    let products: Vec<&str> = f[..call]
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("imul "))
+        .collect();
+    assert_eq!(products.len(), 3, "{}", asm);
+    for reg in products {
+        assert!(
+            ["ebx", "r12d", "r13d", "r14d", "r15d"].contains(&reg),
+            "{}",
+            asm
+        );
+    }
+}
+
+#[test]
+fn call_arguments_loaded_into_argument_registers() {
+    let asm = compile(
+        "precolor",
+        "int f(int a, int b, int c); int g() { int a = 1; int b = 2; int c = 3; return f(a, b, c); }",
+    );
+    for reg in ["rdi", "rsi", "rdx"] {
+        assert!(asm.contains(&format!("movsxd {}, ", reg)), "{}", asm);
+        assert!(!asm.contains(&format!("mov {}, ", reg)), "{}", asm);
+    }
+}
+
+// A frame of 16KB is allocated and touched a page at a time.
+#[test]
+fn large_frame_is_probed() {
+    let src = "int main() { char buf[16384]; buf[0] = 1; return buf[0]; }";
+    let asm = compile_with("probe", &["-fstack-clash-protection"], src);
+    assert!(asm.contains(".Lprobe0:\n"), "{}", asm);
+    assert!(
+        asm.contains("\tsub rsp, 4096\n\tor qword ptr [rsp], 0\n"),
+        "{}",
+        asm
+    );
+    assert!(asm.contains("\tjne .Lprobe0\n"), "{}", asm);
+
+    let asm = compile("no_probe", src);
+    assert!(
+        !asm.contains(".Lprobe") && !asm.contains("or qword ptr"),
+        "{}",
+        asm
+    );
+    assert!(asm.contains("\tsub rsp, 16392\n"), "{}", asm);
+}
+
+#[test]
+fn leaf_function_saves_no_registers() {
+    let asm = compile_with("leaf", &["-O1"], "int f() { return 1; }");
+    let f: Vec<&str> = asm[asm.find("\nf:").unwrap()..]
+        .lines()
+        .map(|line| line.trim())
+        .collect();
+    assert_eq!(
+        f,
+        [
+            "",
+            "f:",
+            "push rbp",
+            "mov rbp, rsp",
+            "mov r10, 1",
+            "mov rax, r10",
+            ".Lend0:",
+            "mov rsp, rbp",
+            "pop rbp",
+            "ret",
+            ".size f, .-f"
+        ],
+        "{}",
+        asm
+    );
+}
+
+#[test]
+fn no_jump_to_next_label() {
+    let asm = compile_with(
+        "jumps",
+        &["-O1"],
+        "int f(int x) { int y; if (x) y = 1; else y = 2; while (x > 0) x = x - 1; return y; }",
+    );
+    let lines: Vec<&str> = asm.lines().collect();
+    for pair in lines.windows(2) {
+        if let Some(label) = pair[0].trim().strip_prefix("jmp ") {
+            assert_ne!(pair[1], format!("{}:", label), "{}", asm);
+        }
+    }
+    // every label left is jumped to
+    for line in &lines {
+        if let Some(label) = line.strip_suffix(':').filter(|l| l.starts_with(".L")) {
+            if !label.starts_with(".Lend") {
+                assert!(asm.contains(&format!(" {}\n", label)), "{}", asm);
+            }
+        }
+    }
+}
+
+#[test]
+fn comparison_fused_into_branch() {
+    let asm = compile(
+        "fused",
+        "int f(int a, int b) { if (a < b) return 1; return 2; }",
+    );
+    let f = &asm[asm.find("\nf:").unwrap()..];
+    assert_eq!(f.matches("cmp ").count(), 1, "{}", asm);
+    assert!(f.contains("\tjge .L"), "{}", asm);
+    assert!(!f.contains("setl"), "{}", asm);
+}
+
+#[test]
+fn suffix_strings_are_merged() {
+    let asm = compile_with(
+        "merge",
+        &["-fmerge-strings"],
+        "int main() { char *a = \"hello\"; char *b = \"lo\"; return b - a; }",
+    );
+    assert_eq!(asm.matches(".string").count(), 1, "{}", asm);
+    assert!(asm.contains(".set .L.str2, .L.str1+3"), "{}", asm);
+}
+
+#[test]
+fn output_file_matches_stdout() {
+    let src = "int g = 2; int f(int x) { return x * g; } int main() { return f(3); }";
+    let asm = compile("output", src);
+    let dir = common::tmp_dir("out");
+    fs::create_dir_all(dir.join("build")).unwrap();
+    let path = dir.join("foo.c");
+    fs::write(&path, src).unwrap();
+    let run = |out_path: &str| {
+        Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+            .arg("-o")
+            .arg(dir.join(out_path))
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let out = run("build/foo.s");
+    assert!(out.status.success() && out.stdout.is_empty());
+    assert_eq!(fs::read_to_string(dir.join("build/foo.s")).unwrap(), asm);
+    let out = run("missing/foo.s");
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).starts_with("cannot write"),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// -MD writes a make rule for the object to `<stem>.d`, or to the file
+// given with -MF.
+#[test]
+fn dependency_file_lists_headers() {
+    let dir = common::tmp_dir("dep");
+    fs::write(dir.join("dep.h"), "int f();\n").unwrap();
+    fs::write(
+        dir.join("dep.c"),
+        "#include \"dep.h\"\nint main() { return 0; }\n",
+    )
+    .unwrap();
+    let run = |flags: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+            .args(flags)
+            .arg("dep.c")
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    };
+    run(&["-MD"]);
+    assert_eq!(
+        fs::read_to_string(dir.join("dep.d")).unwrap(),
+        "dep.o: dep.c dep.h\n"
+    );
+    run(&["-MD", "-MF", "other.d"]);
+    assert_eq!(
+        fs::read_to_string(dir.join("other.d")).unwrap(),
+        "dep.o: dep.c dep.h\n"
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn att_syntax() {
+    let asm = compile_with(
+        "att",
+        &["--syntax=att"],
+        "int g; int f(int *p, char c) { int x = *p; g = c; return x << c; } int main() { int (*h)(int *, char) = f; int y = 2; return h(&y, 1); }",
+    );
+    assert!(asm.starts_with(".att_syntax\n"), "{}", asm);
+    for insn in [
+        "\tpushq %rbp\n",
+        "\tmovq %rsp, %rbp\n",
+        "\tmovq %rdi, -8(%rbp)\n",
+        "\tmovb %sil, -9(%rbp)\n",
+        "\tmovl (%r11), %r10d\n",
+        "\tmovsbq ",
+        "\tmovslq ",
+        "\tleaq g, ",
+        "\tshll %cl, ",
+        "\tmovq $0, %rax\n",
+        "\tcall *%",
+    ] {
+        assert!(asm.contains(insn), "{}\n{}", insn, asm);
+    }
+    assert!(!asm.contains('['), "{}", asm);
+}
+
+#[test]
+fn self_xor_is_zero() {
+    let asm = compile_with(
+        "self_xor",
+        &["-O1"],
+        "int main() { int x = 5; int *p = &x; return (x ^ x) + (*p - *p); }",
+    );
+    let main = asm.split("main:").nth(1).unwrap();
+    // the only subtraction allocates the frame
+    assert!(!main.contains("xor"), "{}", asm);
+    assert_eq!(main.matches("\tsub ").count(), 1, "{}", asm);
+    assert!(main.contains(", 0\n"), "{}", asm);
+}
@@ -0,0 +1,125 @@
+// Times the compilation of large generated sources. Run with
+// `cargo test --release --test bench -- --ignored --nocapture`.
+
+mod common;
+
+use mir9cc::compile;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+// `n` small functions with locals, loops, branches, calls and string
+// literals, so every pass has work to do.
+fn source(n: usize) -> String {
+    let mut src = String::from("int printf();\nint g;\n");
+    for i in 0..n {
+        src.push_str(&format!(
+            "int f{0}(int a, int b) {{\n\
+             \x20 int x = a + {0};\n\
+             \x20 int y = b * 3;\n\
+             \x20 int i;\n\
+             \x20 for (i = 0; i < 10; i++) {{\n\
+             \x20   if (x < y)\n\
+             \x20     x = x + i;\n\
+             \x20   else\n\
+             \x20     y = y - i;\n\
+             \x20 }}\n\
+             \x20 switch (x % 4) {{\n\
+             \x20 case 0:\n\
+             \x20   g++;\n\
+             \x20   break;\n\
+             \x20 default:\n\
+             \x20   printf(\"%d\\n\", x);\n\
+             \x20 }}\n\
+             \x20 return x + y;\n\
+             }}\n\n",
+            i
+        ));
+    }
+    src.push_str("int main() {\n  int s = 0;\n");
+    for i in 0..n {
+        src.push_str(&format!("  s = s + f{}(s, {});\n", i, i));
+    }
+    src.push_str("  return s;\n}\n");
+    src
+}
+
+#[test]
+#[ignore]
+fn compile_large_source() {
+    // about 20k lines
+    let src = source(1000);
+    let lines = src.lines().count();
+    let runs = 5;
+    let start = Instant::now();
+    for _ in 0..runs {
+        compile(&src).unwrap();
+    }
+    let elapsed = start.elapsed() / runs;
+    println!("{} lines: {:?} per compilation", lines, elapsed);
+}
+
+// Times the compiler binary `bin` on `path`, passing `args` first.
+fn time_binary(bin: &OsStr, args: &[&str], path: &Path, runs: u32) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        let out = Command::new(bin).args(args).arg(path).output().unwrap();
+        assert!(out.status.success());
+    }
+    start.elapsed() / runs
+}
+
+fn time_jobs(path: &Path, jobs: usize, runs: u32) -> Duration {
+    let bin = OsStr::new(env!("CARGO_BIN_EXE_mir9cc"));
+    time_binary(bin, &["-j", &jobs.to_string()], path, runs)
+}
+
+#[test]
+#[ignore]
+fn compile_in_parallel() {
+    let dir = common::tmp_dir("bench");
+    let path = dir.join("bench.c");
+    std::fs::write(&path, source(500)).unwrap();
+    let jobs = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let runs = 5;
+    let sequential = time_jobs(&path, 1, runs);
+    let parallel = time_jobs(&path, jobs, runs);
+    println!(
+        "500 functions: {:?} with -j1, {:?} with -j{}",
+        sequential, parallel, jobs
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Compares this build with another one of the compiler, e.g. built from
+// the commit before a change:
+//
+//   MIR9CC_BASELINE=/path/to/old/mir9cc \
+//     cargo test --release --test bench -- --ignored --nocapture
+#[test]
+#[ignore]
+fn compare_with_baseline() {
+    let baseline = match std::env::var_os("MIR9CC_BASELINE") {
+        Some(baseline) => baseline,
+        None => {
+            println!("set MIR9CC_BASELINE to the compiler to compare with");
+            return;
+        }
+    };
+    let dir = common::tmp_dir("baseline");
+    let path = dir.join("bench.c");
+    let src = source(1000);
+    std::fs::write(&path, &src).unwrap();
+    let runs = 5;
+    let before = time_binary(&baseline, &[], &path, runs);
+    let after = time_binary(OsStr::new(env!("CARGO_BIN_EXE_mir9cc")), &[], &path, runs);
+    println!(
+        "{} lines: {:?} before, {:?} after ({:.2}x)",
+        src.lines().count(),
+        before,
+        after,
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+// Helpers shared by the integration tests. Each test file uses only
+// some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// Returns the directory for the files of `test`, private to this process.
+pub fn tmp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mir9cc-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Writes `src` to `<name>.c` in the directory of `test`, runs mir9cc on
+// it with `flags` and removes the file again.
+pub fn mir9cc(test: &str, name: &str, flags: &[&str], src: &str) -> Output {
+    let path = tmp_dir(test).join(format!("{}.c", name));
+    fs::write(&path, src).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+        .args(flags)
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&path);
+    out
+}
+
+// Writes `src` to `<test>.c`, builds and runs it with `--run`, passing
+// `flags` before the file and `link` after `--`, and returns the output
+// of the program. The directory of `test` is removed once it's empty.
+pub fn run(test: &str, flags: &[&str], src: &str, link: &[&Path]) -> Output {
+    let dir = tmp_dir(test);
+    let path = dir.join(format!("{}.c", test));
+    fs::write(&path, src).unwrap();
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_mir9cc"));
+    cmd.arg("--run").args(flags).arg(&path);
+    if !link.is_empty() {
+        cmd.arg("--").args(link);
+    }
+    let out = cmd.output().unwrap();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir(&dir);
+    out
+}
+
+// Compiles `src`, which must succeed, and returns the printed output.
+pub fn assembly(test: &str, name: &str, flags: &[&str], src: &str) -> String {
+    let out = mir9cc(test, name, flags, src);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).to_string()
+}
+
+// Compiles `src` and returns whether it succeeded and its stderr.
+pub fn diagnostics(test: &str, name: &str, flags: &[&str], src: &str) -> (bool, String) {
+    let out = mir9cc(test, name, flags, src);
+    (
+        out.status.success(),
+        String::from_utf8_lossy(&out.stderr).to_string(),
+    )
+}
@@ -0,0 +1,90 @@
+// Uses the compiler as a library, without spawning processes.
+
+use mir9cc::compile;
+
+#[test]
+fn compiles_to_assembly() {
+    let asm = compile("int main() { return 42; }").unwrap();
+    assert!(asm.starts_with(".intel_syntax noprefix\n"), "{}", asm);
+    assert!(asm.contains("main:"), "{}", asm);
+}
+
+#[test]
+fn state_is_reset_between_compilations() {
+    let src = "int g = 3; int f(int x) { return x + g; } int main() { return f(2); }";
+    let first = compile(src).unwrap();
+    // leaves globals, labels and errors behind
+    let _ = compile("int h; int main() { int y; return undefined_var + y; }");
+    let second = compile(src).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn reports_the_first_error() {
+    let e = compile("int main() {\n  int x;\n  x = ;\n  return y;\n}\n").unwrap_err();
+    assert_eq!(e.msg, "expected expression but got ';'");
+}
+
+#[test]
+fn jumps_outside_of_loops() {
+    let e = compile("int main(){break;}").unwrap_err();
+    assert_eq!(e.msg, "'break' statement not in loop or switch statement");
+    let e = compile("int main(){switch(1){case 1: continue;} return 0;}").unwrap_err();
+    assert_eq!(e.msg, "'continue' statement not in loop statement");
+    assert!(compile("int main(){for(;;){switch(1){case 1: continue;} break;} return 0;}").is_ok());
+}
+
+#[test]
+fn missing_include() {
+    let e = compile("#include \"no-such-header.h\"\nint main() { return 0; }").unwrap_err();
+    assert_eq!(e.msg, "failed to read file: no-such-header.h");
+}
+
+#[test]
+fn string_labels_are_numbered_per_compilation() {
+    let src = "int main() { char *s = \"hi\"; return s[0]; }";
+    let _ = compile("int main() { char *a = \"a\"; char *b = \"b\"; return 0; }");
+    let asm = compile(src).unwrap();
+    assert!(asm.contains(".L.str1:"), "{}", asm);
+}
+
+#[test]
+fn global_initialized_with_enum_constant() {
+    let asm = compile("enum { K = 7 }; int g = K; int main() { return g; }").unwrap();
+    assert!(asm.contains("g:\n\t.long 7\n"), "{}", asm);
+}
+
+#[test]
+fn stray_semicolons_at_file_scope() {
+    let asm =
+        compile("int x;;\n;\nint f() { return 1; };\nint main() { return x + f(); }").unwrap();
+    assert!(asm.contains("\nf:"), "{}", asm);
+    assert!(asm.contains("\nx:"), "{}", asm);
+}
+
+#[test]
+fn options_are_reset_between_compilations() {
+    *mir9cc::gen_x86::ATT_SYNTAX.lock().unwrap() = true;
+    *mir9cc::gen_x86::PIC.lock().unwrap() = true;
+    let asm = compile("int g; int main() { return g; }").unwrap();
+    assert!(asm.starts_with(".intel_syntax noprefix\n"), "{}", asm);
+    assert!(
+        !asm.contains("@GOTPCREL") && !asm.contains("[rip"),
+        "{}",
+        asm
+    );
+}
+
+#[test]
+fn malformed_input_is_an_error() {
+    let e = compile("int main() { return 1 @ 2; }").unwrap_err();
+    assert_eq!(e.msg, "stray '@' in program");
+    let e = compile("int main() { return 0; } /* no end").unwrap_err();
+    assert_eq!(e.msg, "unterminated comment");
+    let e = compile("#define F(a, b) a\nint main() { return F(1); }").unwrap_err();
+    assert_eq!(e.msg, "macro 'F' takes 2 arguments, but 1 given");
+    let e = compile("#define F(a b) a\nint main() { return 0; }").unwrap_err();
+    assert_eq!(e.msg, "expected ',' or ')' in macro parameter list");
+    let e = compile("#define 1\nint main() { return 0; }").unwrap_err();
+    assert_eq!(e.msg, "macro name expected");
+}
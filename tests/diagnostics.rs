@@ -0,0 +1,687 @@
+// Checks the warnings and errors reported for a few snippets.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+// Compiles `src` and returns whether it succeeded and its stderr.
+fn compile(name: &str, src: &str) -> (bool, String) {
+    compile_with(name, &[], src)
+}
+
+fn compile_with(name: &str, flags: &[&str], src: &str) -> (bool, String) {
+    common::diagnostics("diag", name, flags, src)
+}
+
+#[test]
+fn negative_char_case_in_range() {
+    let (ok, stderr) = compile(
+        "char_case_neg",
+        "int main() { char c = -1; switch (c) { case -1: return 1; case -128: return 2; case 127: return 3; } return 0; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+}
+
+#[test]
+fn char_case_out_of_range() {
+    let (ok, stderr) = compile(
+        "char_case_range",
+        "int main() { char c = 0; switch (c) { case 300: return 1; case -129: return 2; } return 0; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("case value 300 is out of range of 'char' (-128..127)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("case value -129 is out of range of 'char' (-128..127)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn char_case_duplicate_after_conversion() {
+    let (ok, stderr) = compile(
+        "char_case_dup",
+        "int main() { char c = 0; switch (c) { case 0: return 1; case 256: return 2; case -1: return 3; case 255: return 4; } return 0; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("duplicate case value 0 after conversion to 'char' (previous label was 0)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr
+            .contains("duplicate case value -1 after conversion to 'char' (previous label was -1)"),
+        "{}",
+        stderr
+    );
+}
+
+// Conflicting file-scope declarations are all reported, whether they
+// declare functions or variables. Compatible ones are merged.
+#[test]
+fn conflicting_declarations() {
+    let (ok, stderr) = compile(
+        "redecl",
+        "int f(int a);\nint f(char *a);\nint g = 1;\nint g = 2;\nint h;\nint h(void);\nextern char g;\nint k;\nint k = 3;\nint f();\nint main() { return g + k; }\n",
+    );
+    assert!(!ok);
+    for (pos, msg) in [
+        ("2:5", "conflicting types for 'f'"),
+        ("4:5", "redefinition of 'g'"),
+        ("6:5", "'h' redeclared as different kind of symbol"),
+        ("7:13", "conflicting types for 'g'"),
+    ] {
+        assert!(
+            stderr.contains(&format!("redecl.c:{}: error: {}\n", pos, msg)),
+            "{}",
+            stderr
+        );
+    }
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+}
+
+#[test]
+fn call_to_function_defined_below() {
+    let (ok, stderr) = compile(
+        "call_below",
+        "int main() { return helper(3); }\nint helper(int x) { return x * 2; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(stderr.is_empty(), "{}", stderr);
+}
+
+#[test]
+fn address_of_register_variable() {
+    let (ok, stderr) = compile(
+        "register_addr",
+        "int main() { register int i = 3; auto int j = 4; int *p = &j; p = &i; return i; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("warning: address of register variable 'i' requested"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("'j'"), "{}", stderr);
+}
+
+#[test]
+fn assignment_to_rvalue() {
+    let (ok, stderr) = compile(
+        "rvalue",
+        "int main() {\n int x = 1;\n 1 = 2;\n (x++) = 5;\n --x = 1;\n x++ ++;\n return x;\n}",
+    );
+    assert!(!ok);
+    for line in 3..=6 {
+        assert!(
+            stderr.contains(&format!("rvalue.c:{}:", line)),
+            "{}",
+            stderr
+        );
+    }
+    assert_eq!(
+        stderr.matches("error: not an lvalue").count(),
+        4,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn address_of_rvalue() {
+    let (ok, stderr) = compile(
+        "addr_rvalue",
+        "int main() {\n  int x = 1;\n  int *p = &(x + 1);\n  return *p;\n}\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("addr_rvalue.c:3:14: error: cannot take the address of an rvalue"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn array_initialized_from_scalar() {
+    let (ok, stderr) = compile("ary_init", "int main() {\n  int a[2] = 1;\n  return 0;\n}");
+    assert!(!ok);
+    assert!(
+        stderr.contains("ary_init.c:2:7: error: cannot assign to 'a' of array type 'int [2]'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn duplicate_case_value() {
+    let (ok, stderr) = compile(
+        "dup_case",
+        "int main() { int x = 1;\n switch (x) {\n case 1: return 1;\n case 1: return 2; } return 0; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("duplicate case value 1 (previous case at line 3)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn multiple_default_labels() {
+    let (ok, stderr) = compile(
+        "dup_default",
+        "int main() { int x = 1;\n switch (x) {\n default: return 1;\n default: return 2; } return 0; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("multiple default labels in one switch (previous default at line 3)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn switch_missing_enumerator() {
+    let src = "enum color { RED, GREEN, BLUE };\nint f(enum color c) {\n  switch (c) {\n  case RED:\n    return 1;\n  case GREEN:\n    return 2;\n  }\n  return 0;\n}\nint main() { return f(RED); }\n";
+    let (ok, stderr) = compile_with("switch_enum", &["-Wswitch"], src);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains(
+            "switch_enum.c:3:3: warning: enumeration values not handled in switch: 'BLUE'"
+        ),
+        "{}",
+        stderr
+    );
+
+    let with_default = src.replace(
+        "    return 2;\n",
+        "    return 2;\n  default:\n    return 3;\n",
+    );
+    let (ok, stderr) = compile_with("switch_default", &["-Wswitch"], &with_default);
+    assert!(ok && stderr.is_empty(), "{}", stderr);
+}
+
+#[test]
+fn int_case_not_range_checked() {
+    let (ok, stderr) = compile(
+        "int_case",
+        "int main() { int x = 300; switch (x) { case 300: return 1; case 44: return 2; } return 0; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+}
+
+#[test]
+fn flexible_array_member_not_last() {
+    let (ok, stderr) = compile(
+        "flexible",
+        "struct s {\n  int n;\n  char data[];\n  int m;\n};\nint main() { return sizeof(struct s); }\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains(
+            "flexible.c:4:3: error: flexible array member 'data' must be the last member"
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn sizeof_incomplete_struct() {
+    let (ok, stderr) = compile(
+        "sizeof_incomplete",
+        "struct notyet; int main() { struct notyet *p; return sizeof(struct notyet) + sizeof(*p); }",
+    );
+    assert!(!ok);
+    assert_eq!(
+        stderr
+            .matches("invalid application of 'sizeof' to incomplete type 'struct notyet'")
+            .count(),
+        2,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn alignof_incomplete_struct() {
+    let (ok, stderr) = compile(
+        "alignof_incomplete",
+        "int main() { return _Alignof(struct notyet); }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("invalid application of '_Alignof' to incomplete type 'struct notyet'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn assign_to_array_or_function() {
+    let (ok, stderr) = compile(
+        "assign_array",
+        "int g[2]; int f() { return 1; } int main() { int arr[3]; int *p = arr; arr = p; g = p; arr++; f = 0; return 0; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("cannot assign to 'arr' of array type 'int [3]'"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("cannot assign to 'g' of array type 'int [2]'"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("cannot assign to function 'f'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn assign_through_array() {
+    let (ok, stderr) = compile(
+        "assign_elem",
+        "int main() { int arr[3]; arr[0] = 1; *arr = 2; *(arr + 1) = 3; return arr[0]; }",
+    );
+    assert!(ok, "{}", stderr);
+}
+
+#[test]
+fn invalid_cast() {
+    let (ok, stderr) = compile(
+        "invalid_cast",
+        "struct s { int a; }; int main() { struct s x; int y = (int)x; (void)x; return y; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("invalid cast from 'struct s' to 'int'"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("invalid cast").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn int_conversion() {
+    let src =
+        "int main() { int x = 1; int *p = 5; int *q = 0; int *r = 1 - 1; p = x; x = q; return 0; }";
+    let (ok, stderr) = compile("int_conversion", src);
+    assert!(ok, "{}", stderr);
+    assert_eq!(
+        stderr
+            .matches("incompatible integer to pointer conversion assigning to 'int *' from 'int'")
+            .count(),
+        2,
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr
+            .contains("incompatible pointer to integer conversion assigning to 'int' from 'int *'"),
+        "{}",
+        stderr
+    );
+
+    let (ok, stderr) = compile_with("int_conversion_off", &["-Wno-int-conversion"], src);
+    assert!(ok, "{}", stderr);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+}
+
+#[test]
+fn incompatible_pointer_types() {
+    let (ok, stderr) = compile(
+        "ptr_types",
+        "int main() {\n  int a = 1;\n  int *p = &a;\n  char *c = p;\n  void *v = p;\n  p = v;\n  c = v;\n  return *c;\n}\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains(
+            "ptr_types.c:4:13: error: incompatible pointer types assigning to 'char *' from 'int *'"
+        ),
+        "{}",
+        stderr
+    );
+    // void * converts to and from any object pointer
+    assert_eq!(stderr.matches("error").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn unused_variable() {
+    let src = "int f(int p) {\n  return 0;\n}\nint main() {\n  int used = 1;\n  int unused;\n  return used + f(1);\n}\n";
+    let (ok, stderr) = compile_with("unused", &["-Wunused"], src);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("unused.c:6:7: warning: unused variable 'unused'"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("warning").count(), 1, "{}", stderr);
+
+    let (ok, stderr) = compile_with("unused_param", &["-Wunused-parameter"], src);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("unused_param.c:1:11: warning: unused parameter 'p'"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("warning").count(), 1, "{}", stderr);
+
+    let (ok, stderr) = compile("unused_off", src);
+    assert!(ok && stderr.is_empty(), "{}", stderr);
+}
+
+// Taking the address of a variable or casting it to void uses it.
+#[test]
+fn unused_warning_suppressed() {
+    let (ok, stderr) = compile_with(
+        "used",
+        &["-Wunused", "-Wunused-parameter"],
+        "int g(int *p);\nint f(int p) {\n  (void)p;\n  return 0;\n}\nint main() {\n  int a;\n  int b;\n  (void)b;\n  return g(&a) + f(1);\n}\n",
+    );
+    assert!(ok && stderr.is_empty(), "{}", stderr);
+}
+
+// Only the first dead statement is reported, and falling through to a
+// case label isn't.
+#[test]
+fn unreachable_code() {
+    let src = "int main(int x) {\n  if (x) {\n    return 1;\n    x = 2;\n    x = 4;\n  }\n  switch (x) {\n  case 1:\n    x = 3;\n  case 2:\n    break;\n  }\n  return 0;\n}\n";
+    let (ok, stderr) = compile_with("unreachable", &["-Wunreachable-code"], src);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("unreachable.c:4:5: warning: code will never be executed"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("warning").count(), 1, "{}", stderr);
+
+    let (ok, stderr) = compile("unreachable_off", src);
+    assert!(ok && stderr.is_empty(), "{}", stderr);
+}
+
+#[test]
+fn uninitialized_variable() {
+    let (ok, stderr) = compile_with(
+        "uninit",
+        &["-Wuninitialized"],
+        "int main() {\n  int x;\n  return x;\n}\n",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("uninit.c:3:10: warning: variable 'x' is used uninitialized"),
+        "{}",
+        stderr
+    );
+
+    let (ok, stderr) = compile_with(
+        "assigned",
+        &["-Wuninitialized"],
+        "int main() {\n  int x;\n  x = 2;\n  return x;\n}\n",
+    );
+    assert!(ok && stderr.is_empty(), "{}", stderr);
+}
+
+// Parsing resumes after a bad statement, so every error is reported in
+// one run. A variable with a bad initializer is still defined, and the
+// variable of a `for` with a bad header still goes out of scope.
+#[test]
+fn errors_reported_together() {
+    let (ok, stderr) = compile(
+        "three",
+        "int main() {\n  int x = ;\n  int y = x + 1;\n  for (int i = 0; i < ; i++)\n    y = y + i;\n  int *p = &y +;\n  return x + *p + i;\n}\n",
+    );
+    assert!(!ok);
+    for pos in ["2:11", "4:23", "6:16"] {
+        assert!(
+            stderr.contains(&format!(
+                "three.c:{}: error: expected expression but got ';'",
+                pos
+            )),
+            "{}",
+            stderr
+        );
+    }
+    assert!(
+        stderr.contains("three.c:7:19: error: 'i' is not defined"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+}
+
+// Each bad statement is reported once, at its own position, and the
+// walk goes on with the next one.
+#[test]
+fn sema_errors_reported_together() {
+    let (ok, stderr) = compile(
+        "sema_errors",
+        "int main() {\n  int x;\n  int *p = &x;\n  1 = 2;\n  x = *x;\n  p = p + p;\n  x = x.a;\n  return x;\n}\n",
+    );
+    assert!(!ok);
+    for (pos, msg) in [
+        ("4:3", "not an lvalue"),
+        ("5:7", "operand must be a pointer"),
+        ("6:7", "pointer + pointer is not defined"),
+        ("7:8", "request for member 'a' in something not a structure"),
+    ] {
+        assert!(
+            stderr.contains(&format!("sema_errors.c:{}: error: {}\n", pos, msg)),
+            "{}",
+            stderr
+        );
+    }
+    assert_eq!(stderr.matches("error:").count(), 4, "{}", stderr);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+}
+
+#[test]
+fn for_variable_out_of_scope() {
+    let (ok, stderr) = compile(
+        "for_scope",
+        "int main() { for (int i = 0; i < 2; i++) ; return i; }",
+    );
+    assert!(!ok);
+    assert!(stderr.contains("'i' is not defined"), "{}", stderr);
+}
+
+#[test]
+fn misspelled_variable() {
+    let (ok, stderr) = compile(
+        "typo",
+        "int main() {\n  int count = 1;\n  return conut;\n}\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("typo.c:3:10: error: 'conut' is not defined; did you mean 'count'?"),
+        "{}",
+        stderr
+    );
+
+    let (ok, stderr) = compile(
+        "no_typo",
+        "int main() {\n  int count = 1;\n  return zebra;\n}\n",
+    );
+    assert!(!ok);
+    assert!(stderr.contains("'zebra' is not defined\n"), "{}", stderr);
+    assert!(!stderr.contains("did you mean"), "{}", stderr);
+}
+
+#[test]
+fn struct_assigned_from_other_tag() {
+    let (ok, stderr) = compile(
+        "struct_tag",
+        "struct P { int x; }; struct Q { int x; };\nint main() { struct P a; struct Q b; a = b; return 0; }",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("2:38: error: incompatible types assigning to 'struct P' from 'struct Q'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn unknown_struct_member() {
+    let (ok, stderr) = compile(
+        "member",
+        "struct vec { int len; int cap; };\nint main() {\n  struct vec v;\n  struct vec *p = &v;\n  int x;\n  v.len = 1;\n  return v.lenn + p->capp + x.len;\n}\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains(
+            "member.c:7:11: error: no member named 'lenn' in 'struct vec'; members are: 'len', 'cap'"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "member.c:7:20: error: no member named 'capp' in 'struct vec'; members are: 'len', 'cap'"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "member.c:7:30: error: request for member 'len' in something not a structure"
+        ),
+        "{}",
+        stderr
+    );
+}
+
+// A constant zero divisor is an error where the value is needed at
+// compile time and a warning elsewhere. Other divisors are left alone.
+#[test]
+fn division_by_zero() {
+    let src = "int main(int x) {\n  int y = 5 / 0;\n  int z = x % 0;\n  int w = 5 / x;\n  return y + z + w;\n}\n";
+    let (ok, stderr) = compile("div_zero", src);
+    assert!(ok, "{}", stderr);
+    assert!(
+        stderr.contains("div_zero.c:2:11: warning: division by zero is undefined"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("div_zero.c:3:11: warning: remainder by zero is undefined"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("warning").count(), 2, "{}", stderr);
+
+    let (ok, stderr) = compile(
+        "div_zero_const",
+        "int a[4 / 0];\nint main() { return 0; }\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("div_zero_const.c:1:7: error: division by zero in constant expression"),
+        "{}",
+        stderr
+    );
+
+    // the global is still declared, and an unselected branch is not
+    // evaluated
+    let (ok, stderr) = compile(
+        "div_zero_global",
+        "int g = 1 / 0;\nint h = 1 ? 2 : 1 / 0;\nint main() { return g + h; }\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains("div_zero_global.c:1:9: error: division by zero in constant expression"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn non_constant_global_initializer() {
+    let (ok, stderr) = compile(
+        "non_const_init",
+        "int x = 3;\nint g = x;\nint main() { return g; }\n",
+    );
+    assert!(!ok);
+    assert!(
+        stderr.contains(
+            "non_const_init.c:2:9: error: initializer element is not a compile-time constant"
+        ),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn call_of_non_function() {
+    let (ok, stderr) = compile("call_int", "int main() { int x = 1; return x(2); }");
+    assert!(!ok);
+    assert!(
+        stderr.contains("1:32: error: called object is not a function"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn version_needs_no_file() {
+    let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+        .arg("--version")
+        .arg("missing.c")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.starts_with(&format!("mir9cc {} (", env!("CARGO_PKG_VERSION"))),
+        "{}",
+        stdout
+    );
+}
+
+// Each file counts its own lines, whether it is included or includes.
+#[test]
+fn lines_of_included_file() {
+    let dir = common::tmp_dir("include-test");
+    fs::write(dir.join("lines.h"), "int a;\nint b;\n\nint c = ;\n").unwrap();
+    fs::write(
+        dir.join("lines.c"),
+        "#include \"lines.h\"\nint main() {\n  return x;\n}\n",
+    )
+    .unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+        .arg("lines.c")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        stderr.contains("In file included from lines.c:1:"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("lines.h:4:9: error: expected expression"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("lines.c:3:10: error: 'x' is not defined"),
+        "{}",
+        stderr
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
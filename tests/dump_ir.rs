@@ -0,0 +1,228 @@
+// Checks the IR printed by `-dump-ir1` for a few snippets, at -O1 unless
+// given other flags, and what the passes report with `-fstats`.
+
+mod common;
+
+fn dump_ir1(name: &str, src: &str) -> String {
+    let stdout = common::assembly("ir", name, &["-O1", "-dump-ir1"], src);
+    // the IR dump comes before the assembly
+    stdout.split(".intel_syntax").next().unwrap().to_string()
+}
+
+#[test]
+fn add_zero_is_folded() {
+    let ir = dump_ir1("add_zero", "int main() { int x = 3; return x + 0; }");
+    assert!(!ir.contains("Add"), "{}", ir);
+}
+
+#[test]
+fn mul_one_is_folded() {
+    let ir = dump_ir1("mul_one", "int main() { int x = 3; return 1 * x / 1; }");
+    assert!(!ir.contains("Mul") && !ir.contains("Div"), "{}", ir);
+}
+
+#[test]
+fn mul_zero_keeps_side_effects() {
+    let ir = dump_ir1(
+        "mul_zero",
+        "int f(); int main() { int x = 3; return x * 0 + f() * 0; }",
+    );
+    assert!(ir.contains("Call"), "{}", ir);
+}
+
+#[test]
+fn void_cast_keeps_only_side_effects() {
+    let ir = dump_ir1(
+        "void_cast",
+        "int f(); int main() { int x = 2; (void)(x + 3); (void)f(); return 0; }",
+    );
+    assert!(!ir.contains("Add"), "{}", ir);
+    assert!(!ir.contains(", 3 ;"), "{}", ir);
+    assert!(ir.contains("Call"), "{}", ir);
+}
+
+#[test]
+fn casts_show_extension() {
+    let ir = dump_ir1(
+        "casts",
+        "int main() { int x = 300; int *p = &x; return (char)x + (_Bool)x + (int)p; }",
+    );
+    assert!(ir.contains("Movsx1"), "{}", ir);
+    assert!(ir.contains("Ne"), "{}", ir);
+    assert!(ir.contains("Movsx4"), "{}", ir);
+}
+
+#[test]
+fn loads_have_the_size_of_their_type() {
+    let ir = dump_ir1(
+        "load_sizes",
+        "int main() { char c = 1; int i = 2; int *p = &i; char *q = &c; return c + i + *p + *q; }",
+    );
+    // c and *q, i and *p, p and q
+    assert_eq!(ir.matches("Load1 ").count(), 2, "{}", ir);
+    assert_eq!(ir.matches("Load4 ").count(), 2, "{}", ir);
+    assert_eq!(ir.matches("Load8 ").count(), 2, "{}", ir);
+}
+
+#[test]
+fn ir_has_source_lines() {
+    let ir = dump_ir1("lines", "int main() {\n  int x = 3;\n  return x * 3;\n}\n");
+    assert!(
+        ir.lines()
+            .any(|l| l.starts_with("Imm") && l.ends_with(", 3 ; line 2")),
+        "{}",
+        ir
+    );
+    assert!(
+        ir.lines()
+            .any(|l| l.starts_with("Mul") && l.ends_with("; line 3")),
+        "{}",
+        ir
+    );
+}
+
+#[test]
+fn constant_branch_drops_dead_arm() {
+    let ir = dump_ir1(
+        "dead_arm",
+        "int main() { int x; if (1) x = 2; else x = 345; return x; }",
+    );
+    assert!(!ir.contains("Br"), "{}", ir);
+    assert!(!ir.contains(", 345"), "{}", ir);
+}
+
+#[test]
+fn overwritten_store_is_removed() {
+    let ir = dump_ir1(
+        "dead_store",
+        "int main() { int x = 1; x = 2; x = 3; return x; }",
+    );
+    assert_eq!(ir.matches("Store4").count(), 1, "{}", ir);
+}
+
+#[test]
+fn store_to_escaped_slot_is_kept() {
+    let ir = dump_ir1(
+        "escaped_store",
+        "int g(int *p); int main() { int x = 1; int *p = &x; x = 2; x = 3; return *p; }",
+    );
+    // x = 1, p = &x, x = 2 and x = 3
+    assert_eq!(ir.matches("Store").count(), 4, "{}", ir);
+}
+
+#[test]
+fn stats_report_dce() {
+    let (ok, stderr) = common::diagnostics(
+        "ir",
+        "stats",
+        &["-O1", "-fstats"],
+        "int main() { int x = 1; x = 2; if (0) return 3; return x; }",
+    );
+    assert!(ok, "{}", stderr);
+    assert!(stderr.contains("dce: "), "{}", stderr);
+    assert!(stderr.contains(" 1 dead stores removed"), "{}", stderr);
+}
+
+fn dump_ir2(name: &str, flags: &[&str], src: &str) -> String {
+    let flags = [&["-dump-ir2"], flags].concat();
+    let stdout = common::assembly("ir", name, &flags, src);
+    stdout.split(".intel_syntax").next().unwrap().to_string()
+}
+
+#[test]
+fn copies_are_propagated_with_o2() {
+    let src = "int main() { int a = 1; int b = 2; return a + b * 3; }";
+    let plain = dump_ir2("copies", &[], src);
+    let optimized = dump_ir2("copies_o", &["-O2"], src);
+    let copies = |ir: &str| ir.lines().filter(|l| l.starts_with("Mov ")).count();
+    assert!(copies(&plain) > 0, "{}", plain);
+    assert_eq!(copies(&optimized), 0, "{}", optimized);
+}
+
+#[test]
+fn repeated_address_is_reused_with_o2() {
+    let src = "struct P { int x; int y; }; int main() { struct P s; s.y = 3; return s.y + s.y; }";
+    let lea = |ir: &str| ir.lines().filter(|l| l.starts_with("Lea ")).count();
+    let adds = |ir: &str| ir.lines().filter(|l| l.starts_with("Add")).count();
+    let plain = dump_ir2("struct_addr", &[], src);
+    let optimized = dump_ir2("struct_addr_o", &["-O2"], src);
+    assert_eq!(lea(&plain), 3, "{}", plain);
+    assert_eq!(lea(&optimized), 1, "{}", optimized);
+    // s.y is loaded once and its address computed once
+    assert_eq!(adds(&optimized), 2, "{}", optimized);
+    assert_eq!(optimized.matches("Load4").count(), 1, "{}", optimized);
+}
+
+#[test]
+fn volatile_loads_are_kept_with_o2() {
+    let src = "int g; int main() { return g + g; }";
+    let plain = dump_ir2("twice", &["-O2"], src);
+    assert_eq!(plain.matches("Load4").count(), 1, "{}", plain);
+    let volatile = dump_ir2("volatile_twice", &["-O2"], &format!("volatile {}", src));
+    assert_eq!(
+        volatile.matches("Load4 volatile").count(),
+        2,
+        "{}",
+        volatile
+    );
+}
+
+#[test]
+fn ternary_result_is_live_across_blocks() {
+    let dump = dump_ir2(
+        "liveness",
+        &["-O1", "-dump-liveness"],
+        "int f(int x) { return x ? x + 1 : 2; }",
+    );
+    let blocks: Vec<&str> = dump
+        .lines()
+        .skip_while(|l| !l.starts_with("-dump-liveness"))
+        .filter(|l| l.starts_with(".L"))
+        .collect();
+    // both arms pass the result to the last block, which reads it
+    let join = blocks.last().unwrap();
+    let reg = join
+        .split("in [")
+        .nth(1)
+        .unwrap()
+        .split(']')
+        .next()
+        .unwrap();
+    assert!(reg.starts_with('r'), "{}", dump);
+    let out = format!("out [{}]", reg);
+    assert_eq!(
+        blocks.iter().filter(|l| l.ends_with(&out)).count(),
+        2,
+        "{}",
+        dump
+    );
+}
+
+// The IR of function `name` without the source lines, which move when
+// code is added above it.
+fn function_ir(ir: &str, name: &str) -> String {
+    let header = format!("{}():", name);
+    ir.lines()
+        .skip_while(|l| *l != header)
+        .skip(1)
+        .take_while(|l| !l.ends_with("():"))
+        .map(|l| l.split(" ; line").next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn numbering_is_per_function() {
+    let f = "int f(int x) { int s = 0; while (x) { s = s + x; x--; } return s ? s : -1; }\n";
+    let alone = dump_ir1("numbering", f);
+    let after = dump_ir1(
+        "numbering_after",
+        &format!(
+            "int g(int y) {{ if (y < 3) return y * 2; for (;;) y--; }}\n{}",
+            f
+        ),
+    );
+    let ir = function_ir(&alone, "f");
+    assert!(ir.contains(".L"), "{}", alone);
+    assert_eq!(ir, function_ir(&after, "f"));
+}
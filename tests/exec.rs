@@ -0,0 +1,238 @@
+// End-to-end tests: each snippet is compiled by mir9cc, assembled and
+// linked by `cc`, and run. The exit status of the binary is checked.
+// The IR verifier is enabled for every snippet.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const CASES: &[(&str, i32)] = &[
+    ("int main() { return 0; }", 0),
+    ("int main() { return 42; }", 42),
+    ("int main() { return 5 + 20 - 4; }", 21),
+    ("int main() { return (3 + 5) * 2 / 4; }", 4),
+    ("int main() { return 17 % 5; }", 2),
+    ("int main() { return -3 + 10; }", 7),
+    ("int main() { return (1 << 4) | 3; }", 19),
+    ("int main() { int a = 3; int b = 4; return a * a + b * b; }", 25),
+    ("int main() { if (2 < 1) return 1; else return 2; }", 2),
+    ("int main() { int i = 0; int s = 0; while (i < 10) { s = s + i; i++; } return s; }", 45),
+    ("int main() { int s = 0; for (int i = 0; i < 5; i++) s += i * i; return s; }", 30),
+    ("int main() { int i = 0; do { i += 3; } while (i < 20); return i; }", 21),
+    ("int main() { int x = 2; switch (x) { case 1: return 10; case 2: return 20; } return 0; }", 20),
+    ("int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } int main() { return fib(10); }", 55),
+    ("int main() { int a[3]; a[0] = 1; a[1] = 2; a[2] = 3; int *p = a; return *(p + 2) + p[1]; }", 5),
+    ("int main() { int s = 0; for (int i = 0; i < 4; i++) { if (i == 1) continue; if (i == 3) break; s += i > 1 && s ? 5 : 1; } if (0) return 9; return s; }", 6),
+];
+
+// The left operands of a right-nested expression stay live until the
+// innermost one is computed, so 16 of them don't fit in the registers
+// and have to be spilled, along with the arguments of the call taking
+// it.
+fn many_live_temporaries() -> (String, i32) {
+    let mut expr = String::from("a * 15 + 1");
+    let mut expected = 15 + 1;
+    for i in (0..15).rev() {
+        let op = if i % 2 == 0 { '+' } else { '-' };
+        expr = format!("a * {} + 1 {} ({})", i, op, expr);
+        expected = if op == '+' {
+            i + 1 + expected
+        } else {
+            i + 1 - expected
+        };
+    }
+    let src = format!(
+        "int g(int a, int b, int c, int d, int e, int f) {{ return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6; }} \
+         int f(int a) {{ return g(a * 2, a * 3, a * 4, a * 5, a * 6, {}); }} \
+         int main() {{ return f(1); }}",
+        expr
+    );
+    expected = 2 + 3 * 2 + 4 * 3 + 5 * 4 + 6 * 5 + expected * 6;
+    (src, expected & 0xff)
+}
+
+fn run(id: usize, src: &str) -> Result<i32, String> {
+    let dir = common::tmp_dir("exec");
+    let path = |ext: &str| -> PathBuf { dir.join(format!("{}.{}", id, ext)) };
+    fs::write(path("c"), src).map_err(|e| e.to_string())?;
+
+    let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+        .arg("--verify-ir")
+        .arg(path("c"))
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !out.status.success() {
+        return Err(format!(
+            "mir9cc failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    fs::write(path("s"), &out.stdout).map_err(|e| e.to_string())?;
+
+    let out = Command::new("cc")
+        .arg("-static")
+        .arg("-o")
+        .arg(path("out"))
+        .arg(path("s"))
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !out.status.success() {
+        return Err(format!(
+            "cc failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    let status = Command::new(path("out"))
+        .status()
+        .map_err(|e| e.to_string())?;
+    status.code().ok_or(String::from("killed by a signal"))
+}
+
+#[test]
+fn exit_status() {
+    let mut failures = vec![];
+    let cases = CASES
+        .iter()
+        .map(|(src, expected)| (src.to_string(), *expected))
+        .chain(std::iter::once(many_live_temporaries()));
+    for (id, (src, expected)) in cases.enumerate() {
+        match run(id, &src) {
+            Ok(code) if code == expected => {}
+            Ok(code) => failures.push(format!(
+                "{}\n  {} expected, but got {}",
+                src, expected, code
+            )),
+            Err(e) => failures.push(format!("{}\n  {}", src, e)),
+        }
+    }
+    let _ = fs::remove_dir_all(common::tmp_dir("exec"));
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn run_flag() {
+    let dir = common::tmp_dir("run-test");
+    fs::write(dir.join("ext.c"), "int ext() { return 5; }").unwrap();
+    let status = Command::new("cc")
+        .arg("-c")
+        .arg("-o")
+        .arg(dir.join("ext.o"))
+        .arg(dir.join("ext.c"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let src = "int printf(); int ext(); int main() { printf(\"hi\\n\"); return ext(); }";
+    let out = common::run("run-test", &[], src, &[&dir.join("ext.o")]);
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hi\n");
+
+    // without ext.o the program doesn't link
+    let out = common::run("run-test", &[], src, &[]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("cc failed to link the program"),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Control characters in string literals must reach the binary as they
+// were written.
+#[test]
+fn escapes_in_global_strings() {
+    let out = common::run(
+        "esc-test",
+        &[],
+        "int printf(); char reset[] = \"\\033[0m\"; char *red = \"\\033[31m1\\t\";\n\
+         char e[] = \"\\351\";\n\
+         int main() { printf(\"%s%s%s\", red, reset, e); return sizeof(reset); }",
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(out.stdout, b"\x1b[31m1\t\x1b[0m\xe9");
+}
+
+// Bytes past 0x7f and escaped quotes and backslashes reach the binary as
+// they were written.
+#[test]
+fn high_bytes_and_quotes_in_strings() {
+    let out = common::run(
+        "bytes-test",
+        &[],
+        "int write(int fd, char *buf, int n);\n\
+         int main() { char *s = \"caf\\xe9 \\\"a\\\\b\\\"\\n\"; write(1, s, 11); write(1, \"\\351\", 1);\n\
+         return sizeof(\"\\xe9\"); }",
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(out.stdout, b"caf\xe9 \"a\\b\"\n\xe9");
+}
+
+// Position-independent code links as a PIE with the defaults of cc.
+#[test]
+fn pic_links_without_flags() {
+    let out = common::run(
+        "pic-test",
+        &["-fpic"],
+        "int printf(); int g = 3; char *msg = \"hello\";\n\
+         int main() { printf(\"%s, world\\n\", msg); return g; }",
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hello, world\n");
+}
+
+// A constant subexpression is folded at -O1 only, and the program
+// returns the same at every level.
+#[test]
+fn optimization_levels() {
+    let src = "int main() { int x = 4; return x + 2 * 3 * 5; }";
+    let o0 = common::assembly("olevel-test", "fold", &["-O0"], src);
+    let o1 = common::assembly("olevel-test", "fold", &["-O1"], src);
+    assert!(o0.contains("imul"), "{}", o0);
+    assert!(!o1.contains("imul") && o1.contains(", 30\n"), "{}", o1);
+    for level in ["-O0", "-O1", "-O2"] {
+        let out = common::run("olevel-test", &[level], src, &[]);
+        assert_eq!(out.status.code(), Some(34), "{}", level);
+    }
+}
+
+// The stack is 16-byte aligned at every call, whatever the frame: at the
+// entry of `aligned`, rsp is 8 past a multiple of 16 for the return
+// address. Checked at each level, as the passes change which registers
+// are saved.
+#[test]
+fn stack_aligned_at_calls() {
+    let dir = common::tmp_dir("align-test");
+    fs::write(
+        dir.join("aligned.s"),
+        ".intel_syntax noprefix\n.text\n.global aligned\naligned:\n\
+         \tmov rax, rsp\n\tand rax, 15\n\tcmp rax, 8\n\tsete al\n\tmovzx eax, al\n\tret\n\
+         .section .note.GNU-stack,\"\",@progbits\n",
+    )
+    .unwrap();
+    let src = "int aligned();\n\
+         int seven(int a, int b, int c, int d, int e, int f, int g) { return aligned() * g; }\n\
+         int eight(int a, int b, int c, int d, int e, int f, int g, int h) { return aligned() * h; }\n\
+         int live(int x) { int a = x + 1; int b = x + 2; int c = x + 3; return aligned() * (a + b + c - 3 * x - 5); }\n\
+         int odd_frame() { char buf[13]; buf[0] = 1; return aligned() * buf[0]; }\n\
+         int main() { return aligned() + seven(1, 2, 3, 4, 5, 6, 1) + eight(1, 2, 3, 4, 5, 6, 7, 1) \
+         + live(4) + odd_frame(); }";
+    for level in ["-O0", "-O1", "-O2"] {
+        let out = common::run("align-test", &[level], src, &[&dir.join("aligned.s")]);
+        // one for each call found aligned
+        assert_eq!(
+            out.status.code(),
+            Some(5),
+            "{}: {}",
+            level,
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
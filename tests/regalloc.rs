@@ -0,0 +1,123 @@
+// Compares the linear scan register allocator with the iterative one
+// it replaced: the test programs must behave the same with either, and
+// linear scan must spill to fewer stack slots.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const ALLOCATORS: [&str; 2] = ["-fregalloc=linear", "-fregalloc=iterative"];
+
+// Compiles `path` with the given allocator and returns the assembly and
+// the number of spill slots reported by -fstats.
+fn compile(path: &str, allocator: &str) -> (String, usize) {
+    let out = Command::new(env!("CARGO_BIN_EXE_mir9cc"))
+        .args(["--verify-ir", "-fstats", allocator, path])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+    assert!(out.status.success(), "{}", stderr);
+    let slots = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("regalloc: "))
+        .and_then(|line| line.strip_suffix(" spill slots"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    (String::from_utf8_lossy(&out.stdout).to_string(), slots)
+}
+
+// Assembles and links the program, runs it and returns its exit status
+// and output.
+fn run(dir: &Path, name: &str, asm: &str, objs: &[&str]) -> (Option<i32>, String) {
+    let asm_path = dir.join(format!("{}.s", name));
+    let bin_path = dir.join(name);
+    fs::write(&asm_path, asm).unwrap();
+    let out = Command::new("cc")
+        .arg("-static")
+        .arg("-o")
+        .arg(&bin_path)
+        .arg(&asm_path)
+        .args(objs)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = Command::new(&bin_path).output().unwrap();
+    (
+        out.status.code(),
+        String::from_utf8_lossy(&out.stdout).to_string(),
+    )
+}
+
+#[test]
+fn allocators_agree_on_test_corpus() {
+    let dir = common::tmp_dir("regalloc-corpus");
+    let helper = dir.join("gcc.o");
+    let out = Command::new("cc")
+        .arg("-c")
+        .arg("-o")
+        .arg(&helper)
+        .arg("test/gcc.c")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let helper = helper.to_str().unwrap();
+
+    for (path, objs) in [
+        ("test/test.c", vec![helper]),
+        ("test/token.c", vec![]),
+        ("test/exit.c", vec![]),
+    ] {
+        let results: Vec<_> = ALLOCATORS
+            .iter()
+            .enumerate()
+            .map(|(i, allocator)| {
+                let (asm, _) = compile(path, allocator);
+                let name = format!("{}-{}", path.replace('/', "-"), i);
+                run(&dir, &name, &asm, &objs)
+            })
+            .collect();
+        assert_eq!(results[0], results[1], "{}", path);
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn linear_scan_takes_fewer_spill_slots() {
+    // 16 values live at once, twice in a row: the slots spilled to by
+    // the first statement are free again in the second one
+    let mut expr = String::from("a * 15 + 1");
+    for i in (0..15).rev() {
+        expr = format!("a * {} + 1 - ({})", i, expr);
+    }
+    let src = format!(
+        "int f(int a) {{ int x = {0}; int y = {0}; return x + y; }} \
+         int main() {{ return f(1); }}",
+        expr
+    );
+    let dir = common::tmp_dir("regalloc-stress");
+    let path = dir.join("stress.c");
+    fs::write(&path, src).unwrap();
+    let path = path.to_str().unwrap();
+
+    let (linear_asm, linear) = compile(path, ALLOCATORS[0]);
+    let (iterative_asm, iterative) = compile(path, ALLOCATORS[1]);
+    assert!(linear > 0);
+    assert!(
+        linear < iterative,
+        "linear scan took {} spill slots, the iterative allocator {}",
+        linear,
+        iterative
+    );
+    assert_eq!(
+        run(&dir, "linear", &linear_asm, &[]),
+        run(&dir, "iterative", &iterative_asm, &[])
+    );
+    let _ = fs::remove_dir_all(dir);
+}